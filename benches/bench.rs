@@ -1,9 +1,12 @@
 use std::io::Cursor;
 
-use arcode::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+use arcode::{
+    rangecoder::RangeEncoder, ArithmeticDecoder, ArithmeticEncoder, ByteModel, EOFKind, Model,
+    Range, SourceModel, StaticModel,
+};
 use bitbit::{BitReader, BitWriter, MSB};
 use byte_unit::Byte;
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 
 mod sherlock;
 
@@ -63,6 +66,142 @@ fn decode(data: &[u8]) {
     }
 }
 
+fn encode_byte_model(data: &[u8]) {
+    let mut model = ByteModel::with_eof();
+
+    let compressed = Cursor::new(vec![]);
+    let mut compressed_writer = BitWriter::new(compressed);
+
+    let mut encoder = ArithmeticEncoder::new(48);
+
+    for &sym in data {
+        encoder
+            .encode(sym.into(), &model, &mut compressed_writer)
+            .unwrap();
+        model.update_symbol(sym.into());
+    }
+    encoder.encode(256, &model, &mut compressed_writer).unwrap();
+    encoder.finish_encode(&mut compressed_writer).unwrap();
+}
+
+fn encode_range_coder(data: &[u8]) {
+    let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+
+    let mut compressed = vec![];
+    let mut encoder = RangeEncoder::new();
+
+    for &sym in data {
+        encoder.encode(sym.into(), &model, &mut compressed).unwrap();
+        model.update_symbol(sym.into());
+    }
+    encoder
+        .encode(model.eof(), &model, &mut compressed)
+        .unwrap();
+    encoder.finish_encode(&mut compressed).unwrap();
+}
+
+/// A static model of `data` whose total is a power of two, so coding it
+/// scales intervals without dividing.
+fn normalized_model(data: &[u8]) -> Model {
+    let mut counts = vec![0; 256];
+    data.iter().for_each(|&byte| counts[byte as usize] += 1);
+    let mut model = Model::builder().counts(counts).build();
+    model.normalize(16).unwrap();
+    model
+}
+
+fn encode_normalized<M: SourceModel>(model: &M, data: &[u8]) {
+    let compressed = Cursor::new(vec![]);
+    let mut compressed_writer = BitWriter::new(compressed);
+
+    let mut encoder = ArithmeticEncoder::new(48);
+
+    for &sym in data {
+        encoder
+            .encode(sym.into(), model, &mut compressed_writer)
+            .unwrap();
+    }
+    encoder.finish_encode(&mut compressed_writer).unwrap();
+}
+
+/// Symbols drawn from a `1 << bits` alphabet with a simple LCG so the
+/// benchmark does not need a random number crate.
+fn large_alphabet_symbols(bits: u32, len: usize) -> Vec<u32> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            // skew towards small symbols like token streams are
+            ((state >> 33) as u32 >> (state % 8) as u32) & ((1 << bits) - 1)
+        })
+        .collect()
+}
+
+fn encode_large(bits: u32, symbols: &[u32]) -> Vec<u8> {
+    let mut model = Model::builder()
+        .num_bits(bits)
+        .eof(EOFKind::EndAddOne)
+        .build();
+
+    let compressed = Cursor::new(vec![]);
+    let mut compressed_writer = BitWriter::new(compressed);
+
+    let mut encoder = ArithmeticEncoder::new(60);
+
+    for &sym in symbols {
+        encoder.encode(sym, &model, &mut compressed_writer).unwrap();
+        model.update_symbol(sym);
+    }
+    encoder
+        .encode(model.eof(), &model, &mut compressed_writer)
+        .unwrap();
+    encoder.finish_encode(&mut compressed_writer).unwrap();
+    compressed_writer.pad_to_byte().unwrap();
+
+    compressed_writer.get_ref().get_ref().clone()
+}
+
+fn decode_large(bits: u32, data: &[u8]) {
+    let mut model = Model::builder()
+        .num_bits(bits)
+        .eof(EOFKind::EndAddOne)
+        .build();
+
+    let mut input_reader = BitReader::<_, MSB>::new(data);
+    let mut decoder = ArithmeticDecoder::new(60);
+
+    while !decoder.finished() {
+        let sym = decoder.decode(&model, &mut input_reader).unwrap();
+        model.update_symbol(sym);
+    }
+}
+
+pub fn bench_large_alphabet(c: &mut Criterion) {
+    for &bits in &[16, 20, 24] {
+        let symbols = large_alphabet_symbols(bits, 20_000);
+        let compressed = encode_large(bits, &symbols);
+        let label = format!("2^{}", bits);
+
+        c.bench_with_input(
+            BenchmarkId::new("encode_large_alphabet", &label),
+            &symbols,
+            |b, data| {
+                b.iter(|| encode_large(bits, data));
+            },
+        );
+
+        c.bench_with_input(
+            BenchmarkId::new("decode_large_alphabet", &label),
+            &compressed,
+            |b, data| {
+                b.iter(|| decode_large(bits, data));
+            },
+        );
+    }
+}
+
 pub fn bench_encode(c: &mut Criterion) {
     let scale = 300;
     let sherlock_bytes = sherlock::SHERLOCK.bytes().into_iter().collect::<Vec<u8>>();
@@ -75,7 +214,7 @@ pub fn bench_encode(c: &mut Criterion) {
     let label_decode = byte.get_appropriate_unit(false).to_string();
 
     c.bench_with_input(
-        BenchmarkId::new("encode_single", label_encode),
+        BenchmarkId::new("encode_single", &label_encode),
         &sherlock_bytes,
         |b, data| {
             b.iter(|| encode(data));
@@ -89,7 +228,73 @@ pub fn bench_encode(c: &mut Criterion) {
             b.iter(|| decode(data));
         },
     );
+
+    c.bench_with_input(
+        BenchmarkId::new("encode_byte_model", &label_encode),
+        &sherlock_bytes,
+        |b, data| {
+            b.iter(|| encode_byte_model(data));
+        },
+    );
+
+    c.bench_with_input(
+        BenchmarkId::new("encode_range_coder", &label_encode),
+        &sherlock_bytes,
+        |b, data| {
+            b.iter(|| encode_range_coder(data));
+        },
+    );
+
+    let model = normalized_model(&sherlock_bytes);
+    c.bench_with_input(
+        BenchmarkId::new("encode_normalized", &label_encode),
+        &sherlock_bytes,
+        |b, data| {
+            b.iter(|| encode_normalized(&model, data));
+        },
+    );
+
+    let model = StaticModel::from(&model);
+    c.bench_with_input(
+        BenchmarkId::new("encode_static", label_encode),
+        &sherlock_bytes,
+        |b, data| {
+            b.iter(|| encode_normalized(&model, data));
+        },
+    );
+}
+
+/// Scales every count of `total` to `range`, summing the bounds so none of
+/// the work is optimized away.
+fn scale_counts(range: &Range, total: u64) -> u64 {
+    (0..1000)
+        .map(|i| {
+            let low = i * (total / 1000);
+            let (low, high) = range.interval_counts(low, low + 1, total);
+            low ^ high
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+pub fn bench_interval_counts(c: &mut Criterion) {
+    // `width() * total` fits in a u64 for the first and needs 128 bits for
+    // the second
+    for &(precision, total) in &[(32, 100_003), (62, 100_003)] {
+        let range = Range::new(precision);
+        c.bench_with_input(
+            BenchmarkId::new("interval_counts", format!("precision {}", precision)),
+            &total,
+            |b, &total| {
+                b.iter(|| scale_counts(&range, black_box(total)));
+            },
+        );
+    }
 }
 
-criterion_group!(benches, bench_encode);
+criterion_group!(
+    benches,
+    bench_encode,
+    bench_large_alphabet,
+    bench_interval_counts
+);
 criterion_main!(benches);
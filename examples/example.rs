@@ -10,7 +10,9 @@ use std::{
     time::Instant,
 };
 
-use arcode::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+use arcode::{
+    precision::Rescale, recommended_precision, ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model,
+};
 use bitbit::{BitReader, BitWriter, MSB};
 
 mod doc_examples;
@@ -23,12 +25,12 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 fn comp_decomp() -> Result<(), Box<dyn Error>> {
     let num_symbols = 257;
-    let precision = 42;
     let input_path = "./examples/sherlock.txt";
     let compressed_path = "./examples/compressed.dat";
     let uncompressed_path = "./examples/decompressed.txt";
 
     let num_bytes = fs::metadata(input_path).unwrap().len();
+    let precision = recommended_precision(num_symbols as u32, num_bytes, Rescale::Never);
     let mut byte_break = (num_bytes as f64 / 8f64) as u64;
     if byte_break < 2 {
         byte_break = 1;
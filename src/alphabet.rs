@@ -0,0 +1,167 @@
+//! Mapping Rust types to symbol alphabets.
+//!
+//! Hand written `as u32` conversions on the encoder side and `match`es on
+//! the decoder side tend to drift apart when a variant is added. Types that
+//! implement [`Alphabet`] define the mapping once, and
+//! [`symbol_alphabet!`](crate::symbol_alphabet) implements it for fieldless
+//! enums:
+//!
+//! ```rust
+//! use arcode::{alphabet::Alphabet, symbol_alphabet};
+//!
+//! symbol_alphabet! {
+//!     #[derive(Debug, PartialEq)]
+//!     pub enum Op {
+//!         Push,
+//!         Pop,
+//!         Add,
+//!     }
+//! }
+//!
+//! assert_eq!(Op::NUM_SYMBOLS, 3);
+//! assert_eq!(Op::Pop.to_symbol(), 1);
+//! assert_eq!(Op::from_symbol(2), Some(Op::Add));
+//! assert_eq!(Op::model().num_symbols(), 3);
+//! ```
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// A type whose values map one to one onto the symbols
+/// `0..NUM_SYMBOLS`.
+pub trait Alphabet: Copy {
+    const NUM_SYMBOLS: u32;
+
+    fn to_symbol(self) -> u32;
+
+    /// `None` if `symbol` does not map to a value.
+    fn from_symbol(symbol: u32) -> Option<Self>;
+
+    /// A uniform model over the alphabet.
+    fn model() -> Model {
+        Model::builder().num_symbols(Self::NUM_SYMBOLS).build()
+    }
+
+    /// Encodes `self` with `model`, which is not updated.
+    fn encode<W: Write>(
+        self,
+        encoder: &mut ArithmeticEncoder,
+        model: &Model,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        encoder.encode(self.to_symbol(), model, output)
+    }
+
+    /// Decodes a value with `model`, which is not updated. Symbols that do
+    /// not map to a value, such as an EOF added to the model, are an
+    /// [`InvalidData`](ErrorKind::InvalidData) error.
+    fn decode<R: Read, B: Bit>(
+        decoder: &mut ArithmeticDecoder,
+        model: &Model,
+        input: &mut BitReader<R, B>,
+    ) -> Result<Self, Error> {
+        let symbol = decoder.decode(model, input)?;
+        Self::from_symbol(symbol).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("symbol {} is not part of the alphabet", symbol),
+            )
+        })
+    }
+}
+
+/// Declares a fieldless enum and implements [`Alphabet`] for it, numbering
+/// the variants from 0 in declaration order. Explicit discriminants are not
+/// allowed so the symbols stay contiguous. `Clone` and `Copy` are derived.
+#[macro_export]
+macro_rules! symbol_alphabet {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy)]
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant),+
+        }
+
+        impl $crate::alphabet::Alphabet for $name {
+            const NUM_SYMBOLS: u32 = [$($name::$variant),+].len() as u32;
+
+            fn to_symbol(self) -> u32 {
+                self as u32
+            }
+
+            fn from_symbol(symbol: u32) -> Option<Self> {
+                [$($name::$variant),+].get(symbol as usize).copied()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::Alphabet;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    symbol_alphabet! {
+        #[derive(Debug, PartialEq)]
+        enum Suit {
+            Clubs,
+            Diamonds,
+            /// documented variant
+            Hearts,
+            Spades,
+        }
+    }
+
+    #[test]
+    fn mapping() {
+        assert_eq!(Suit::NUM_SYMBOLS, 4);
+        for symbol in 0..4 {
+            assert_eq!(Suit::from_symbol(symbol).unwrap().to_symbol(), symbol);
+        }
+        assert_eq!(Suit::from_symbol(4), None);
+    }
+
+    #[test]
+    fn e2e() {
+        let hand = [Suit::Spades, Suit::Hearts, Suit::Hearts, Suit::Clubs];
+        let model = Model::builder()
+            .num_symbols(Suit::NUM_SYMBOLS)
+            .eof(EOFKind::EndAddOne)
+            .build();
+
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &suit in &hand {
+            suit.encode(&mut encoder, &model, &mut out_writer).unwrap();
+        }
+        encoder
+            .encode(model.eof(), &model, &mut out_writer)
+            .unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut decoder = ArithmeticDecoder::new(32);
+        let input = Cursor::new(out_writer.get_ref().get_ref().clone());
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+        for suit in &hand {
+            assert_eq!(
+                &Suit::decode(&mut decoder, &model, &mut in_reader).unwrap(),
+                suit
+            );
+        }
+        // the EOF is not a suit
+        assert!(Suit::decode(&mut decoder, &model, &mut in_reader).is_err());
+    }
+}
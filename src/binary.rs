@@ -4,8 +4,133 @@ use bitbit::{reader::Bit, BitReader, BitWriter};
 
 use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
 
-pub struct BinaryCoder {
-    models: Vec<Model>,
+/// Number of fractional bits of the probabilities passed to
+/// [`ArithmeticEncoder::encode_bit`] and [`ArithmeticDecoder::decode_bit`].
+pub const PROBABILITY_BITS: u32 = 16;
+
+/// A probability of 1 in units of `1 / 2^PROBABILITY_BITS`.
+pub const PROBABILITY_ONE: u32 = 1 << PROBABILITY_BITS;
+
+/// An adaptive binary model that is a single probability of a 0, moved a
+/// `1 / 2^rate` of the way towards each coded bit with shifts, like the
+/// state of LPAQ or CABAC. Coded with
+/// [`ArithmeticEncoder::encode_bit`], it takes two bytes instead of a
+/// [`Model`] with its Fenwick tree, and adapts faster to changing
+/// statistics at the cost of never settling exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShiftModel {
+    probability_zero: u16,
+    rate: u8,
+}
+
+impl ShiftModel {
+    /// A probability of one half that moves by `1 / 2^rate` per update.
+    /// A `rate` around 4 adapts quickly, around 7 estimates closely.
+    ///
+    /// # Panics
+    /// If `rate` is not between 1 and `PROBABILITY_BITS - 1`.
+    pub fn new(rate: u32) -> Self {
+        assert!(
+            (1..PROBABILITY_BITS).contains(&rate),
+            "rate must be between 1 and PROBABILITY_BITS - 1"
+        );
+        Self {
+            probability_zero: (PROBABILITY_ONE / 2) as u16,
+            rate: rate as u8,
+        }
+    }
+
+    /// The probability of a 0 in units of `1 / PROBABILITY_ONE`, never 0
+    /// or [`PROBABILITY_ONE`] since a shift stops short of both.
+    pub fn probability_zero(&self) -> u32 {
+        self.probability_zero.into()
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate.into()
+    }
+
+    pub fn update(&mut self, bit: bool) {
+        let probability_zero = self.probability_zero();
+        let probability_zero = if bit {
+            probability_zero - (probability_zero >> self.rate)
+        } else {
+            probability_zero + ((PROBABILITY_ONE - probability_zero) >> self.rate)
+        };
+        self.probability_zero = probability_zero as u16;
+    }
+}
+
+/// A model of one bit of a [`BinaryCoder`].
+pub trait BitModel {
+    fn encode_bit<W: Write>(
+        &self,
+        bit: bool,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error>;
+
+    fn decode_bit<R: Read, B: Bit>(
+        &self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<bool, Error>;
+
+    fn update_bit(&mut self, bit: bool);
+}
+
+/// A binary [`Model`] with symbols 0 and 1.
+impl BitModel for Model {
+    fn encode_bit<W: Write>(
+        &self,
+        bit: bool,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        encoder.encode(bit.into(), self, output)
+    }
+
+    fn decode_bit<R: Read, B: Bit>(
+        &self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<bool, Error> {
+        Ok(decoder.decode(self, input)? == 1)
+    }
+
+    fn update_bit(&mut self, bit: bool) {
+        self.update_symbol(bit.into());
+    }
+}
+
+impl BitModel for ShiftModel {
+    fn encode_bit<W: Write>(
+        &self,
+        bit: bool,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        encoder.encode_bit(bit, self.probability_zero(), output)
+    }
+
+    fn decode_bit<R: Read, B: Bit>(
+        &self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<bool, Error> {
+        decoder.decode_bit(self.probability_zero(), input)
+    }
+
+    fn update_bit(&mut self, bit: bool) {
+        self.update(bit);
+    }
+}
+
+/// Codes values of a fixed bit width most significant bit first, with one
+/// adaptive [`BitModel`] per bit position: a binary [`Model`] by default,
+/// or a [`ShiftModel`] from [`with_shift`](BinaryCoder::with_shift).
+pub struct BinaryCoder<M = Model> {
+    models: Vec<M>,
 }
 
 impl BinaryCoder {
@@ -21,8 +146,19 @@ impl BinaryCoder {
         }
         Self { models }
     }
+}
 
-    pub fn from_values(models: Vec<Model>) -> Self {
+impl BinaryCoder<ShiftModel> {
+    /// One [`ShiftModel`] of `rate` per bit, much cheaper to update than
+    /// the binary [`Model`]s of [`new`](BinaryCoder::new). Needs a
+    /// precision of at least `PROBABILITY_BITS + 2`.
+    pub fn with_shift(bit_width: u32, rate: u32) -> Self {
+        Self::from_values(vec![ShiftModel::new(rate); bit_width as usize])
+    }
+}
+
+impl<M: BitModel> BinaryCoder<M> {
+    pub fn from_values(models: Vec<M>) -> Self {
         Self { models }
     }
 
@@ -33,9 +169,9 @@ impl BinaryCoder {
         value: u32,
     ) -> Result<(), Error> {
         for i in 0..self.models.len() {
-            let symbol = (value >> (self.models.len() - i - 1) as u32) & 0x1;
-            encoder.encode(symbol, &self.models[i], output)?;
-            self.models[i].update_symbol(symbol);
+            let bit = (value >> (self.models.len() - i - 1) as u32) & 0x1 == 1;
+            self.models[i].encode_bit(bit, encoder, output)?;
+            self.models[i].update_bit(bit);
         }
         Ok(())
     }
@@ -47,14 +183,63 @@ impl BinaryCoder {
     ) -> Result<u32, Error> {
         let mut value: u32 = 0;
         for model in &mut self.models {
-            let sym = decoder.decode(model, input)?;
-            model.update_symbol(sym);
-            value = value * 2 + sym;
+            let bit = model.decode_bit(decoder, input)?;
+            model.update_bit(bit);
+            value = value * 2 + u32::from(bit);
         }
         Ok(value)
     }
 
-    pub fn models(&self) -> &[Model] {
+    pub fn models(&self) -> &[M] {
         &self.models
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{BinaryCoder, ShiftModel, PROBABILITY_ONE};
+    use crate::{ArithmeticDecoder, ArithmeticEncoder};
+
+    #[test]
+    fn shift_model() {
+        let mut model = ShiftModel::new(4);
+        assert_eq!(model.probability_zero(), PROBABILITY_ONE / 2);
+        model.update(false);
+        assert_eq!(model.probability_zero(), PROBABILITY_ONE / 2 + (1 << 11));
+        for _ in 0..1000 {
+            model.update(true);
+        }
+        assert_eq!(model.probability_zero(), 15);
+        for _ in 0..1000 {
+            model.update(false);
+        }
+        assert_eq!(model.probability_zero(), PROBABILITY_ONE - 15);
+    }
+
+    #[test]
+    fn shift_coder() {
+        let values: Vec<u32> = (0..3000u32).map(|i| (i % 40) * (i % 3)).collect();
+        let mut coder = BinaryCoder::with_shift(7, 5);
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for &value in &values {
+            coder.encode(&mut encoder, &mut output, value).unwrap();
+        }
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+        let compressed = output.get_ref().get_ref();
+        assert!(compressed.len() < values.len() * 7 / 8);
+
+        let mut coder = BinaryCoder::with_shift(7, 5);
+        let mut decoder = ArithmeticDecoder::new(32);
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &value in &values {
+            assert_eq!(coder.decode(&mut decoder, &mut input).unwrap(), value);
+        }
+        assert_eq!(coder.models().len(), 7);
+    }
+}
@@ -0,0 +1,220 @@
+//! An encoder that resolves the middle half scalings of
+//! [`ArithmeticEncoder`](crate::ArithmeticEncoder) with carries into its own
+//! output buffer instead of counting pending bits.
+//!
+//! Scaling the middle half emits half a bit: a 0 here and a 1 added to the
+//! next bit, which carries back into the buffered bits when that bit is a 1
+//! too. The output is exactly what `ArithmeticEncoder` writes (padded to a
+//! byte), so [`ArithmeticDecoder`](crate::ArithmeticDecoder) decodes it, but
+//! it goes to a plain [`Write`] in whole bytes as soon as no carry can reach
+//! them anymore.
+
+use std::io::{Error, Write};
+
+use crate::{encode::check_headroom, Model, Range, SourceModel};
+
+pub struct CarryEncoder {
+    range: Range,
+    /// Whole bytes a carry can still reach.
+    buffer: Vec<u8>,
+    /// The last `bits` bits written, not yet a whole byte.
+    byte: u8,
+    bits: u32,
+    /// The next bit gets a 1 added, from the last middle half scaling.
+    carry: bool,
+}
+
+impl CarryEncoder {
+    /// See [`ArithmeticEncoder::new`](crate::ArithmeticEncoder::new).
+    pub fn new(precision: u64) -> Self {
+        Self {
+            range: Range::new(precision),
+            buffer: Vec::new(),
+            byte: 0,
+            bits: 0,
+            carry: false,
+        }
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        symbol: u32,
+        source_model: &Model,
+        output: &mut W,
+    ) -> Result<(), Error> {
+        check_headroom(&self.range, source_model)?;
+        source_model.check_codable(symbol)?;
+        let low_high = self.range.calculate_range(symbol, source_model);
+        self.range.update_range(low_high);
+
+        while self.range.in_bottom_half() || self.range.in_upper_half() {
+            if self.range.in_bottom_half() {
+                self.range.scale_bottom_half();
+                self.push(false, false);
+            } else {
+                self.range.scale_upper_half();
+                self.push(true, false);
+            }
+        }
+
+        while self.range.in_middle_half() {
+            self.range.scale_middle_half();
+            self.push(false, true);
+        }
+
+        self.flush_settled(output)
+    }
+
+    /// Writes the final bits and the rest of the buffer, padded to a byte.
+    pub fn finish_encode<W: Write>(&mut self, output: &mut W) -> Result<(), Error> {
+        // half a bit and then the bit that picks the quarter, as
+        // `ArithmeticEncoder::finish_encode` does with a pending bit
+        self.push(false, true);
+        self.push(!self.range.in_bottom_quarter(), false);
+
+        output.write_all(&self.buffer)?;
+        self.buffer.clear();
+        if self.bits > 0 {
+            output.write_all(&[self.byte << (8 - self.bits)])?;
+        }
+        self.byte = 0;
+        self.bits = 0;
+        Ok(())
+    }
+
+    /// Appends `bit`, adds the carry of a previous middle half scaling to
+    /// it and remembers whether this one was a middle half scaling too.
+    fn push(&mut self, bit: bool, middle: bool) {
+        self.byte = (self.byte << 1) | u8::from(bit);
+        self.bits += 1;
+        if std::mem::replace(&mut self.carry, middle) {
+            self.add_one();
+        }
+        if self.bits == 8 {
+            self.buffer.push(self.byte);
+            self.byte = 0;
+            self.bits = 0;
+        }
+    }
+
+    /// Adds 1 at the last bit written.
+    fn add_one(&mut self) {
+        let sum = u16::from(self.byte) + 1;
+        if sum < 1 << self.bits {
+            self.byte = sum as u8;
+            return;
+        }
+        self.byte = 0;
+        for byte in self.buffer.iter_mut().rev() {
+            if *byte == u8::MAX {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                return;
+            }
+        }
+        unreachable!("a carry never reaches flushed bytes");
+    }
+
+    /// Writes the buffered bytes no carry can reach: everything before the
+    /// last 0 bit, as a carry stops there.
+    fn flush_settled<W: Write>(&mut self, output: &mut W) -> Result<(), Error> {
+        let settled = if u16::from(self.byte) + 1 < 1 << self.bits {
+            self.buffer.len()
+        } else {
+            match self.buffer.iter().rposition(|&byte| byte != u8::MAX) {
+                Some(index) => index,
+                None => return Ok(()),
+            }
+        };
+        output.write_all(&self.buffer[..settled])?;
+        self.buffer.drain(..settled);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::CarryEncoder;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    fn encode_both(symbols: &[u32], num_symbols: u32, precision: u64) -> (Vec<u8>, Vec<u8>) {
+        let build = || {
+            Model::builder()
+                .num_symbols(num_symbols)
+                .eof(EOFKind::EndAddOne)
+                .build()
+        };
+
+        let mut model = build();
+        let mut encoder = ArithmeticEncoder::new(precision);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in symbols.iter().chain(&[num_symbols]) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut model = build();
+        let mut encoder = CarryEncoder::new(precision);
+        let mut output = vec![];
+        for &symbol in symbols.iter().chain(&[num_symbols]) {
+            encoder.encode(symbol, &model, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut output).unwrap();
+
+        (out_writer.get_ref().get_ref().clone(), output)
+    }
+
+    #[test]
+    fn matches_arithmetic_encoder() {
+        let sherlock: Vec<u32> = include_str!("../tests/sherlock.rs")
+            .bytes()
+            .map(u32::from)
+            .collect();
+        let (expected, output) = encode_both(&sherlock, 256, 48);
+        assert_eq!(output, expected);
+
+        // small precisions scale the middle half much more often
+        let symbols: Vec<u32> = (0..5000).map(|i| (i * 7 + i / 3) % 5).collect();
+        for &precision in &[16, 20, 32] {
+            let (expected, output) = encode_both(&symbols, 5, precision);
+            assert_eq!(output, expected);
+
+            let mut model = Model::builder()
+                .num_symbols(5)
+                .eof(EOFKind::EndAddOne)
+                .build();
+            let mut decoder = ArithmeticDecoder::new(precision);
+            let mut input: BitReader<_, MSB> = BitReader::new(output.as_slice());
+            let mut decoded = vec![];
+            while !decoder.finished() {
+                let symbol = decoder.decode(&model, &mut input).unwrap();
+                model.update_symbol(symbol);
+                decoded.push(symbol);
+            }
+            decoded.pop();
+            assert_eq!(decoded, symbols);
+        }
+    }
+
+    #[test]
+    fn streams_settled_bytes() {
+        let model = Model::builder().num_symbols(256).build();
+        let mut encoder = CarryEncoder::new(32);
+        let mut output = vec![];
+        for symbol in 0..1000 {
+            encoder.encode(symbol % 256, &model, &mut output).unwrap();
+        }
+        // each symbol takes a byte, all but the last few are written
+        assert!(output.len() > 990);
+        encoder.finish_encode(&mut output).unwrap();
+        assert!(output.len() >= 1000);
+    }
+}
@@ -0,0 +1,227 @@
+//! Context modeling with the bookkeeping done once for both directions.
+
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind, Read, Write},
+};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{evaluate::Candidate, ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Index of a model in a [`ContextSwitchingCoder`]'s pool.
+pub type ContextId = usize;
+
+/// The most recently coded symbols, as seen by a context selector.
+#[derive(Clone, Debug)]
+pub struct History {
+    symbols: VecDeque<u32>,
+    capacity: usize,
+    position: u64,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            symbols: VecDeque::with_capacity(capacity),
+            capacity,
+            position: 0,
+        }
+    }
+
+    /// The symbol coded `back + 1` symbols ago, so `get(0)` is the last one.
+    pub fn get(&self, back: usize) -> Option<u32> {
+        self.symbols
+            .len()
+            .checked_sub(back + 1)
+            .map(|index| self.symbols[index])
+    }
+
+    /// Number of symbols remembered, at most the capacity.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Number of symbols coded so far.
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn push(&mut self, symbol: u32) {
+        if self.capacity == 0 {
+            self.position += 1;
+            return;
+        }
+        if self.symbols.len() == self.capacity {
+            self.symbols.pop_front();
+        }
+        self.symbols.push_back(symbol);
+        self.position += 1;
+    }
+}
+
+/// Codes every symbol with the model `selector` picks from the pool based
+/// on the symbols before it, then updates that model and the history.
+///
+/// Because the same selection and update steps run for
+/// [`encode`](Self::encode) and [`decode`](Self::decode), the decoder can
+/// not drift out of phase with the encoder as long as both are created with
+/// the same models, history length and (deterministic) selector.
+pub struct ContextSwitchingCoder<F: Fn(&History) -> ContextId> {
+    models: Vec<Model>,
+    selector: F,
+    history: History,
+}
+
+impl<F: Fn(&History) -> ContextId> ContextSwitchingCoder<F> {
+    /// `history_len` is how many previous symbols the selector can see.
+    pub fn new(models: Vec<Model>, history_len: usize, selector: F) -> Self {
+        Self {
+            models,
+            selector,
+            history: History::new(history_len),
+        }
+    }
+
+    fn select(&self) -> Result<ContextId, Error> {
+        let context = (self.selector)(&self.history);
+        if context < self.models.len() {
+            Ok(context)
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "selector chose context {} but there are only {} models",
+                    context,
+                    self.models.len()
+                ),
+            ))
+        }
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        symbol: u32,
+    ) -> Result<(), Error> {
+        let context = self.select()?;
+        encoder.encode(symbol, &self.models[context], output)?;
+        self.update(context, symbol);
+        Ok(())
+    }
+
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        let context = self.select()?;
+        let symbol = decoder.decode(&self.models[context], input)?;
+        self.update(context, symbol);
+        Ok(symbol)
+    }
+
+    fn update(&mut self, context: ContextId, symbol: u32) {
+        self.models[context].update_symbol(symbol);
+        self.history.push(symbol);
+    }
+
+    /// The model the next symbol will be coded with. Useful for checking
+    /// for the EOF symbol.
+    pub fn next_model(&self) -> Result<&Model, Error> {
+        Ok(&self.models[self.select()?])
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+}
+
+/// Scores the selected model, then updates it and the history.
+impl<F: Fn(&History) -> ContextId> Candidate for ContextSwitchingCoder<F> {
+    fn observe(&mut self, symbol: u32) -> Result<u32, Error> {
+        let context = self.select()?;
+        let cost = self.models[context].observe(symbol)?;
+        self.history.push(symbol);
+        Ok(cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{ContextSwitchingCoder, History};
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    #[test]
+    fn history() {
+        let mut history = History::new(2);
+        assert_eq!(history.get(0), None);
+        history.push(1);
+        history.push(2);
+        history.push(3);
+        assert_eq!(history.get(0), Some(3));
+        assert_eq!(history.get(1), Some(2));
+        assert_eq!(history.get(2), None);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.position(), 3);
+    }
+
+    fn new_coder() -> ContextSwitchingCoder<impl Fn(&History) -> usize> {
+        let models = vec![Model::builder().num_symbols(4).eof(EOFKind::End).build(); 4];
+        ContextSwitchingCoder::new(models, 1, |history: &History| {
+            history.get(0).unwrap_or(0) as usize
+        })
+    }
+
+    #[test]
+    fn e2e() {
+        let data = [0, 1, 2, 0, 1, 2, 0, 1, 2, 2, 1, 0];
+
+        let mut coder = new_coder();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &data {
+            coder.encode(&mut encoder, &mut out_writer, symbol).unwrap();
+        }
+        let eof = coder.next_model().unwrap().eof();
+        coder.encode(&mut encoder, &mut out_writer, eof).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        assert_eq!(coder.models()[0].counts(), &[2, 4, 1, 2]);
+
+        let mut coder = new_coder();
+        let mut decoder = ArithmeticDecoder::new(32);
+        let input = Cursor::new(out_writer.get_ref().get_ref().clone());
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = coder.decode(&mut decoder, &mut in_reader).unwrap();
+            if !decoder.finished() {
+                decoded.push(symbol);
+            }
+        }
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn invalid_context() {
+        let models = vec![Model::builder().num_symbols(4).build()];
+        let mut coder = ContextSwitchingCoder::new(models, 0, |_: &History| 1);
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        assert!(coder.encode(&mut encoder, &mut out_writer, 0).is_err());
+    }
+}
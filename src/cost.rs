@@ -0,0 +1,210 @@
+//! Fixed-point `-log2(p)` helpers used for bit-cost estimation.
+//!
+//! Costs are expressed in units of `1 / 2^COST_FRACTIONAL_BITS` of a bit and
+//! are computed with integer arithmetic only (a small table plus linear
+//! interpolation), so results are bit-identical on every platform.
+
+use std::collections::HashMap;
+
+/// Number of fractional bits in a fixed-point cost.
+pub const COST_FRACTIONAL_BITS: u32 = 16;
+
+/// The fixed-point representation of exactly one bit.
+pub const COST_ONE_BIT: u32 = 1 << COST_FRACTIONAL_BITS;
+
+const TABLE_BITS: u32 = 8;
+const TABLE_SIZE: usize = (1 << TABLE_BITS) + 1;
+
+/// `log2(1 + i / 2^TABLE_BITS)` in fixed point for every `i` in
+/// `[0, 2^TABLE_BITS]`. Built at compile time so no floating point is
+/// involved.
+static LOG2_TABLE: [u32; TABLE_SIZE] = build_table();
+
+const fn build_table() -> [u32; TABLE_SIZE] {
+    let mut table = [0u32; TABLE_SIZE];
+    let mut i = 0;
+    while i < TABLE_SIZE {
+        // mantissa in [1, 2] as Q31
+        let mantissa = (1u64 << 31) + ((i as u64) << (31 - TABLE_BITS));
+        let log = log2_mantissa(mantissa);
+        // round the Q24 result to the nearest step of the cost precision
+        table[i] = ((log + (1 << 7)) >> (24 - COST_FRACTIONAL_BITS)) as u32;
+        i += 1;
+    }
+    table
+}
+
+/// log2 of a Q31 mantissa in `[1, 2]` as Q24, computed by repeated squaring.
+const fn log2_mantissa(mut mantissa: u64) -> u64 {
+    let mut result = 0;
+    let mut bit = 1 << 23;
+    while bit > 0 {
+        mantissa = ((mantissa as u128 * mantissa as u128) >> 31) as u64;
+        if mantissa >= 1 << 32 {
+            mantissa >>= 1;
+            result |= bit;
+        }
+        bit >>= 1;
+    }
+    result
+}
+
+/// Fixed-point `log2(x)`. `log2(0)` is defined as 0.
+pub fn log2(x: u64) -> u32 {
+    if x == 0 {
+        return 0;
+    }
+    let exponent = 63 - x.leading_zeros();
+    // bits below the leading one, left aligned
+    let fraction = (x << x.leading_zeros()) << 1;
+    let index = (fraction >> (64 - TABLE_BITS)) as usize;
+    let remainder = (fraction >> (64 - TABLE_BITS - COST_FRACTIONAL_BITS)) & 0xFFFF;
+
+    let base = LOG2_TABLE[index];
+    let delta = u64::from(LOG2_TABLE[index + 1] - base);
+    let interpolated = base + ((delta * remainder) >> COST_FRACTIONAL_BITS) as u32;
+
+    (exponent << COST_FRACTIONAL_BITS) + interpolated
+}
+
+/// Fixed-point cost (`-log2(freq / total)`) of a symbol occupying `freq`
+/// out of `total`. A symbol with a `freq` of 0 can not be coded and costs
+/// `u32::MAX`.
+pub fn cost(freq: u64, total: u64) -> u32 {
+    if freq == 0 {
+        return u32::MAX;
+    }
+    log2(total).saturating_sub(log2(freq))
+}
+
+/// Converts a fixed-point cost (or a sum of costs) to bits.
+pub fn to_bits(cost: u64) -> f64 {
+    cost as f64 / f64::from(COST_ONE_BIT)
+}
+
+/// The exact fixed-point cost of one encoded symbol. See
+/// [`ArithmeticEncoder::enable_cost_log`](crate::ArithmeticEncoder::enable_cost_log).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SymbolCost {
+    pub symbol: u32,
+    /// The context set on the encoder when the symbol was encoded
+    pub context: usize,
+    pub cost: u32,
+}
+
+/// Accumulates the fixed-point cost spent in each coding context.
+///
+/// Contexts are ids chosen by the caller (usually the index of the model
+/// used to code a symbol). Only contexts that have been recorded in are
+/// stored, so ids need not be dense. See
+/// [`ArithmeticEncoder::enable_rate_tracking`](crate::ArithmeticEncoder::enable_rate_tracking).
+#[derive(Clone, Debug, Default)]
+pub struct RateTracker {
+    contexts: HashMap<usize, u64>,
+    current: usize,
+}
+
+impl RateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the context that subsequent costs are attributed to.
+    pub fn set_context(&mut self, context: usize) {
+        self.current = context;
+    }
+
+    pub const fn context(&self) -> usize {
+        self.current
+    }
+
+    /// Adds `cost` to the current context.
+    pub fn record(&mut self, cost: u32) {
+        *self.contexts.entry(self.current).or_insert(0) += u64::from(cost);
+    }
+
+    /// Fixed-point cost spent in `context`.
+    pub fn context_cost(&self, context: usize) -> u64 {
+        self.contexts.get(&context).copied().unwrap_or(0)
+    }
+
+    /// Bits spent in `context`.
+    pub fn context_bits(&self, context: usize) -> f64 {
+        to_bits(self.context_cost(context))
+    }
+
+    /// Fixed-point cost spent across every context.
+    pub fn total_cost(&self) -> u64 {
+        self.contexts.values().sum()
+    }
+
+    /// Bits spent across every context.
+    pub fn total_bits(&self) -> f64 {
+        to_bits(self.total_cost())
+    }
+
+    /// Fixed-point cost per context, for every context recorded in.
+    pub const fn costs(&self) -> &HashMap<usize, u64> {
+        &self.contexts
+    }
+
+    /// Clears all accumulated costs. The current context is kept.
+    pub fn reset(&mut self) {
+        self.contexts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cost, log2, to_bits, RateTracker, COST_ONE_BIT};
+
+    #[test]
+    fn powers_of_two_are_exact() {
+        for exponent in 0..64 {
+            assert_eq!(log2(1 << exponent), exponent * COST_ONE_BIT);
+        }
+    }
+
+    #[test]
+    fn close_to_float_log2() {
+        for &x in &[3u64, 5, 7, 10, 100, 255, 1_000, 12_345, 1 << 40 | 12_345] {
+            let expected = (x as f64).log2();
+            assert!((to_bits(log2(x).into()) - expected).abs() < 1e-4, "{}", x);
+        }
+    }
+
+    #[test]
+    fn symbol_cost() {
+        assert_eq!(cost(1, 2), COST_ONE_BIT);
+        assert_eq!(cost(1, 8), 3 * COST_ONE_BIT);
+        assert_eq!(cost(4, 4), 0);
+        assert_eq!(cost(0, 4), u32::MAX);
+        assert!((to_bits(cost(1, 3).into()) - 3f64.log2()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rate_tracker() {
+        let mut tracker = RateTracker::new();
+        tracker.record(COST_ONE_BIT);
+        tracker.set_context(3);
+        tracker.record(2 * COST_ONE_BIT);
+        tracker.record(COST_ONE_BIT);
+
+        assert_eq!(tracker.context_bits(0), 1.0);
+        assert_eq!(tracker.context_bits(1), 0.0);
+        assert_eq!(tracker.context_bits(3), 3.0);
+        assert_eq!(tracker.context_bits(7), 0.0);
+        assert_eq!(tracker.total_bits(), 4.0);
+
+        // sparse ids only store the contexts recorded in
+        tracker.set_context(1 << 40);
+        tracker.record(COST_ONE_BIT);
+        assert_eq!(tracker.context_bits(1 << 40), 1.0);
+        assert_eq!(tracker.costs().len(), 3);
+        tracker.set_context(3);
+
+        tracker.reset();
+        assert_eq!(tracker.total_cost(), 0);
+        assert_eq!(tracker.context(), 3);
+    }
+}
@@ -5,14 +5,164 @@ use std::io::{Error, ErrorKind, Read};
 
 use bitbit::{reader::Bit, BitReader};
 
-use crate::{Model, Range};
+use crate::{
+    encode::{
+        bit_interval, check_bit_probability, check_exclusions, check_headroom, check_interval,
+        check_large_headroom, renormalize,
+    },
+    error::{DecodeError, DecodeFailure, LimitError, PrecisionError},
+    lookup::LookupTable,
+    model::LargeModel,
+    precision::{check_compatible, recommended_precision, Rescale},
+    range::check_precision,
+    state::{self, CoderState},
+    Model, Range, SourceModel,
+};
 
+/// Where the decoder reads its input from one bit at a time.
+trait BitSource {
+    fn read_bit(&mut self) -> Result<bool, Error>;
+}
+
+impl<R: Read, B: Bit> BitSource for BitReader<R, B> {
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        BitReader::read_bit(self)
+    }
+}
+
+/// The bits of a byte slice, most significant first, loaded a word at a
+/// time instead of through [`Read`].
+struct SliceBits<'a> {
+    /// The bytes not yet loaded into `buffer`.
+    bytes: &'a [u8],
+    buffer: u64,
+    /// Number of low bits of `buffer` still to be read.
+    available: u32,
+}
+
+impl<'a> SliceBits<'a> {
+    const fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            buffer: 0,
+            available: 0,
+        }
+    }
+
+    /// Loads the next 8 bytes, or the last byte once fewer remain.
+    fn refill(&mut self) -> Result<(), Error> {
+        if self.bytes.len() >= 8 {
+            let (head, rest) = self.bytes.split_at(8);
+            let mut word = [0; 8];
+            word.copy_from_slice(head);
+            self.buffer = u64::from_be_bytes(word);
+            self.available = 64;
+            self.bytes = rest;
+        } else {
+            let (&byte, rest) = self
+                .bytes
+                .split_first()
+                .ok_or_else(|| Error::from(ErrorKind::UnexpectedEof))?;
+            self.buffer = byte.into();
+            self.available = 8;
+            self.bytes = rest;
+        }
+        Ok(())
+    }
+}
+
+impl BitSource for SliceBits<'_> {
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        if self.available == 0 {
+            self.refill()?;
+        }
+        self.available -= 1;
+        Ok((self.buffer >> self.available) & 1 == 1)
+    }
+}
+
+/// Number of registers in a decoder's [`CoderState`].
+pub(crate) const STATE_WORDS: usize = 15;
+/// Index of `bits_read` among them.
+pub(crate) const BITS_READ_WORD: usize = 7;
+
+/// Hard caps for [`ArithmeticDecoder::decode_with_limit`] and
+/// [`ArithmeticDecoder::set_limits`], counted from the start of the stream.
+/// The default has no limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_symbols: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_symbols: u64::MAX,
+            max_bytes: u64::MAX,
+        }
+    }
+}
+
+/// A symbol returned by [`ArithmeticDecoder::decode_with_info`] along with
+/// how it was coded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodeInfo {
+    pub symbol: u32,
+    /// The `(low, high)` probability interval of the symbol in the model it
+    /// was decoded with.
+    pub probability: (f64, f64),
+    /// Number of bits the encoder shifted out while renormalizing after the
+    /// symbol, counting pending bits. Over a stream these add up to the
+    /// coded length, minus the bits written by `finish_encode`.
+    pub bits: u64,
+    /// Width of the interval the symbol occupied in the decoder's range.
+    pub width: u64,
+    /// Width of the whole range when the symbol was decoded.
+    pub range_width: u64,
+    /// Whether decoding had restarted at a segment boundary (see
+    /// [`continue_with_next_segment`](ArithmeticDecoder::continue_with_next_segment))
+    /// before this symbol. Anything corrupted in an earlier segment can not
+    /// have affected it.
+    pub after_resync: bool,
+}
+
+impl DecodeInfo {
+    /// The share of the range the symbol occupied. Narrow intervals mean the
+    /// model did not expect the symbol, which after corruption is often the
+    /// first sign the decoder is producing garbage, so media decoders can use
+    /// this to decide where to start concealing.
+    pub fn confidence(&self) -> f64 {
+        self.width as f64 / self.range_width as f64
+    }
+}
+
+#[derive(Clone)]
 pub struct ArithmeticDecoder {
     range: Range,
+    initial_precision: u64,
     precision: u64,
     first_time: bool,
     input_buffer: u64,
     finished: bool,
+    past_end: bool,
+    symbols_decoded: u64,
+    bits_read: u64,
+    bits_shifted: u64,
+    /// Set with [`set_limits`](Self::set_limits), for every decode.
+    limits: DecodeLimits,
+    /// Bits that may be read, possibly lowered for one call by
+    /// [`decode_with_limit`](Self::decode_with_limit).
+    bit_limit: u64,
+    segment_start: u64,
+    segment_start_shifted: u64,
+    resyncs: u64,
+    /// Middle half scalings since the last bit the encoder settled.
+    pending_bits: u64,
+    /// Bits read from the source that belong after the window, which
+    /// happens when a section lowers the precision.
+    carry: u64,
+    carry_len: u64,
 }
 
 impl ArithmeticDecoder {
@@ -23,43 +173,506 @@ impl ArithmeticDecoder {
     pub fn new(precision: u64) -> Self {
         Self {
             range: Range::new(precision),
+            initial_precision: precision,
             precision,
             first_time: true,
             input_buffer: 0,
             finished: false,
+            past_end: false,
+            symbols_decoded: 0,
+            bits_read: 0,
+            bits_shifted: 0,
+            limits: DecodeLimits::default(),
+            bit_limit: u64::MAX,
+            segment_start: 0,
+            segment_start_shifted: 0,
+            resyncs: 0,
+            pending_bits: 0,
+            carry: 0,
+            carry_len: 0,
         }
     }
 
-    pub fn decode<R: Read, B: Bit>(
+    /// Like [`new`](Self::new) but returns an error for a precision the
+    /// coder can not use instead of panicking.
+    pub fn try_new(precision: u64) -> Result<Self, PrecisionError> {
+        check_precision(precision)?;
+        Ok(Self::new(precision))
+    }
+
+    /// Like [`try_new`](Self::try_new) but also errors if the precision is
+    /// too low for `source_model`, see
+    /// [`check_compatible`](crate::precision::check_compatible).
+    pub fn for_model<M: SourceModel + ?Sized>(
+        precision: u64,
+        source_model: &M,
+    ) -> Result<Self, PrecisionError> {
+        check_compatible(precision, source_model)?;
+        Ok(Self::new(precision))
+    }
+
+    /// A coder with a precision that fits `source_model` however far it
+    /// grows, as its counts are `u32`s. The precision depends only on the
+    /// model, so encoder and decoder agree when built from the same one.
+    pub fn with_model<M: SourceModel + ?Sized>(source_model: &M) -> Self {
+        Self::with_expected_len(source_model, u32::MAX.into())
+    }
+
+    /// Like [`with_model`](Self::with_model) but for a stream that updates
+    /// `source_model` at most `expected_len` times, which allows a lower
+    /// and so faster precision. See
+    /// [`recommended_precision`](crate::recommended_precision).
+    pub fn with_expected_len<M: SourceModel + ?Sized>(source_model: &M, expected_len: u64) -> Self {
+        Self::new(recommended_precision(
+            source_model.total_count(),
+            expected_len,
+            Rescale::Never,
+        ))
+    }
+
+    /// Decodes a symbol with any [`SourceModel`], such as a [`Model`] or a
+    /// [`ByteModel`](crate::ByteModel).
+    pub fn decode<R: Read, B: Bit, M: SourceModel + ?Sized>(
         &mut self,
-        source_model: &Model,
+        source_model: &M,
         bit_source: &mut BitReader<R, B>,
     ) -> Result<u32, Error> {
-        if self.first_time {
-            for _ in 0..self.precision {
-                self.input_buffer = (self.input_buffer << 1) | self.bit(bit_source)?;
+        self.decode_from(source_model, bit_source)
+    }
+
+    /// [`decode`](Self::decode) from any source of bits.
+    fn decode_from<S: BitSource + ?Sized, M: SourceModel + ?Sized>(
+        &mut self,
+        source_model: &M,
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        check_headroom(&self.range, source_model)?;
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
+        let symbol = self
+            .lookup(source_model)
+            .ok_or_else(|| self.error(DecodeFailure::NoMatchingSymbol))?;
+        let low_high = self.range.calculate_range(symbol, source_model);
+        self.take_symbol(symbol, low_high, source_model.eof(), bit_source)
+    }
+
+    /// Like [`decode`](Self::decode) but also returns the probability the
+    /// model gave the symbol, the number of bits it took and how confident
+    /// the decoder can be in it.
+    pub fn decode_with_info<R: Read, B: Bit>(
+        &mut self,
+        source_model: &Model,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<DecodeInfo, Error> {
+        self.decode_with_info_from(source_model, bit_source)
+    }
+
+    /// [`decode_with_info`](Self::decode_with_info) from any source of bits.
+    fn decode_with_info_from<S: BitSource + ?Sized>(
+        &mut self,
+        source_model: &Model,
+        bit_source: &mut S,
+    ) -> Result<DecodeInfo, Error> {
+        let shifted = self.bits_shifted;
+        let range = self.range.clone();
+        let symbol = self.decode_from(source_model, bit_source)?;
+        let (low, high) = range.calculate_range(symbol, source_model);
+        Ok(DecodeInfo {
+            symbol,
+            probability: source_model.probability(symbol),
+            bits: self.bits_shifted - shifted,
+            width: high - low,
+            range_width: range.width(),
+            after_resync: self.resyncs > 0,
+        })
+    }
+
+    /// Decodes symbols into `out` until it is full or the EOF is decoded,
+    /// returning how many were written, without the EOF. Reusing `out`
+    /// across streams keeps decompression loops free of allocations.
+    ///
+    /// `update` is called with the model after every symbol but the EOF,
+    /// so the model adapts like it would when decoding symbol by symbol:
+    /// pass `|model, symbol| model.update_symbol(symbol)` for an adaptive
+    /// [`Model`] and `|_, _| {}` for a static one.
+    ///
+    /// Once the EOF has been decoded, further calls write nothing and
+    /// return 0.
+    pub fn decode_symbols_into<R: Read, B: Bit, M: SourceModel + ?Sized, U: FnMut(&mut M, u32)>(
+        &mut self,
+        source_model: &mut M,
+        bit_source: &mut BitReader<R, B>,
+        out: &mut [u32],
+        update: U,
+    ) -> Result<usize, Error> {
+        self.decode_symbols_into_from(source_model, bit_source, out, update)
+    }
+
+    /// [`decode_symbols_into`](Self::decode_symbols_into) from any source of bits.
+    fn decode_symbols_into_from<
+        S: BitSource + ?Sized,
+        M: SourceModel + ?Sized,
+        U: FnMut(&mut M, u32),
+    >(
+        &mut self,
+        source_model: &mut M,
+        bit_source: &mut S,
+        out: &mut [u32],
+        mut update: U,
+    ) -> Result<usize, Error> {
+        for (written, slot) in out.iter_mut().enumerate() {
+            if self.finished {
+                return Ok(written);
             }
-            self.first_time = false;
+            let symbol = self.decode_from(source_model, bit_source)?;
+            if symbol == source_model.eof() {
+                return Ok(written);
+            }
+            update(source_model, symbol);
+            *slot = symbol;
+        }
+        Ok(out.len())
+    }
+
+    /// Like [`decode_symbols_into`](Self::decode_symbols_into) for a model
+    /// of bytes, such as a [`ByteModel`](crate::ByteModel): every symbol but
+    /// the EOF must be below 256, which is checked before decoding.
+    pub fn decode_into<R: Read, B: Bit, M: SourceModel + ?Sized, U: FnMut(&mut M, u32)>(
+        &mut self,
+        source_model: &mut M,
+        bit_source: &mut BitReader<R, B>,
+        out: &mut [u8],
+        update: U,
+    ) -> Result<usize, Error> {
+        self.decode_into_from(source_model, bit_source, out, update)
+    }
+
+    /// [`decode_into`](Self::decode_into) from any source of bits.
+    fn decode_into_from<S: BitSource + ?Sized, M: SourceModel + ?Sized, U: FnMut(&mut M, u32)>(
+        &mut self,
+        source_model: &mut M,
+        bit_source: &mut S,
+        out: &mut [u8],
+        mut update: U,
+    ) -> Result<usize, Error> {
+        let num_symbols = source_model.num_symbols();
+        if num_symbols > 257 || (num_symbols == 257 && source_model.eof() != 256) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "decoding into bytes needs a model whose symbols other than the EOF are below 256",
+            ));
+        }
+        for (written, slot) in out.iter_mut().enumerate() {
+            if self.finished {
+                return Ok(written);
+            }
+            let symbol = self.decode_from(source_model, bit_source)?;
+            if symbol == source_model.eof() {
+                return Ok(written);
+            }
+            update(source_model, symbol);
+            *slot = symbol as u8;
+        }
+        Ok(out.len())
+    }
+
+    /// Like [`decode`](Self::decode) but fails with a [`LimitError`] instead of
+    /// decoding more than `limits.max_symbols` symbols or reading more than
+    /// `limits.max_bytes` bytes from `bit_source`. Use this for untrusted
+    /// streams that may never encode an EOF.
+    pub fn decode_with_limit<R: Read, B: Bit, M: SourceModel + ?Sized>(
+        &mut self,
+        source_model: &M,
+        bit_source: &mut BitReader<R, B>,
+        limits: &DecodeLimits,
+    ) -> Result<u32, Error> {
+        self.decode_with_limit_from(source_model, bit_source, limits)
+    }
+
+    /// [`decode_with_limit`](Self::decode_with_limit) from any source of bits.
+    fn decode_with_limit_from<S: BitSource + ?Sized, M: SourceModel + ?Sized>(
+        &mut self,
+        source_model: &M,
+        bit_source: &mut S,
+        limits: &DecodeLimits,
+    ) -> Result<u32, Error> {
+        if self.symbols_decoded >= limits.max_symbols {
+            return Err(self.error(DecodeFailure::Limit(LimitError::Symbols {
+                limit: limits.max_symbols,
+            })));
+        }
+
+        let bit_limit = limits.max_bytes.saturating_mul(8).min(self.bit_limit);
+        let previous = std::mem::replace(&mut self.bit_limit, bit_limit);
+        let result = self.decode_from(source_model, bit_source);
+        self.bit_limit = previous;
+        result
+    }
+
+    /// Like [`decode`](Self::decode) but resolves the symbol with `table`, a
+    /// direct lookup for small alphabets and a block search, vectorized with
+    /// the `simd` feature, for larger ones, see [`lookup`](crate::lookup).
+    /// The table is rebuilt whenever the model's counts differ from the ones
+    /// it was built from.
+    pub fn decode_with_table<R: Read, B: Bit>(
+        &mut self,
+        source_model: &Model,
+        table: &mut LookupTable,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        self.decode_with_table_from(source_model, table, bit_source)
+    }
+
+    /// [`decode_with_table`](Self::decode_with_table) from any source of bits.
+    fn decode_with_table_from<S: BitSource + ?Sized>(
+        &mut self,
+        source_model: &Model,
+        table: &mut LookupTable,
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        check_headroom(&self.range, source_model)?;
+        table.refresh(source_model);
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
+        let symbol = self
+            .target_count(source_model.total_count().into())
+            .map(|count| table.symbol_at(count as u32))
+            .ok_or_else(|| self.error(DecodeFailure::NoMatchingSymbol))?;
+        let low_high = self.range.calculate_range(symbol, source_model);
+        self.take_symbol(symbol, low_high, source_model.eof(), bit_source)
+    }
+
+    /// Decodes a symbol of a [`LargeModel`], whose total may exceed
+    /// `u32::MAX`.
+    pub fn decode_large<R: Read, B: Bit>(
+        &mut self,
+        source_model: &LargeModel,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        self.decode_large_from(source_model, bit_source)
+    }
+
+    /// [`decode_large`](Self::decode_large) from any source of bits.
+    fn decode_large_from<S: BitSource + ?Sized>(
+        &mut self,
+        source_model: &LargeModel,
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        check_large_headroom(&self.range, source_model)?;
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
+        let symbol = self
+            .target_count(source_model.total_count())
+            .map(|count| source_model.symbol_at(count))
+            .ok_or_else(|| self.error(DecodeFailure::NoMatchingSymbol))?;
+        let (low, high) = source_model.cumulative(symbol);
+        let low_high = self
+            .range
+            .interval_counts(low, high, source_model.total_count());
+        self.take_symbol(symbol, low_high, source_model.eof(), bit_source)
+    }
+
+    /// Decodes a symbol encoded with
+    /// [`ArithmeticEncoder::encode_excluding`](crate::ArithmeticEncoder::encode_excluding)
+    /// using the same `exclusions`, which must be sorted without duplicates.
+    pub fn decode_excluding<R: Read, B: Bit>(
+        &mut self,
+        source_model: &Model,
+        exclusions: &[u32],
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        self.decode_excluding_from(source_model, exclusions, bit_source)
+    }
+
+    /// [`decode_excluding`](Self::decode_excluding) from any source of bits.
+    fn decode_excluding_from<S: BitSource + ?Sized>(
+        &mut self,
+        source_model: &Model,
+        exclusions: &[u32],
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        check_headroom(&self.range, source_model)?;
+        check_exclusions(exclusions, source_model)?;
+        self.decode_with(
+            source_model.num_symbols(),
+            source_model.eof(),
+            bit_source,
+            |range, symbol| range.calculate_range_excluding(symbol, exclusions, source_model),
+        )
+    }
+
+    /// Decodes a binary decision coded with
+    /// [`ArithmeticEncoder::encode_bit`](crate::ArithmeticEncoder::encode_bit)
+    /// using the same `probability_zero`.
+    pub fn decode_bit<R: Read, B: Bit>(
+        &mut self,
+        probability_zero: u32,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<bool, Error> {
+        self.decode_bit_from(probability_zero, bit_source)
+    }
+
+    /// [`decode_bit`](Self::decode_bit) from any source of bits.
+    fn decode_bit_from<S: BitSource + ?Sized>(
+        &mut self,
+        probability_zero: u32,
+        bit_source: &mut S,
+    ) -> Result<bool, Error> {
+        check_bit_probability(&self.range, probability_zero)?;
+        let bit = self.decode_with(2, 2, bit_source, |range, bit| {
+            bit_interval(range, bit == 1, probability_zero)
+        })?;
+        Ok(bit == 1)
+    }
+
+    /// The cumulative count out of `total` that the input falls on, for
+    /// streams written with
+    /// [`ArithmeticEncoder::encode_interval`](crate::ArithmeticEncoder::encode_interval).
+    /// The caller finds the interval `[low, high)` containing it and passes
+    /// that to [`decode_interval`](Self::decode_interval), which consumes it.
+    /// Until then the input is not advanced.
+    pub fn decode_target<R: Read, B: Bit>(
+        &mut self,
+        total: u32,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        self.decode_target_from(total, bit_source)
+    }
+
+    /// [`decode_target`](Self::decode_target) from any source of bits.
+    fn decode_target_from<S: BitSource + ?Sized>(
+        &mut self,
+        total: u32,
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        check_interval(&self.range, 0, total, total)?;
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
+        self.target_count(total.into())
+            .map(|count| count as u32)
+            .ok_or_else(|| self.error(DecodeFailure::NoMatchingSymbol))
+    }
+
+    /// Consumes the interval `[low, high)` out of `total`, which must contain
+    /// the count returned by [`decode_target`](Self::decode_target), and
+    /// renormalizes. There is no EOF symbol in this mode; call
+    /// [`set_finished`](Self::set_finished) once the stream has ended.
+    pub fn decode_interval<R: Read, B: Bit>(
+        &mut self,
+        low: u32,
+        high: u32,
+        total: u32,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<(), Error> {
+        self.decode_interval_from(low, high, total, bit_source)
+    }
+
+    /// [`decode_interval`](Self::decode_interval) from any source of bits.
+    fn decode_interval_from<S: BitSource + ?Sized>(
+        &mut self,
+        low: u32,
+        high: u32,
+        total: u32,
+        bit_source: &mut S,
+    ) -> Result<(), Error> {
+        check_interval(&self.range, low, high, total)?;
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
+        let low_high = self
+            .range
+            .interval_counts(low.into(), high.into(), total.into());
+        if !(low_high.0..low_high.1).contains(&self.input_buffer) {
+            return Err(self.error(DecodeFailure::NoMatchingSymbol));
         }
+        // `low` is below `total`, so never taken for an EOF
+        self.take_symbol(low, low_high, u32::MAX, bit_source)
+            .map(|_| ())
+    }
+
+    /// `interval` maps a symbol to its `(low, high)` interval in the
+    /// current range. Excluded symbols map to an empty interval and are
+    /// never selected.
+    fn decode_with<S: BitSource + ?Sized, F: Fn(&Range, u32) -> (u64, u64)>(
+        &mut self,
+        num_symbols: u32,
+        eof: u32,
+        bit_source: &mut S,
+        interval: F,
+    ) -> Result<u32, Error> {
+        self.check_unfinished()?;
+        self.fill(bit_source)?;
 
         let symbol: u32;
         let mut low_high: (u64, u64);
-        let mut sym_idx_low_high = (0, source_model.num_symbols());
+        // [low, high) of the symbols that can still contain the input
+        let mut sym_idx_low_high = (0, num_symbols);
 
         loop {
+            if sym_idx_low_high.0 >= sym_idx_low_high.1 {
+                return Err(self.error(DecodeFailure::NoMatchingSymbol));
+            }
             let sym_idx_mid = (sym_idx_low_high.0 + sym_idx_low_high.1) / 2;
-            low_high = self.range.calculate_range(sym_idx_mid, source_model);
+            low_high = interval(&self.range, sym_idx_mid);
             if low_high.0 <= self.input_buffer && self.input_buffer < low_high.1 {
                 symbol = sym_idx_mid;
                 break;
             } else if self.input_buffer >= low_high.1 {
                 sym_idx_low_high.0 = sym_idx_mid + 1;
             } else {
-                sym_idx_low_high.1 = sym_idx_mid - 1;
+                sym_idx_low_high.1 = sym_idx_mid;
             }
         }
 
-        if symbol == source_model.eof() {
+        self.take_symbol(symbol, low_high, eof, bit_source)
+    }
+
+    /// The symbol whose interval contains the input, found by inverting
+    /// [`Range::interval_counts`] to a cumulative count and looking that up
+    /// once, a single Fenwick tree descent for a [`Model`], instead of
+    /// searching over symbols.
+    fn lookup<M: SourceModel + ?Sized>(&self, source_model: &M) -> Option<u32> {
+        let count = self.target_count(source_model.total_count().into())? as u32;
+        // skewed models mostly decode their dominant symbol
+        match source_model.dominant() {
+            Some((symbol, low, high)) if low <= count && count < high => Some(symbol),
+            _ => Some(source_model.symbol_at(count)),
+        }
+    }
+
+    /// The largest cumulative count out of `total` that scales to at most
+    /// the input, or `None` if the input is outside of the range. Below
+    /// `total`, so it fits whatever type `total` came from.
+    fn target_count(&self, total: u64) -> Option<u64> {
+        let total = u128::from(total);
+        if total == 0 || !(self.range.low()..self.range.high()).contains(&self.input_buffer) {
+            return None;
+        }
+        let offset = u128::from(self.input_buffer - self.range.low());
+        Some((((offset + 1) * total - 1) / u128::from(self.range.width())) as u64)
+    }
+
+    /// Consumes `symbol`, whose interval `low_high` contains the input, and
+    /// renormalizes.
+    fn take_symbol<S: BitSource + ?Sized>(
+        &mut self,
+        symbol: u32,
+        low_high: (u64, u64),
+        eof: u32,
+        bit_source: &mut S,
+    ) -> Result<u32, Error> {
+        // only a model whose `symbol_at` disagrees with its `cumulative`
+        // can get here with an empty interval
+        if low_high.0 >= low_high.1 {
+            return Err(self.error(DecodeFailure::NoMatchingSymbol));
+        }
+        self.symbols_decoded += 1;
+
+        if symbol == eof {
+            // account for the bits the encoder shifted out after the EOF
+            // without reading past the end of the segment
+            let mut range = self.range.clone();
+            range.update_range(low_high);
+            self.bits_shifted += renormalize(&mut range);
             self.set_finished();
             return Ok(symbol);
         }
@@ -69,33 +682,74 @@ impl ArithmeticDecoder {
         while self.range.in_bottom_half() || self.range.in_upper_half() {
             if self.range.in_bottom_half() {
                 self.range.scale_bottom_half();
-                self.input_buffer = (2 * self.input_buffer) | self.bit(bit_source)?;
+                self.input_buffer = (2 * self.input_buffer) | self.shift(bit_source)?;
+                self.pending_bits = 0;
             } else if self.range.in_upper_half() {
                 self.range.scale_upper_half();
                 self.input_buffer =
-                    (2 * (self.input_buffer - self.range.half())) | self.bit(bit_source)?;
+                    (2 * (self.input_buffer - self.range.half())) | self.shift(bit_source)?;
+                self.pending_bits = 0;
             }
         }
 
         while self.range.in_middle_half() {
             self.range.scale_middle_half();
             self.input_buffer =
-                (2 * (self.input_buffer - self.range.quarter())) | self.bit(bit_source)?;
+                (2 * (self.input_buffer - self.range.quarter())) | self.shift(bit_source)?;
+            self.pending_bits += 1;
         }
 
         Ok(symbol)
     }
 
-    fn bit<R: Read, B: Bit>(&mut self, source: &mut BitReader<R, B>) -> Result<u64, Error> {
+    /// Reads the first `precision` bits into the input buffer.
+    fn fill<S: BitSource + ?Sized>(&mut self, bit_source: &mut S) -> Result<(), Error> {
+        if self.first_time {
+            for _ in 0..self.precision {
+                self.input_buffer = (self.input_buffer << 1) | self.bit(bit_source)?;
+            }
+            self.first_time = false;
+        }
+        Ok(())
+    }
+
+    fn error(&self, failure: DecodeFailure) -> Error {
+        DecodeError {
+            failure,
+            bit_offset: self.bits_read,
+            symbols_decoded: self.symbols_decoded,
+        }
+        .into()
+    }
+
+    /// Reads the bit shifted in while renormalizing.
+    fn shift<S: BitSource + ?Sized>(&mut self, bit_source: &mut S) -> Result<u64, Error> {
+        self.bits_shifted += 1;
+        self.bit(bit_source)
+    }
+
+    fn bit<S: BitSource + ?Sized>(&mut self, source: &mut S) -> Result<u64, Error> {
+        if self.bits_read >= self.bit_limit {
+            return Err(self.error(DecodeFailure::Limit(LimitError::Bytes {
+                limit: self.bit_limit / 8,
+            })));
+        }
+
+        if self.carry_len > 0 {
+            self.carry_len -= 1;
+            self.bits_read += 1;
+            return Ok((self.carry >> self.carry_len) & 1);
+        }
+
         match source.read_bit() {
-            Ok(res) => Ok(u64::from(res)),
+            Ok(res) => {
+                self.bits_read += 1;
+                Ok(u64::from(res))
+            }
             Err(_e) => {
+                self.past_end = true;
                 if self.precision == 0 {
-                    return Err(Error::new(
-                        ErrorKind::UnexpectedEof,
-                        "EOF has been read $PRECISION times and \nEOF symbol has not been \
-                         decoded.\nDid you forget to encode the EOF symbol?",
-                    ));
+                    return Err(self.error(DecodeFailure::MissingEof));
                 }
                 self.precision -= 1;
                 Ok(0)
@@ -103,23 +757,644 @@ impl ArithmeticDecoder {
         }
     }
 
+    /// Starts decoding the next segment of a stream written with
+    /// [`ArithmeticEncoder::finish_segment`](crate::ArithmeticEncoder::finish_segment)
+    /// (or independently encoded streams joined with
+    /// [`splice`](crate::segment::splice)). Every symbol of the current
+    /// segment, including its EOF if it has one, must have been decoded.
+    ///
+    /// The decoder reads ahead, so the start of the next segment is already
+    /// in its window; this works out where the current segment ended from
+    /// the number of bits the encoder must have written, keeps the bits
+    /// after it and refills the window from `bit_source`.
+    pub fn continue_with_next_segment<R: Read, B: Bit>(
+        &mut self,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<(), Error> {
+        self.continue_with_next_segment_from(bit_source)
+    }
+
+    /// [`continue_with_next_segment`](Self::continue_with_next_segment) from any source of bits.
+    fn continue_with_next_segment_from<S: BitSource + ?Sized>(
+        &mut self,
+        bit_source: &mut S,
+    ) -> Result<(), Error> {
+        let (window, carried) = self.end_segment(bit_source)?;
+        let next_start = self.bits_read;
+        self.start_segment(window, carried, bit_source)?;
+
+        if carried == 0 && self.bits_read == next_start {
+            return Err(self.error(DecodeFailure::NoNextSegment));
+        }
+        self.resyncs += 1;
+        Ok(())
+    }
+
+    /// Like [`continue_with_next_segment`](Self::continue_with_next_segment)
+    /// for a stream written with
+    /// [`ArithmeticEncoder::finish_section`](crate::ArithmeticEncoder::finish_section),
+    /// switching to the precision stored in front of the next section.
+    pub fn continue_with_next_section<R: Read, B: Bit>(
+        &mut self,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<(), Error> {
+        self.continue_with_next_section_from(bit_source)
+    }
+
+    /// [`continue_with_next_section`](Self::continue_with_next_section) from any source of bits.
+    fn continue_with_next_section_from<S: BitSource + ?Sized>(
+        &mut self,
+        bit_source: &mut S,
+    ) -> Result<(), Error> {
+        let (mut window, mut carried) = self.end_segment(bit_source)?;
+
+        // the precision is the first byte of the section
+        self.past_end = false;
+        let precision = if carried >= 8 {
+            carried -= 8;
+            let precision = window >> carried;
+            window &= (1 << carried) - 1;
+            precision
+        } else {
+            let mut precision = window;
+            for _ in carried..8 {
+                precision = (precision << 1) | self.bit(bit_source)?;
+            }
+            window = 0;
+            carried = 0;
+            precision
+        };
+        if self.past_end {
+            return Err(self.error(DecodeFailure::NoNextSegment));
+        }
+        if let Err(error) = check_precision(precision) {
+            return Err(self.error(DecodeFailure::InvalidPrecision(error)));
+        }
+
+        self.initial_precision = precision;
+        self.segment_start += 8;
+        self.start_segment(window, carried, bit_source)?;
+        self.resyncs += 1;
+        Ok(())
+    }
+
+    /// Skips to the end of the current segment, returning the bits of the
+    /// next one already read (as a value and a count).
+    fn end_segment<S: BitSource + ?Sized>(
+        &mut self,
+        bit_source: &mut S,
+    ) -> Result<(u64, u64), Error> {
+        self.fill(bit_source)?;
+
+        // the encoder wrote the shifted bits plus 2 from finish_encode, then
+        // padded to a byte
+        let shifted = self.bits_shifted - self.segment_start_shifted;
+        let segment_bits = (shifted + 2 + 7) / 8 * 8;
+        let read = self.bits_read - self.segment_start;
+
+        // bits of the next segment already in the window, above any zeros
+        // read past the end of the input
+        let carried = read.saturating_sub(segment_bits);
+        // only a corrupted restored state gets here with a full u64 of bits
+        if carried >= 64 {
+            return Err(state::invalid());
+        }
+        let zeros = self.initial_precision - self.precision;
+        let mask = (1 << carried) - 1;
+        let window = (self.input_buffer >> zeros) & mask;
+        // with a small precision the window may not reach the next segment
+        for _ in read..segment_bits {
+            self.bit(bit_source)?;
+        }
+
+        self.segment_start += segment_bits;
+        self.segment_start_shifted = self.bits_shifted;
+        Ok((window, carried))
+    }
+
+    /// Resets the decoder and fills the window, starting with `carried`
+    /// bits already read into `window`.
+    fn start_segment<S: BitSource + ?Sized>(
+        &mut self,
+        window: u64,
+        carried: u64,
+        bit_source: &mut S,
+    ) -> Result<(), Error> {
+        let (window, carried) = if carried > self.initial_precision {
+            // give back what does not fit in the window
+            let excess = carried - self.initial_precision;
+            if self.carry_len + excess >= 64 {
+                return Err(state::invalid());
+            }
+            self.carry = (self.carry & ((1 << self.carry_len) - 1))
+                | ((window & ((1 << excess) - 1)) << self.carry_len);
+            self.carry_len += excess;
+            self.bits_read -= excess;
+            (window >> excess, self.initial_precision)
+        } else {
+            (window, carried)
+        };
+
+        self.range = Range::new(self.initial_precision);
+        self.precision = self.initial_precision;
+        self.input_buffer = window;
+        self.finished = false;
+        self.past_end = false;
+        self.pending_bits = 0;
+        for _ in carried..self.precision {
+            self.input_buffer = (self.input_buffer << 1) | self.bit(bit_source)?;
+        }
+        Ok(())
+    }
+
+    /// Number of times decoding restarted at a segment boundary.
+    pub const fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Number of symbols decoded so far, including the EOF.
+    pub const fn symbols_decoded(&self) -> u64 {
+        self.symbols_decoded
+    }
+
+    /// Number of bits read from the input so far.
+    pub const fn bits_read(&self) -> u64 {
+        self.bits_read
+    }
+
+    /// Number of bits the encoder has settled for the symbols decoded so
+    /// far. Pending bits are not settled until the next half scaling.
+    pub(crate) const fn bits_settled(&self) -> u64 {
+        self.bits_shifted - self.pending_bits
+    }
+
+    /// Number of bits taken from the bit source, which is ahead of
+    /// [`bits_read`](Self::bits_read) when bits were given back after a
+    /// section lowered the precision.
+    pub(crate) const fn source_position(&self) -> u64 {
+        self.bits_read + self.carry_len
+    }
+
     pub fn set_finished(&mut self) {
         self.finished = true;
     }
 
+    /// Errors instead of decoding past the end of the stream, where the
+    /// input is padding or the next segment and would decode to garbage, or
+    /// past the symbol limit.
+    fn check_unfinished(&self) -> Result<(), Error> {
+        if self.finished {
+            Err(self.error(DecodeFailure::Finished))
+        } else if self.symbols_decoded >= self.limits.max_symbols {
+            Err(self.error(DecodeFailure::Limit(LimitError::Symbols {
+                limit: self.limits.max_symbols,
+            })))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Caps every decode, like
+    /// [`decode_with_limit`](Self::decode_with_limit) does for one call, so a
+    /// corrupted or malicious stream that never reaches its EOF can not
+    /// decode without end. The limits count from the start of the stream and
+    /// are kept by [`reset`](Self::reset), but not saved by
+    /// [`checkpoint`](Self::checkpoint).
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+        self.bit_limit = limits.max_bytes.saturating_mul(8);
+    }
+
+    pub const fn limits(&self) -> DecodeLimits {
+        self.limits
+    }
+
     pub const fn finished(&self) -> bool {
         self.finished
     }
+
+    /// Starts over as a fresh decoder, so the next call reads a new,
+    /// independent stream from whatever bit source it is given. The
+    /// precision of the current section is kept.
+    pub fn reset(&mut self) {
+        let limits = self.limits;
+        *self = Self::new(self.initial_precision);
+        self.set_limits(limits);
+    }
+
+    /// Saves the range, input window and stream position so decoding can be
+    /// resumed later with [`restore`](Self::restore), see
+    /// [`state`](crate::state).
+    pub fn checkpoint(&self) -> CoderState {
+        let flags = u64::from(self.first_time)
+            | u64::from(self.finished) << 1
+            | u64::from(self.past_end) << 2;
+        CoderState::decoder(vec![
+            self.initial_precision,
+            self.precision,
+            self.range.low(),
+            self.range.high(),
+            self.input_buffer,
+            flags,
+            self.symbols_decoded,
+            self.bits_read,
+            self.bits_shifted,
+            self.segment_start,
+            self.segment_start_shifted,
+            self.resyncs,
+            self.pending_bits,
+            self.carry,
+            self.carry_len,
+        ])
+    }
+
+    /// A decoder in the state saved by [`checkpoint`](Self::checkpoint),
+    /// which reads on from [`CoderState::bits_read`] bits into the input.
+    pub fn restore(state: &CoderState) -> Result<Self, Error> {
+        let words = state.decoder_words()?;
+        let (initial_precision, precision) = (words[0], words[1]);
+        let range = state::restore_range(initial_precision, words[2], words[3])?;
+        let (bits_read, bits_shifted) = (words[BITS_READ_WORD], words[8]);
+        let (segment_start, segment_start_shifted) = (words[9], words[10]);
+        let first_time = words[5] & 1 != 0;
+        let mut consistent = precision <= initial_precision
+            && words[4] < 1 << initial_precision
+            && words[5] <= 0b111
+            && segment_start <= bits_read
+            && segment_start_shifted <= bits_shifted
+            && words[12] <= bits_shifted
+            && words[14] < 64;
+        if consistent {
+            // a segment reads its window, then one bit per shift
+            let read = bits_read - segment_start;
+            let shifted = bits_shifted - segment_start_shifted;
+            consistent = if first_time {
+                read <= initial_precision && shifted == 0
+            } else {
+                read <= initial_precision.saturating_add(shifted)
+            };
+        }
+        if !consistent {
+            return Err(state::invalid());
+        }
+        Ok(Self {
+            range,
+            initial_precision,
+            precision,
+            first_time,
+            input_buffer: words[4],
+            finished: words[5] & 2 != 0,
+            past_end: words[5] & 4 != 0,
+            symbols_decoded: words[6],
+            bits_read,
+            bits_shifted,
+            limits: DecodeLimits::default(),
+            bit_limit: u64::MAX,
+            segment_start,
+            segment_start_shifted,
+            resyncs: words[11],
+            pending_bits: words[12],
+            carry: words[13],
+            carry_len: words[14],
+        })
+    }
+}
+
+/// An [`ArithmeticDecoder`] over a compressed buffer that is entirely in
+/// memory, which loads bits from the slice a word at a time instead of
+/// going through a [`BitReader`] and [`Read`]. It decodes with the same logic,
+/// so it reads exactly what the streaming decoder reads from the same
+/// bytes.
+pub struct SliceDecoder<'a> {
+    decoder: ArithmeticDecoder,
+    input: SliceBits<'a>,
+}
+
+impl<'a> SliceDecoder<'a> {
+    pub fn new(precision: u64, input: &'a [u8]) -> Self {
+        Self {
+            decoder: ArithmeticDecoder::new(precision),
+            input: SliceBits::new(input),
+        }
+    }
+
+    /// See [`ArithmeticDecoder::decode`].
+    pub fn decode<M: SourceModel + ?Sized>(&mut self, source_model: &M) -> Result<u32, Error> {
+        self.decoder.decode_from(source_model, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_with_info`].
+    pub fn decode_with_info(&mut self, source_model: &Model) -> Result<DecodeInfo, Error> {
+        self.decoder
+            .decode_with_info_from(source_model, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_symbols_into`].
+    pub fn decode_symbols_into<M: SourceModel + ?Sized, U: FnMut(&mut M, u32)>(
+        &mut self,
+        source_model: &mut M,
+        out: &mut [u32],
+        update: U,
+    ) -> Result<usize, Error> {
+        self.decoder
+            .decode_symbols_into_from(source_model, &mut self.input, out, update)
+    }
+
+    /// See [`ArithmeticDecoder::decode_into`].
+    pub fn decode_into<M: SourceModel + ?Sized, U: FnMut(&mut M, u32)>(
+        &mut self,
+        source_model: &mut M,
+        out: &mut [u8],
+        update: U,
+    ) -> Result<usize, Error> {
+        self.decoder
+            .decode_into_from(source_model, &mut self.input, out, update)
+    }
+
+    /// See [`ArithmeticDecoder::decode_with_limit`].
+    pub fn decode_with_limit<M: SourceModel + ?Sized>(
+        &mut self,
+        source_model: &M,
+        limits: &DecodeLimits,
+    ) -> Result<u32, Error> {
+        self.decoder
+            .decode_with_limit_from(source_model, &mut self.input, limits)
+    }
+
+    /// See [`ArithmeticDecoder::decode_with_table`].
+    pub fn decode_with_table(
+        &mut self,
+        source_model: &Model,
+        table: &mut LookupTable,
+    ) -> Result<u32, Error> {
+        self.decoder
+            .decode_with_table_from(source_model, table, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_large`].
+    pub fn decode_large(&mut self, source_model: &LargeModel) -> Result<u32, Error> {
+        self.decoder
+            .decode_large_from(source_model, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_excluding`].
+    pub fn decode_excluding(
+        &mut self,
+        source_model: &Model,
+        exclusions: &[u32],
+    ) -> Result<u32, Error> {
+        self.decoder
+            .decode_excluding_from(source_model, exclusions, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_bit`].
+    pub fn decode_bit(&mut self, probability_zero: u32) -> Result<bool, Error> {
+        self.decoder
+            .decode_bit_from(probability_zero, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_target`].
+    pub fn decode_target(&mut self, total: u32) -> Result<u32, Error> {
+        self.decoder.decode_target_from(total, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::decode_interval`].
+    pub fn decode_interval(&mut self, low: u32, high: u32, total: u32) -> Result<(), Error> {
+        self.decoder
+            .decode_interval_from(low, high, total, &mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::continue_with_next_segment`].
+    pub fn continue_with_next_segment(&mut self) -> Result<(), Error> {
+        self.decoder
+            .continue_with_next_segment_from(&mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::continue_with_next_section`].
+    pub fn continue_with_next_section(&mut self) -> Result<(), Error> {
+        self.decoder
+            .continue_with_next_section_from(&mut self.input)
+    }
+
+    /// See [`ArithmeticDecoder::set_limits`].
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.decoder.set_limits(limits);
+    }
+
+    pub const fn finished(&self) -> bool {
+        self.decoder.finished()
+    }
+
+    /// The decoder, for its counters such as
+    /// [`bits_read`](ArithmeticDecoder::bits_read).
+    pub const fn decoder(&self) -> &ArithmeticDecoder {
+        &self.decoder
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::io::{Cursor, ErrorKind};
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{ArithmeticDecoder, DecodeInfo, DecodeLimits, SliceDecoder};
+    use crate::{
+        binary::PROBABILITY_ONE,
+        error::{DecodeError, DecodeFailure, LimitError},
+        lookup::LookupTable,
+        ArithmeticEncoder, ByteModel, EOFKind, Model,
+    };
+
+    #[test]
+    fn slice_decoder() {
+        let data = include_bytes!("../tests/sherlock.rs");
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &byte in data.iter() {
+            encoder
+                .encode(byte.into(), &model, &mut out_writer)
+                .unwrap();
+            model.update_symbol(byte.into());
+        }
+        encoder.encode(256, &model, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref();
+
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut reference_model = model.clone();
+        let mut decoder = SliceDecoder::new(48, encoded);
+        let mut reference = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        for &byte in data.iter() {
+            assert_eq!(decoder.decode(&model).unwrap(), byte.into());
+            model.update_symbol(byte.into());
+            reference.decode(&reference_model, &mut in_reader).unwrap();
+            reference_model.update_symbol(byte.into());
+            assert_eq!(decoder.decoder().bits_read(), reference.bits_read());
+        }
+        assert_eq!(decoder.decode(&model).unwrap(), 256);
+        assert!(decoder.finished());
+        assert!(decoder.decode(&model).is_err());
+
+        // truncated input decodes like it does when streamed
+        let truncated = &encoded[..encoded.len() / 2];
+        let mut decoder = SliceDecoder::new(48, truncated);
+        let mut reference = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(truncated);
+        for _ in 0..=data.len() {
+            let decoded = decoder.decode(&model).map_err(|e| e.to_string());
+            let streamed = reference
+                .decode(&model, &mut in_reader)
+                .map_err(|e| e.to_string());
+            assert_eq!(decoded, streamed);
+            if decoded.is_err() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn slice_decoder_entry_points() {
+        let mut model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let exclusions = [2, 5];
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for i in 0..40u32 {
+            encoder.encode(i % 9, &model, &mut out_writer).unwrap();
+            encoder
+                .encode_excluding(i % 2 * 7, &exclusions, &model, &mut out_writer)
+                .unwrap();
+            encoder
+                .encode_bit(i % 3 == 0, PROBABILITY_ONE / 5, &mut out_writer)
+                .unwrap();
+            encoder
+                .encode_interval(i, i + 1, 100, &mut out_writer)
+                .unwrap();
+        }
+        encoder.encode(9, &model, &mut out_writer).unwrap();
+        encoder.finish_segment(&mut out_writer).unwrap();
+        for i in 0..40u32 {
+            encoder.encode(i % 7, &model, &mut out_writer).unwrap();
+        }
+        encoder.encode(9, &model, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref();
+
+        let mut decoder = SliceDecoder::new(32, encoded);
+        let mut reference = ArithmeticDecoder::new(32);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        for i in 0..40u32 {
+            assert_eq!(
+                decoder.decode_with_info(&model).unwrap(),
+                reference.decode_with_info(&model, &mut in_reader).unwrap()
+            );
+            assert_eq!(
+                decoder.decode_excluding(&model, &exclusions).unwrap(),
+                i % 2 * 7
+            );
+            reference
+                .decode_excluding(&model, &exclusions, &mut in_reader)
+                .unwrap();
+            assert_eq!(decoder.decode_bit(PROBABILITY_ONE / 5).unwrap(), i % 3 == 0);
+            reference
+                .decode_bit(PROBABILITY_ONE / 5, &mut in_reader)
+                .unwrap();
+            assert_eq!(decoder.decode_target(100).unwrap(), i);
+            reference.decode_target(100, &mut in_reader).unwrap();
+            decoder.decode_interval(i, i + 1, 100).unwrap();
+            reference
+                .decode_interval(i, i + 1, 100, &mut in_reader)
+                .unwrap();
+            assert_eq!(decoder.decoder().bits_read(), reference.bits_read());
+        }
+        assert_eq!(decoder.decode(&model).unwrap(), 9);
+        assert_eq!(reference.decode(&model, &mut in_reader).unwrap(), 9);
+
+        decoder.continue_with_next_segment().unwrap();
+        reference
+            .continue_with_next_segment(&mut in_reader)
+            .unwrap();
+        let mut symbols = [0; 64];
+        let mut expected = [0; 64];
+        let written = decoder
+            .decode_symbols_into(&mut model, &mut symbols, |_, _| {})
+            .unwrap();
+        assert_eq!(
+            reference
+                .decode_symbols_into(&mut model, &mut in_reader, &mut expected, |_, _| {})
+                .unwrap(),
+            written
+        );
+        assert_eq!(written, 40);
+        assert_eq!(symbols, expected);
+        assert_eq!(decoder.decoder().bits_read(), reference.bits_read());
+    }
+
+    #[test]
+    fn decode_into() {
+        let data = b"decode into a buffer that is reused";
+        let initial = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut model = initial.clone();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &byte in data {
+            encoder
+                .encode(byte.into(), &model, &mut out_writer)
+                .unwrap();
+            model.update_symbol(byte.into());
+        }
+        encoder.encode(256, &model, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref();
+
+        let update = |model: &mut Model, symbol| model.update_symbol(symbol);
+        let mut buffer = [0u8; 16];
+        let mut decoded = vec![];
+        let mut model = initial.clone();
+        let mut decoder = ArithmeticDecoder::new(32);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        loop {
+            let written = decoder
+                .decode_into(&mut model, &mut in_reader, &mut buffer, update)
+                .unwrap();
+            decoded.extend_from_slice(&buffer[..written]);
+            if written < buffer.len() {
+                break;
+            }
+        }
+        assert_eq!(decoded, data);
+        assert_eq!(
+            decoder
+                .decode_into(&mut model, &mut in_reader, &mut buffer, update)
+                .unwrap(),
+            0
+        );
 
-    use bitbit::{BitReader, MSB};
+        let mut symbols = vec![0u32; 64];
+        let mut model = initial;
+        let mut decoder = ArithmeticDecoder::new(32);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        let written = decoder
+            .decode_symbols_into(&mut model, &mut in_reader, &mut symbols, update)
+            .unwrap();
+        assert_eq!(written, data.len());
+        assert!(symbols[..written]
+            .iter()
+            .zip(data.iter())
+            .all(|(&symbol, &byte)| symbol == byte.into()));
 
-    use super::ArithmeticDecoder;
-    use crate::{EOFKind, Model};
+        let mut wide = Model::builder().num_symbols(300).build();
+        let mut decoder = ArithmeticDecoder::new(32);
+        let error = decoder
+            .decode_into(&mut wide, &mut in_reader, &mut buffer, update)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
 
     #[test]
     fn e2e() {
@@ -138,4 +1413,583 @@ mod tests {
         }
         assert_eq!(output, &[7, 2, 2, 2, 7]);
     }
+
+    #[test]
+    fn e2e_excluding() {
+        let mut source_model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let to_encode: [(u32, &[u32]); 6] = [
+            (7, &[]),
+            (2, &[7, 8]),
+            (0, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            (8, &[0, 1, 2, 3, 4, 5, 6, 7]),
+            (3, &[2]),
+            (9, &[1, 2, 3]),
+        ];
+
+        let mut encoder = ArithmeticEncoder::new(30);
+        let mut output = Cursor::new(vec![]);
+        let mut out_writer = BitWriter::new(&mut output);
+        for &(symbol, exclusions) in &to_encode {
+            encoder
+                .encode_excluding(symbol, exclusions, &source_model, &mut out_writer)
+                .unwrap();
+            source_model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut source_model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(output.into_inner()));
+        let mut decoder = ArithmeticDecoder::new(30);
+        for &(symbol, exclusions) in &to_encode {
+            let sym = decoder
+                .decode_excluding(&source_model, exclusions, &mut in_reader)
+                .unwrap();
+            assert_eq!(sym, symbol);
+            source_model.update_symbol(sym);
+        }
+        assert!(decoder.finished());
+    }
+
+    #[test]
+    fn e2e_byte_model() {
+        let data = b"abracadabra, abracadabra";
+        let mut model = ByteModel::with_eof();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for symbol in data.iter().map(|&byte| u32::from(byte)).chain(Some(256)) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut model = ByteModel::with_eof();
+        let mut in_reader: BitReader<_, MSB> =
+            BitReader::new(Cursor::new(out_writer.get_ref().get_ref().clone()));
+        let mut decoder = ArithmeticDecoder::new(32);
+        let mut output = vec![];
+        while !decoder.finished() {
+            let symbol = decoder.decode(&model, &mut in_reader).unwrap();
+            model.update_symbol(symbol);
+            output.push(symbol as u8);
+        }
+        output.pop();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn e2e_with_table() {
+        let input = Cursor::new(vec![184, 96, 208]);
+        let mut source_model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut table = LookupTable::new();
+        let mut output = Vec::new();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+
+        let mut decoder = ArithmeticDecoder::new(30);
+        while !decoder.finished() {
+            let sym = decoder
+                .decode_with_table(&source_model, &mut table, &mut in_reader)
+                .unwrap();
+            source_model.update_symbol(sym);
+            if sym != source_model.eof() {
+                output.push(sym);
+            };
+        }
+        assert_eq!(output, &[7, 2, 2, 2, 7]);
+    }
+
+    #[test]
+    fn reset() {
+        let messages: [&[u32]; 3] = [&[3, 1, 4, 1, 5], &[9, 2, 6], &[5, 3, 5, 8, 9, 7]];
+        let build = || {
+            Model::builder()
+                .num_symbols(10)
+                .eof(EOFKind::EndAddOne)
+                .build()
+        };
+
+        let mut encoder = ArithmeticEncoder::new(30);
+        let mut decoder = ArithmeticDecoder::new(30);
+        for message in &messages {
+            let mut source_model = build();
+            let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+            for &symbol in message.iter().chain(&[10]) {
+                encoder
+                    .encode(symbol, &source_model, &mut out_writer)
+                    .unwrap();
+                source_model.update_symbol(symbol);
+            }
+            encoder.finish_encode(&mut out_writer).unwrap();
+            out_writer.pad_to_byte().unwrap();
+            encoder.reset();
+
+            let mut source_model = build();
+            let compressed = out_writer.get_ref().get_ref().clone();
+            let mut in_reader: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+            let mut decoded = vec![];
+            while !decoder.finished() {
+                let sym = decoder.decode(&source_model, &mut in_reader).unwrap();
+                source_model.update_symbol(sym);
+                decoded.push(sym);
+            }
+            decoded.pop();
+            assert_eq!(&decoded, message);
+            decoder.reset();
+        }
+    }
+
+    #[test]
+    fn use_after_finish() {
+        let source_model = Model::builder().num_symbols(4).eof(EOFKind::End).build();
+        let mut encoder = ArithmeticEncoder::new(30);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &[1, 2, 3] {
+            encoder
+                .encode(symbol, &source_model, &mut out_writer)
+                .unwrap();
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        let error = encoder
+            .encode(0, &source_model, &mut out_writer)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(encoder.finish_encode(&mut out_writer).is_err());
+        out_writer.pad_to_byte().unwrap();
+
+        let compressed = out_writer.get_ref().get_ref().clone();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        let mut decoder = ArithmeticDecoder::new(30);
+        for _ in 0..3 {
+            decoder.decode(&source_model, &mut in_reader).unwrap();
+        }
+        assert!(decoder.finished());
+        let error = decoder.decode(&source_model, &mut in_reader).unwrap_err();
+        let error = error
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<DecodeError>()
+            .unwrap();
+        assert_eq!(error.failure, DecodeFailure::Finished);
+        assert!(decoder.decode_bit(1 << 8, &mut in_reader).is_err());
+
+        encoder.reset();
+        encoder.encode(0, &source_model, &mut out_writer).unwrap();
+    }
+
+    #[test]
+    fn bits_written() {
+        let mut source_model = Model::builder().num_bits(8).build();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        let mut previous = 0;
+        for &byte in include_str!("../tests/sherlock.rs").as_bytes() {
+            encoder
+                .encode(byte.into(), &source_model, &mut out_writer)
+                .unwrap();
+            source_model.update_symbol(byte.into());
+            let length = encoder.bits_written() + u64::from(encoder.pending_bits());
+            assert!(length >= previous);
+            previous = length;
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        assert_eq!(encoder.pending_bits(), 0);
+        let bits = encoder.bits_written();
+        out_writer.pad_to_byte().unwrap();
+        assert_eq!((bits + 7) / 8, out_writer.get_ref().get_ref().len() as u64);
+        encoder.reset();
+        assert_eq!(encoder.bits_written(), 0);
+    }
+
+    #[test]
+    fn decode_with_limit() {
+        // no EOF is ever decoded from this input
+        let model = Model::builder().num_symbols(4).build();
+        let input = vec![0xA5; 64];
+        let limit_error =
+            |err: std::io::Error| match &err.get_ref()?.downcast_ref::<DecodeError>()?.failure {
+                DecodeFailure::Limit(limit) => Some(limit.clone()),
+                _ => None,
+            };
+
+        let limits = DecodeLimits {
+            max_symbols: 10,
+            max_bytes: 1 << 20,
+        };
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(input.clone()));
+        let mut decoder = ArithmeticDecoder::new(30);
+        for _ in 0..10 {
+            decoder
+                .decode_with_limit(&model, &mut in_reader, &limits)
+                .unwrap();
+        }
+        let err = decoder
+            .decode_with_limit(&model, &mut in_reader, &limits)
+            .unwrap_err();
+        assert_eq!(limit_error(err), Some(LimitError::Symbols { limit: 10 }));
+        assert_eq!(decoder.symbols_decoded(), 10);
+
+        let limits = DecodeLimits {
+            max_symbols: u64::MAX,
+            max_bytes: 8,
+        };
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(input));
+        let mut decoder = ArithmeticDecoder::new(30);
+        let err = loop {
+            if let Err(err) = decoder.decode_with_limit(&model, &mut in_reader, &limits) {
+                break err;
+            }
+        };
+        assert_eq!(limit_error(err), Some(LimitError::Bytes { limit: 8 }));
+        assert_eq!(decoder.bits_read(), 64);
+    }
+
+    #[test]
+    fn set_limits() {
+        // no EOF is ever decoded from this input
+        let model = Model::builder().num_symbols(4).build();
+        let input = vec![0xA5; 64];
+        let mut decoder = ArithmeticDecoder::new(30);
+        decoder.set_limits(DecodeLimits {
+            max_symbols: 10,
+            ..DecodeLimits::default()
+        });
+        for _ in 0..2 {
+            let mut in_reader: BitReader<_, MSB> = BitReader::new(input.as_slice());
+            for _ in 0..10 {
+                decoder.decode(&model, &mut in_reader).unwrap();
+            }
+            let err = decoder.decode(&model, &mut in_reader).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().contains("limit of 10"));
+            // kept for the next stream
+            decoder.reset();
+        }
+
+        decoder.set_limits(DecodeLimits {
+            max_symbols: u64::MAX,
+            max_bytes: 2,
+        });
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input.as_slice());
+        let limits = DecodeLimits {
+            max_symbols: 1000,
+            max_bytes: 8,
+        };
+        // the tighter of the two applies
+        while decoder
+            .decode_with_limit(&model, &mut in_reader, &limits)
+            .is_ok()
+        {}
+        assert_eq!(decoder.bits_read(), 16);
+    }
+
+    #[test]
+    fn explicit_bit_probabilities() {
+        // a probability state machine of the kind codecs keep themselves
+        fn adapt(probability_zero: &mut u32, bit: bool) {
+            if bit {
+                *probability_zero -= *probability_zero >> 4;
+            } else {
+                *probability_zero += (PROBABILITY_ONE - *probability_zero) >> 4;
+            }
+        }
+
+        let bits: Vec<bool> = (0..2000u32).map(|i| (i * 7919) % 23 == 0).collect();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        let mut probability_zero = PROBABILITY_ONE / 2;
+        for &bit in &bits {
+            encoder
+                .encode_bit(bit, probability_zero, &mut out_writer)
+                .unwrap();
+            adapt(&mut probability_zero, bit);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref().clone();
+        assert!(encoded.len() < bits.len() / 8 / 2);
+
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(encoded));
+        let mut decoder = ArithmeticDecoder::new(32);
+        let mut probability_zero = PROBABILITY_ONE / 2;
+        for &bit in &bits {
+            assert_eq!(
+                decoder
+                    .decode_bit(probability_zero, &mut in_reader)
+                    .unwrap(),
+                bit
+            );
+            adapt(&mut probability_zero, bit);
+        }
+
+        for &probability_zero in &[0, PROBABILITY_ONE] {
+            assert!(encoder
+                .encode_bit(true, probability_zero, &mut out_writer)
+                .is_err());
+        }
+        let mut encoder = ArithmeticEncoder::new(17);
+        assert!(encoder.encode_bit(true, 1, &mut out_writer).is_err());
+    }
+
+    #[test]
+    fn explicit_intervals() {
+        let symbols: Vec<u32> = include_str!("../tests/sherlock.rs")
+            .bytes()
+            .map(u32::from)
+            .collect();
+        let new_model = || Model::builder().num_bits(8).build();
+
+        // the same stream as coding with the model itself
+        let mut source_model = new_model();
+        let mut encoder = ArithmeticEncoder::new(40);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        let mut reference = ArithmeticEncoder::new(40);
+        let mut reference_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &symbols {
+            let (low, high, total) = source_model.probability_counts(symbol);
+            encoder
+                .encode_interval(low, high, total, &mut out_writer)
+                .unwrap();
+            reference
+                .encode(symbol, &source_model, &mut reference_writer)
+                .unwrap();
+            source_model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        reference.finish_encode(&mut reference_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        reference_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref().clone();
+        assert_eq!(&encoded, reference_writer.get_ref().get_ref());
+
+        let mut source_model = new_model();
+        let mut decoder = ArithmeticDecoder::new(40);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        for &symbol in &symbols {
+            let total = source_model.total_count();
+            let target = decoder.decode_target(total, &mut in_reader).unwrap();
+            let decoded = source_model.symbol_from_cumulative(target).unwrap();
+            assert_eq!(decoded, symbol);
+            let (low, high) = source_model.cumulative(decoded);
+            decoder
+                .decode_interval(low, high, total, &mut in_reader)
+                .unwrap();
+            source_model.update_symbol(decoded);
+        }
+
+        // an interval that does not hold the target
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded.as_slice());
+        let mut decoder = ArithmeticDecoder::new(40);
+        let target = decoder.decode_target(256, &mut in_reader).unwrap();
+        let other = (target + 128) % 256;
+        assert!(decoder
+            .decode_interval(other, other + 1, 256, &mut in_reader)
+            .is_err());
+
+        // a total too large for a precision of 30
+        let mut encoder = ArithmeticEncoder::new(30);
+        for &(low, high, total) in &[(3, 3, 8), (5, 9, 8), (0, 1, 0), (0, 1, u32::MAX)] {
+            let error = encoder
+                .encode_interval(low, high, total, &mut out_writer)
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn decode_error() {
+        let model = Model::builder().num_symbols(4).eof(EOFKind::End).build();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(vec![0xA5; 4]));
+        let mut decoder = ArithmeticDecoder::new(30);
+        let err = loop {
+            if let Err(err) = decoder.decode(&model, &mut in_reader) {
+                break err;
+            }
+        };
+
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<DecodeError>()
+            .unwrap();
+        assert_eq!(
+            err,
+            &DecodeError {
+                failure: DecodeFailure::MissingEof,
+                bit_offset: 32,
+                symbols_decoded: decoder.symbols_decoded(),
+            }
+        );
+        assert!(err.symbols_decoded > 0);
+    }
+
+    #[test]
+    fn decode_with_info() {
+        let input = Cursor::new(vec![184, 96, 208]);
+        let mut source_model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+        let mut decoder = ArithmeticDecoder::new(30);
+        let mut infos = vec![];
+        while !decoder.finished() {
+            let info = decoder
+                .decode_with_info(&source_model, &mut in_reader)
+                .unwrap();
+            source_model.update_symbol(info.symbol);
+            infos.push(info);
+        }
+
+        let symbols: Vec<u32> = infos.iter().map(|info| info.symbol).collect();
+        assert_eq!(symbols, [7, 2, 2, 2, 7, 9]);
+        assert_eq!(
+            infos[0],
+            DecodeInfo {
+                symbol: 7,
+                probability: (0.7, 0.8),
+                bits: 3,
+                width: 107_374_183,
+                range_width: 1 << 30,
+                after_resync: false,
+            }
+        );
+        assert!((infos[0].confidence() - 0.1).abs() < 1e-6);
+        assert_eq!(infos[1].probability, (2.0 / 11.0, 3.0 / 11.0));
+        // plus 2 bits from finish_encode, padded to the 3 byte input
+        let total: u64 = infos.iter().map(|info| info.bits).sum();
+        assert_eq!((total + 2 + 7) / 8, 3);
+    }
+
+    #[test]
+    fn untrusted_input() {
+        // xorshift, so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let limits = DecodeLimits {
+            max_symbols: 200,
+            max_bytes: 64,
+        };
+        for _ in 0..200 {
+            let bytes: Vec<u8> = (0..next() % 40).map(|_| next() as u8).collect();
+            let counts: Vec<u32> = (0..1 + next() % 20).map(|_| (next() % 5) as u32).collect();
+            let num_symbols = counts.len() as u64;
+            let source_model = match Model::builder().counts(counts).try_build() {
+                Ok(model) => model,
+                Err(_) => continue,
+            };
+            let exclusions: Vec<u32> = (0..next() % 3)
+                .map(|_| (next() % (num_symbols + 2)) as u32)
+                .collect();
+
+            let mut decoder = ArithmeticDecoder::new(16 + next() % 40);
+            let mut input: BitReader<_, MSB> = BitReader::new(bytes.as_slice());
+            for _ in 0..300 {
+                let result = match next() % 3 {
+                    0 => decoder.decode_with_limit(&source_model, &mut input, &limits),
+                    1 => decoder.decode_excluding(&source_model, &exclusions, &mut input),
+                    _ => decoder
+                        .decode_bit((next() % u64::from(PROBABILITY_ONE)) as u32, &mut input)
+                        .map(u32::from),
+                };
+                if result.is_err() || decoder.finished() {
+                    break;
+                }
+            }
+
+            let mut state = decoder.checkpoint().to_bytes();
+            let byte = 1 + (next() as usize) % (state.len() - 1);
+            state[byte] = next() as u8;
+            if let Ok(state) = crate::state::CoderState::from_bytes(&state) {
+                if let Ok(mut decoder) = ArithmeticDecoder::restore(&state) {
+                    let mut input: BitReader<_, MSB> = BitReader::new(bytes.as_slice());
+                    let _ = decoder.decode(&source_model, &mut input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn restore_corrupted_state() {
+        let model = Model::builder().num_symbols(9).eof(EOFKind::End).build();
+        let mut encoder = ArithmeticEncoder::new(24);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for segment in 0..4u32 {
+            for symbol in (0..40).map(|i| (i * segment + i / 3) % 8).chain(Some(8)) {
+                encoder.encode(symbol, &model, &mut output).unwrap();
+            }
+            if segment == 1 {
+                encoder.finish_section(&mut output, 30).unwrap();
+            } else {
+                encoder.finish_segment(&mut output).unwrap();
+            }
+        }
+        let compressed = output.get_ref().get_ref().clone();
+
+        // every state the decoder goes through, saved
+        let mut states = vec![];
+        let mut decoder = ArithmeticDecoder::new(24);
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for segment in 0..4 {
+            states.push(decoder.checkpoint().to_bytes());
+            while !decoder.finished() {
+                decoder.decode(&model, &mut input).unwrap();
+                states.push(decoder.checkpoint().to_bytes());
+            }
+            if segment == 1 {
+                decoder.continue_with_next_section(&mut input).unwrap();
+            } else if segment < 3 {
+                decoder.continue_with_next_segment(&mut input).unwrap();
+            }
+        }
+
+        let word = |state: &mut [u8], index: usize, value: u64| {
+            state[1 + index * 8..][..8].copy_from_slice(&value.to_le_bytes());
+        };
+        // reading far past the window of a filled decoder
+        let mut state = states[5].clone();
+        word(&mut state, super::BITS_READ_WORD, 1000);
+        let state = crate::state::CoderState::from_bytes(&state).unwrap();
+        assert!(ArithmeticDecoder::restore(&state).is_err());
+
+        // xorshift, so failures reproduce
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..5000 {
+            let mut state = states[next() as usize % states.len()].clone();
+            for _ in 0..1 + next() % 3 {
+                let index = next() as usize % super::STATE_WORDS;
+                match next() % 3 {
+                    0 => state[1 + index * 8 + next() as usize % 8] = next() as u8,
+                    1 => word(&mut state, index, next() % 2048),
+                    _ => word(&mut state, index, next()),
+                }
+            }
+            let state = crate::state::CoderState::from_bytes(&state).unwrap();
+            let mut decoder = match ArithmeticDecoder::restore(&state) {
+                Ok(decoder) => decoder,
+                Err(_) => continue,
+            };
+            // whatever was accepted must fail cleanly instead of panicking
+            let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+            for _ in 0..next() % 50 {
+                if decoder.decode(&model, &mut input).is_err() {
+                    break;
+                }
+            }
+            let _ = match next() % 2 {
+                0 => decoder.continue_with_next_segment(&mut input),
+                _ => decoder.continue_with_next_section(&mut input),
+            };
+            let _ = decoder.decode(&model, &mut input);
+        }
+    }
 }
@@ -0,0 +1,188 @@
+//! A coder that codes symbols two at a time as one symbol of a pair model.
+//!
+//! Every pair takes one model lookup and one renormalization instead of
+//! two, which speeds up high-throughput byte streams, and the pair model
+//! also captures how likely each symbol is to follow the one before it.
+//! A stream of odd length ends with an escape and the last symbol coded on
+//! its own.
+
+use std::io::{Error, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+/// The largest alphabet a [`DigramCoder`] takes, whose pair model has
+/// `MAX_DIGRAM_SYMBOLS^2` symbols.
+pub const MAX_DIGRAM_SYMBOLS: u32 = 4096;
+
+/// Codes symbols of an alphabet of `num_symbols` in pairs with an adaptive
+/// model of `num_symbols^2` pairs, plus an escape for a final unpaired
+/// symbol and an EOF. Encoder and decoder must be created with the same
+/// `num_symbols`.
+///
+/// Every pair starts with a count of 1, so for bytes the model needs tens of
+/// thousands of pairs before it predicts as well as an order-0 byte model.
+pub struct DigramCoder {
+    pairs: Model,
+    /// The last symbol of an odd-length stream, uniformly.
+    singles: Model,
+    num_symbols: u32,
+    /// The encoder's unpaired first symbol or the decoder's second symbol
+    /// not yet returned.
+    held: Option<u32>,
+    /// The decoder has reached the end of the stream.
+    ended: bool,
+}
+
+impl DigramCoder {
+    pub fn new(num_symbols: u32) -> Self {
+        assert!(
+            (1..=MAX_DIGRAM_SYMBOLS).contains(&num_symbols),
+            "num_symbols must be between 1 and MAX_DIGRAM_SYMBOLS"
+        );
+
+        Self {
+            pairs: Model::builder()
+                .num_symbols(num_symbols * num_symbols + 1)
+                .eof(EOFKind::EndAddOne)
+                .build(),
+            singles: Model::builder().num_symbols(num_symbols).build(),
+            num_symbols,
+            held: None,
+            ended: false,
+        }
+    }
+
+    /// The symbol of the pair model that escapes to a single symbol.
+    const fn escape(&self) -> u32 {
+        self.num_symbols * self.num_symbols
+    }
+
+    /// Encodes `symbol`, which is written once the next one arrives or the
+    /// stream ends.
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        symbol: u32,
+    ) -> Result<(), Error> {
+        self.singles.check_symbol(symbol)?;
+        match self.held.take() {
+            None => self.held = Some(symbol),
+            Some(first) => {
+                let pair = first * self.num_symbols + symbol;
+                encoder.encode(pair, &self.pairs, output)?;
+                self.pairs.update_symbol(pair);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the end of the stream: the EOF, or for an odd length the
+    /// escape and the held symbol. Call this before [`finish_encode`](ArithmeticEncoder::finish_encode).
+    pub fn encode_eof<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        match self.held.take() {
+            Some(last) => {
+                encoder.encode(self.escape(), &self.pairs, output)?;
+                encoder.encode(last, &self.singles, output)
+            }
+            None => encoder.encode(self.pairs.eof(), &self.pairs, output),
+        }
+    }
+
+    /// Decodes the next symbol, or `None` once the stream has ended.
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<Option<u32>, Error> {
+        if let Some(second) = self.held.take() {
+            return Ok(Some(second));
+        }
+        if self.ended {
+            return Ok(None);
+        }
+
+        let pair = decoder.decode(&self.pairs, input)?;
+        if pair == self.pairs.eof() {
+            self.ended = true;
+            return Ok(None);
+        }
+        if pair == self.escape() {
+            self.ended = true;
+            return decoder.decode(&self.singles, input).map(Some);
+        }
+        self.pairs.update_symbol(pair);
+        self.held = Some(pair % self.num_symbols);
+        Ok(Some(pair / self.num_symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::DigramCoder;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder};
+
+    fn roundtrip(symbols: &[u32], num_symbols: u32) -> usize {
+        let mut coder = DigramCoder::new(num_symbols);
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in symbols {
+            coder.encode(&mut encoder, &mut out_writer, symbol).unwrap();
+        }
+        coder.encode_eof(&mut encoder, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref().clone();
+
+        let mut coder = DigramCoder::new(num_symbols);
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(&encoded));
+        let mut decoded = vec![];
+        while let Some(symbol) = coder.decode(&mut decoder, &mut in_reader).unwrap() {
+            decoded.push(symbol);
+        }
+        assert_eq!(decoded, symbols);
+        encoded.len()
+    }
+
+    #[test]
+    fn e2e() {
+        let sherlock: Vec<u32> = include_str!("../tests/sherlock.rs")
+            .bytes()
+            .map(u32::from)
+            .collect();
+        // even and odd lengths
+        for end in &[sherlock.len() & !1, (sherlock.len() - 1) | 1] {
+            roundtrip(&sherlock[..*end], 256);
+        }
+        let repeated: Vec<u32> = b"abcd"
+            .iter()
+            .cycle()
+            .take(20_000)
+            .map(|&b| b.into())
+            .collect();
+        // under half the bits of the bytes
+        assert!(roundtrip(&repeated, 256) < repeated.len() / 2);
+        roundtrip(&[], 256);
+        roundtrip(&[7], 256);
+        roundtrip(&[0, 2, 1, 1, 2], 3);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut coder = DigramCoder::new(3);
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        assert!(coder.encode(&mut encoder, &mut out_writer, 3).is_err());
+    }
+}
@@ -1,16 +1,33 @@
 //! This module contains the main code for the encoder. It also
 //! contains an simple implementation of a binary encoder.
 
-use std::io::{Error, Write};
+use std::io::{Error, ErrorKind, Write};
 
 use bitbit::BitWriter;
 
-use crate::{Model, Range};
+use crate::{
+    binary::PROBABILITY_ONE,
+    cost::{self, RateTracker, SymbolCost},
+    error::{EmptyIntervalError, FinishedError, ModelError, PrecisionError},
+    model::LargeModel,
+    precision::{check_compatible, recommended_precision, Rescale},
+    range::check_precision,
+    state::{self, CoderState},
+    Model, Range, SourceModel,
+};
+
+/// Number of registers in an encoder's [`CoderState`].
+pub(crate) const STATE_WORDS: usize = 6;
 
 pub struct ArithmeticEncoder {
     _precision: u64,
     pending_bit_count: u32,
+    bits_written: u64,
+    finished: bool,
     range: Range,
+    context: usize,
+    rate: Option<RateTracker>,
+    cost_log: Option<Vec<SymbolCost>>,
 }
 
 impl ArithmeticEncoder {
@@ -22,17 +39,215 @@ impl ArithmeticEncoder {
         Self {
             _precision: precision,
             pending_bit_count: 0,
+            bits_written: 0,
+            finished: false,
             range: Range::new(precision),
+            context: 0,
+            rate: None,
+            cost_log: None,
         }
     }
 
-    pub fn encode<T: Write>(
+    /// Like [`new`](Self::new) but returns an error for a precision the
+    /// coder can not use instead of panicking.
+    pub fn try_new(precision: u64) -> Result<Self, PrecisionError> {
+        check_precision(precision)?;
+        Ok(Self::new(precision))
+    }
+
+    /// Like [`try_new`](Self::try_new) but also errors if the precision is
+    /// too low for `source_model`, see
+    /// [`check_compatible`](crate::precision::check_compatible).
+    pub fn for_model<M: SourceModel + ?Sized>(
+        precision: u64,
+        source_model: &M,
+    ) -> Result<Self, PrecisionError> {
+        check_compatible(precision, source_model)?;
+        Ok(Self::new(precision))
+    }
+
+    /// A coder with a precision that fits `source_model` however far it
+    /// grows, as its counts are `u32`s. The precision depends only on the
+    /// model, so encoder and decoder agree when built from the same one.
+    pub fn with_model<M: SourceModel + ?Sized>(source_model: &M) -> Self {
+        Self::with_expected_len(source_model, u32::MAX.into())
+    }
+
+    /// Like [`with_model`](Self::with_model) but for a stream that updates
+    /// `source_model` at most `expected_len` times, which allows a lower
+    /// and so faster precision. See
+    /// [`recommended_precision`](crate::recommended_precision).
+    pub fn with_expected_len<M: SourceModel + ?Sized>(source_model: &M, expected_len: u64) -> Self {
+        Self::new(recommended_precision(
+            source_model.total_count(),
+            expected_len,
+            Rescale::Never,
+        ))
+    }
+
+    /// Starts accumulating the exact (fixed-point) bits spent on every
+    /// encoded symbol, attributed to the context set with
+    /// [`set_context`](Self::set_context).
+    pub fn enable_rate_tracking(&mut self) {
+        self.rate.get_or_insert_with(RateTracker::new);
+    }
+
+    /// Starts recording the symbol, context and exact fixed-point cost of
+    /// every encoded symbol, in order.
+    pub fn enable_cost_log(&mut self) {
+        self.cost_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Sets the context (usually the index of the model being used) that
+    /// subsequently encoded symbols are attributed to in the rate tracker
+    /// and cost log.
+    pub fn set_context(&mut self, context: usize) {
+        self.context = context;
+        if let Some(rate) = &mut self.rate {
+            rate.set_context(context);
+        }
+    }
+
+    pub const fn rate_tracker(&self) -> Option<&RateTracker> {
+        self.rate.as_ref()
+    }
+
+    pub fn cost_log(&self) -> Option<&[SymbolCost]> {
+        self.cost_log.as_deref()
+    }
+
+    /// Returns the recorded costs and starts a new, empty log.
+    pub fn take_cost_log(&mut self) -> Option<Vec<SymbolCost>> {
+        self.cost_log.as_mut().map(std::mem::take)
+    }
+
+    /// Encodes `symbol` with any [`SourceModel`], such as a [`Model`] or a
+    /// [`ByteModel`](crate::ByteModel).
+    pub fn encode<T: Write, M: SourceModel + ?Sized>(
         &mut self,
         symbol: u32,
-        source_model: &Model,
+        source_model: &M,
         output: &mut BitWriter<T>,
     ) -> Result<(), Error> {
+        check_headroom(&self.range, source_model)?;
+        source_model.check_codable(symbol)?;
         let low_high = self.range.calculate_range(symbol, source_model);
+        self.encode_range(symbol, low_high, output)
+    }
+
+    /// Encodes a binary decision with an explicit probability instead of a
+    /// model, for callers that keep their own probability state.
+    /// `probability_zero` is the probability of `false` in units of
+    /// `1 / 2^PROBABILITY_BITS` (see
+    /// [`PROBABILITY_BITS`](crate::binary::PROBABILITY_BITS)) and must be in
+    /// `[1, 2^PROBABILITY_BITS)` so both outcomes stay codable. Needs a
+    /// precision of at least `PROBABILITY_BITS + 2`.
+    pub fn encode_bit<T: Write>(
+        &mut self,
+        bit: bool,
+        probability_zero: u32,
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        check_bit_probability(&self.range, probability_zero)?;
+        let low_high = bit_interval(&self.range, bit, probability_zero);
+        self.encode_range(u32::from(bit), low_high, output)
+    }
+
+    /// Encodes the interval `[low, high)` of cumulative counts out of
+    /// `total`, for probabilities computed outside of any [`SourceModel`],
+    /// such as by a custom predictor. The decoder must use
+    /// [`ArithmeticDecoder::decode_target`](crate::ArithmeticDecoder::decode_target)
+    /// and [`ArithmeticDecoder::decode_interval`](crate::ArithmeticDecoder::decode_interval)
+    /// with the same intervals. `total` must be at most a quarter of
+    /// `2^precision`. The rate and cost log record `low` as the symbol.
+    pub fn encode_interval<T: Write>(
+        &mut self,
+        low: u32,
+        high: u32,
+        total: u32,
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        check_interval(&self.range, low, high, total)?;
+        let low_high = self
+            .range
+            .interval_counts(low.into(), high.into(), total.into());
+        self.encode_range(low, low_high, output)
+    }
+
+    /// Encodes `symbol` with a [`LargeModel`], whose total may exceed
+    /// `u32::MAX`.
+    pub fn encode_large<T: Write>(
+        &mut self,
+        symbol: u32,
+        source_model: &LargeModel,
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        check_large_headroom(&self.range, source_model)?;
+        source_model.check_codable(symbol)?;
+        let (low, high) = source_model.cumulative(symbol);
+        let low_high = self
+            .range
+            .interval_counts(low, high, source_model.total_count());
+        self.encode_range(symbol, low_high, output)
+    }
+
+    /// Encodes `symbol` as if the symbols in `exclusions` had a count of 0,
+    /// without modifying `source_model`. The decoder must use
+    /// [`ArithmeticDecoder::decode_excluding`](crate::ArithmeticDecoder::decode_excluding)
+    /// with the same exclusions. `exclusions` must be sorted in increasing
+    /// order without duplicates, which is checked.
+    pub fn encode_excluding<T: Write>(
+        &mut self,
+        symbol: u32,
+        exclusions: &[u32],
+        source_model: &Model,
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        if exclusions.contains(&symbol) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "can not encode an excluded symbol",
+            ));
+        }
+
+        check_headroom(&self.range, source_model)?;
+        source_model.check_codable(symbol)?;
+        check_exclusions(exclusions, source_model)?;
+        let low_high = self
+            .range
+            .calculate_range_excluding(symbol, exclusions, source_model);
+        self.encode_range(symbol, low_high, output)
+    }
+
+    fn encode_range<T: Write>(
+        &mut self,
+        symbol: u32,
+        low_high: (u64, u64),
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        if self.finished {
+            return Err(FinishedError.into());
+        }
+        if low_high.0 >= low_high.1 {
+            return Err(EmptyIntervalError {
+                symbol,
+                range_width: self.range.width(),
+            }
+            .into());
+        }
+        if self.rate.is_some() || self.cost_log.is_some() {
+            let cost = cost::cost(low_high.1 - low_high.0, self.range.width());
+            if let Some(rate) = &mut self.rate {
+                rate.record(cost);
+            }
+            if let Some(log) = &mut self.cost_log {
+                log.push(SymbolCost {
+                    symbol,
+                    context: self.context,
+                    cost,
+                });
+            }
+        }
         self.range.update_range(low_high);
 
         while self.range.in_bottom_half() || self.range.in_upper_half() {
@@ -53,8 +268,95 @@ impl ArithmeticEncoder {
         Ok(())
     }
 
+    /// Fixed-point number of bits (see [`cost`](crate::cost)) that encoding
+    /// `symbol` would take given the current range. Nothing is mutated so
+    /// this can be used to compare candidate symbols before committing to
+    /// one. Fails if `symbol` is out of range for `source_model`.
+    pub fn cost_of<M: SourceModel + ?Sized>(
+        &self,
+        symbol: u32,
+        source_model: &M,
+    ) -> Result<u32, ModelError> {
+        source_model.check_symbol(symbol)?;
+        let (low, high) = self.range.calculate_range(symbol, source_model);
+        Ok(cost::cost(high - low, self.range.width()))
+    }
+
+    /// Total fixed-point cost of each candidate symbol sequence, starting
+    /// from the current range and `source_model`. The model is updated with
+    /// [`Model::update_symbol`] after every symbol, exactly as an adaptive
+    /// encoding loop would. Neither the encoder nor `source_model` are
+    /// modified. Fails before costing anything if a symbol is out of range,
+    /// and like [`encode`](Self::encode) if a symbol has a count of 0 or the
+    /// model outgrows the precision by the time it would be coded.
+    ///
+    /// Candidates are evaluated in sorted order so the virtual coder state
+    /// reached after a shared prefix is computed only once. A single copy
+    /// of the model follows along, taking back the updates past the shared
+    /// prefix, or replaying the prefix if its updates can not be undone.
+    pub fn candidate_costs(
+        &self,
+        source_model: &Model,
+        candidates: &[&[u32]],
+    ) -> Result<Vec<u64>, Error> {
+        for &symbol in candidates.iter().copied().flatten() {
+            source_model.check_symbol(symbol)?;
+        }
+
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by(|&a, &b| candidates[a].cmp(candidates[b]));
+
+        let mut costs = vec![0; candidates.len()];
+        // stack[i] is the range and cost after the first i symbols of
+        // `previous`, all of which `model` has been updated with
+        let mut stack = vec![(self.range.clone(), 0u64)];
+        let mut model = source_model.clone();
+        let mut previous: &[u32] = &[];
+
+        for index in order {
+            let candidate = candidates[index];
+            let shared = previous
+                .iter()
+                .zip(candidate)
+                .take_while(|(a, b)| a == b)
+                .count();
+            stack.truncate(shared + 1);
+            if model.is_revertible() {
+                for &symbol in previous[shared..].iter().rev() {
+                    model.revert_symbol(symbol);
+                }
+            } else if shared < previous.len() {
+                model = source_model.clone();
+                for &symbol in &candidate[..shared] {
+                    model.update_symbol(symbol);
+                }
+            }
+
+            for &symbol in &candidate[shared..] {
+                let (range, total) = &stack[stack.len() - 1];
+                let mut range = range.clone();
+
+                check_headroom(&range, &model)?;
+                model.check_codable(symbol)?;
+                let low_high = range.calculate_range(symbol, &model);
+                let total = total + u64::from(cost::cost(low_high.1 - low_high.0, range.width()));
+                range.update_range(low_high);
+                renormalize(&mut range);
+                model.update_symbol(symbol);
+
+                stack.push((range, total));
+            }
+
+            costs[index] = stack[stack.len() - 1].1;
+            previous = candidate;
+        }
+
+        Ok(costs)
+    }
+
     fn emit<T: Write>(&mut self, bit: bool, output: &mut BitWriter<T>) -> Result<(), Error> {
         output.write_bit(bit)?;
+        self.bits_written += 1 + u64::from(self.pending_bit_count);
 
         while self.pending_bit_count > 0 {
             output.write_bit(!bit)?;
@@ -64,7 +366,13 @@ impl ArithmeticEncoder {
         Ok(())
     }
 
+    /// Writes the bits that settle the last symbol. Afterwards the encoder
+    /// refuses to encode or finish again until it is [`reset`](Self::reset).
     pub fn finish_encode<T: Write>(&mut self, output: &mut BitWriter<T>) -> Result<(), Error> {
+        if self.finished {
+            return Err(FinishedError.into());
+        }
+        self.finished = true;
         self.pending_bit_count += 1;
 
         if self.range.in_bottom_quarter() {
@@ -75,16 +383,227 @@ impl ArithmeticEncoder {
 
         Ok(())
     }
+
+    /// Ends the current segment so another one can be appended: finishes
+    /// the encoding, pads `output` to a byte and starts over as a fresh
+    /// encoder. Decode the next segment after calling
+    /// [`ArithmeticDecoder::continue_with_next_segment`](crate::ArithmeticDecoder::continue_with_next_segment).
+    ///
+    /// Models are not touched; start the next segment with fresh models if
+    /// it should be decodable on its own.
+    pub fn finish_segment<T: Write>(&mut self, output: &mut BitWriter<T>) -> Result<(), Error> {
+        self.finish_encode(output)?;
+        output.pad_to_byte()?;
+        self.pending_bit_count = 0;
+        self.finished = false;
+        self.range = Range::new(self._precision);
+        Ok(())
+    }
+
+    /// Starts over with the initial range and no pending bits, so the next
+    /// symbol begins a new, independent stream. Call it after
+    /// [`finish_encode`](Self::finish_encode) to reuse the encoder for many
+    /// small messages. The precision, context and any rate tracking or cost
+    /// log are kept.
+    pub fn reset(&mut self) {
+        self.pending_bit_count = 0;
+        self.bits_written = 0;
+        self.finished = false;
+        self.range = Range::new(self._precision);
+    }
+
+    /// Number of bits written to the output since the encoder was created or
+    /// [`reset`](Self::reset). Padding and the precision bytes of sections
+    /// are not counted.
+    pub const fn bits_written(&self) -> u64 {
+        self.bits_written
+    }
+
+    /// Number of bits the encoder owes the output but can not write until
+    /// the next bit is settled. Each will take exactly one bit, so
+    /// `bits_written() + pending_bits()` is the length of the output so far.
+    pub const fn pending_bits(&self) -> u32 {
+        self.pending_bit_count
+    }
+
+    /// Saves the range and pending bits so encoding can be resumed later
+    /// with [`restore`](Self::restore), see [`state`](crate::state). The
+    /// context, rate tracker and cost log are not saved.
+    pub fn checkpoint(&self) -> CoderState {
+        CoderState::encoder(vec![
+            self._precision,
+            self.range.low(),
+            self.range.high(),
+            self.pending_bit_count.into(),
+            self.bits_written,
+            self.finished.into(),
+        ])
+    }
+
+    /// An encoder in the state saved by [`checkpoint`](Self::checkpoint).
+    pub fn restore(state: &CoderState) -> Result<Self, Error> {
+        let words = state.encoder_words()?;
+        let (precision, low, high) = (words[0], words[1], words[2]);
+        let range = state::restore_range(precision, low, high)?;
+        let pending_bit_count = u32::try_from(words[3])
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid coder state"))?;
+        Ok(Self {
+            pending_bit_count,
+            bits_written: words[4],
+            finished: words[5] != 0,
+            range,
+            ..Self::new(precision)
+        })
+    }
+
+    /// Like [`finish_segment`](Self::finish_segment) but the next segment
+    /// (a section) is encoded with `precision`, which is written in front
+    /// of it as a byte. Decode the next section after calling
+    /// [`ArithmeticDecoder::continue_with_next_section`](crate::ArithmeticDecoder::continue_with_next_section).
+    ///
+    /// This lets a stream mix, say, a high precision header section with a
+    /// bulk section whose models need less.
+    pub fn finish_section<T: Write>(
+        &mut self,
+        output: &mut BitWriter<T>,
+        precision: u64,
+    ) -> Result<(), Error> {
+        check_precision(precision)?;
+        self.finish_segment(output)?;
+        output.write_byte(precision as u8)?;
+        self._precision = precision;
+        self.range = Range::new(precision);
+        Ok(())
+    }
+}
+
+pub(crate) fn check_bit_probability(range: &Range, probability_zero: u32) -> Result<(), Error> {
+    if probability_zero == 0 || probability_zero >= PROBABILITY_ONE {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "probability {} is outside of [1, {})",
+                probability_zero, PROBABILITY_ONE
+            ),
+        ))
+    } else if !range.can_represent(PROBABILITY_ONE.into()) {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the precision is too low for coding bits with explicit probabilities",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The interval of `bit` when `false` takes up `probability_zero` out of
+/// [`PROBABILITY_ONE`].
+pub(crate) fn bit_interval(range: &Range, bit: bool, probability_zero: u32) -> (u64, u64) {
+    let one = PROBABILITY_ONE.into();
+    if bit {
+        range.interval_counts(probability_zero.into(), one, one)
+    } else {
+        range.interval_counts(0, probability_zero.into(), one)
+    }
+}
+
+/// Errors unless `exclusions` are symbols of `source_model` in strictly
+/// increasing order that leave some count, so excluding them can not
+/// underflow the total. Sorted input is checked without allocating.
+pub(crate) fn check_exclusions(exclusions: &[u32], source_model: &Model) -> Result<(), Error> {
+    let mut excluded = 0u64;
+    for (index, &symbol) in exclusions.iter().enumerate() {
+        source_model.check_symbol(symbol)?;
+        if index > 0 && exclusions[index - 1] >= symbol {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "exclusions must be sorted without duplicates, but {} follows {}",
+                    symbol,
+                    exclusions[index - 1]
+                ),
+            ));
+        }
+        excluded += u64::from(source_model.counts()[symbol as usize]);
+    }
+    if excluded >= u64::from(source_model.total_count()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "every symbol with a count is excluded",
+        ));
+    }
+    Ok(())
+}
+
+/// Errors if `source_model` has grown too large for the precision of
+/// `range`, which would make symbols indistinguishable.
+pub(crate) fn check_headroom<M: SourceModel + ?Sized>(
+    range: &Range,
+    source_model: &M,
+) -> Result<(), Error> {
+    check_total(range, source_model.total_count().into())
+}
+
+/// [`check_headroom`] for a [`LargeModel`].
+pub(crate) fn check_large_headroom(range: &Range, source_model: &LargeModel) -> Result<(), Error> {
+    check_total(range, source_model.total_count())
+}
+
+fn check_total(range: &Range, total: u64) -> Result<(), Error> {
+    if range.can_represent(total) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the model total count exceeds what the precision can represent",
+        ))
+    }
+}
+
+/// Errors unless `[low, high)` is a non-empty interval of counts out of
+/// `total` that the precision of `range` can represent.
+pub(crate) fn check_interval(range: &Range, low: u32, high: u32, total: u32) -> Result<(), Error> {
+    if low >= high || high > total {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "[{}, {}) is not a non-empty interval of a total of {}",
+                low, high, total
+            ),
+        ));
+    }
+    check_total(range, total.into())
+}
+
+/// Scales `range` the same way [`ArithmeticEncoder::encode`] does, without
+/// producing any output. Returns the number of bits shifted out, counting
+/// pending bits.
+pub(crate) fn renormalize(range: &mut Range) -> u64 {
+    let mut shifted = 0;
+    while range.in_bottom_half() || range.in_upper_half() {
+        if range.in_bottom_half() {
+            range.scale_bottom_half();
+        } else {
+            range.scale_upper_half();
+        }
+        shifted += 1;
+    }
+
+    while range.in_middle_half() {
+        range.scale_middle_half();
+        shifted += 1;
+    }
+    shifted
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::Cursor;
+    use std::io::{Cursor, ErrorKind};
 
     use bitbit::BitWriter;
 
     use super::ArithmeticEncoder;
-    use crate::{EOFKind, Model};
+    use crate::{cost, error::ModelError, EOFKind, Model};
 
     #[test]
     fn e2e() {
@@ -104,4 +623,218 @@ mod test {
         out_writer.pad_to_byte().unwrap();
         assert_eq!(output.get_ref(), &[184, 96, 208]);
     }
+
+    #[test]
+    fn zero_count() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        let model = Model::builder().counts(vec![3, 0, 5]).build();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        encoder.encode(0, &model, &mut out_writer).unwrap();
+        let error = encoder.encode(1, &model, &mut out_writer).unwrap_err();
+        let error = error.get_ref().unwrap();
+        assert_eq!(
+            error.downcast_ref::<ModelError>(),
+            Some(&ModelError::ZeroCount { symbol: 1 })
+        );
+        // the stream is still intact
+        encoder.encode(2, &model, &mut out_writer).unwrap();
+    }
+
+    #[test]
+    fn rate_tracking() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        encoder.enable_rate_tracking();
+        let models = [
+            Model::builder().num_symbols(2).build(),
+            Model::builder().num_symbols(8).build(),
+        ];
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for (context, symbol) in [(0, 1), (1, 5), (0, 0), (1, 7)] {
+            encoder.set_context(context);
+            encoder
+                .encode(symbol, &models[context], &mut out_writer)
+                .unwrap();
+        }
+
+        let rate = encoder.rate_tracker().unwrap();
+        assert_eq!(rate.costs().len(), 2);
+        assert!((rate.context_bits(0) - 2.0).abs() < 1e-3);
+        assert!((rate.context_bits(1) - 6.0).abs() < 1e-3);
+        assert!((rate.total_bits() - 8.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cost_of() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        encoder.enable_rate_tracking();
+        let model = Model::builder().counts(vec![1, 3]).build();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+
+        let costs = [
+            encoder.cost_of(0, &model).unwrap(),
+            encoder.cost_of(1, &model).unwrap(),
+        ];
+        assert_eq!(
+            encoder.cost_of(2, &model),
+            Err(ModelError::SymbolOutOfRange {
+                symbol: 2,
+                num_symbols: 2
+            })
+        );
+        assert!((cost::to_bits(costs[0].into()) - 2.0).abs() < 1e-3);
+        assert!((cost::to_bits(costs[1].into()) - (4f64 / 3f64).log2()).abs() < 1e-3);
+        assert_eq!(encoder.rate_tracker().unwrap().total_cost(), 0);
+
+        encoder.encode(0, &model, &mut out_writer).unwrap();
+        assert_eq!(
+            encoder.rate_tracker().unwrap().total_cost(),
+            u64::from(costs[0])
+        );
+    }
+
+    #[test]
+    fn candidate_costs() {
+        let candidates: [&[u32]; 6] = [
+            &[1, 2, 3],
+            &[1, 2],
+            &[0, 0, 0, 0],
+            &[1, 2, 0],
+            &[],
+            &[1, 2, 0, 0, 3, 3, 3, 3],
+        ];
+        // updates that can be taken back, and a rescale that can not
+        let models = [
+            Model::builder().num_symbols(4).build(),
+            Model::builder().num_symbols(4).rescale_at(10).build(),
+        ];
+        let encoder = ArithmeticEncoder::new(30);
+        for model in &models {
+            let costs = encoder.candidate_costs(model, &candidates).unwrap();
+            assert_eq!(costs.len(), candidates.len());
+            assert_eq!(costs[4], 0);
+
+            for (candidate, &batch_cost) in candidates.iter().zip(&costs) {
+                assert_eq!(
+                    encoder.candidate_costs(model, &[candidate]).unwrap(),
+                    [batch_cost]
+                );
+
+                let mut encoder = ArithmeticEncoder::new(30);
+                encoder.enable_rate_tracking();
+                let mut model = model.clone();
+                let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+                for &symbol in *candidate {
+                    encoder.encode(symbol, &model, &mut out_writer).unwrap();
+                    model.update_symbol(symbol);
+                }
+                assert_eq!(encoder.rate_tracker().unwrap().total_cost(), batch_cost);
+            }
+        }
+
+        let model_error = |candidates: &[&[u32]], model: &Model| {
+            let error = encoder.candidate_costs(model, candidates).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            error
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<ModelError>())
+                .cloned()
+        };
+        assert_eq!(
+            model_error(&[&[1], &[0, 4]], &models[0]),
+            Some(ModelError::SymbolOutOfRange {
+                symbol: 4,
+                num_symbols: 4
+            })
+        );
+        // a symbol with a count of 0 is rejected instead of coded
+        let zero = Model::builder().counts(vec![5, 0, 3]).build();
+        assert_eq!(
+            model_error(&[&[0, 1, 2]], &zero),
+            Some(ModelError::ZeroCount { symbol: 1 })
+        );
+        let error = ArithmeticEncoder::new(4)
+            .candidate_costs(&models[0], &[&[0, 1, 2]])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn encode_excluding_rejects_excluded() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        let model = Model::builder().num_symbols(4).build();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+
+        assert!(encoder
+            .encode_excluding(2, &[1, 2], &model, &mut out_writer)
+            .is_err());
+    }
+
+    #[test]
+    fn encode_excluding_rejects_unsorted() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        let model = Model::builder().num_symbols(4).build();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+
+        for exclusions in [&[3, 1][..], &[1, 1]] {
+            let error = encoder
+                .encode_excluding(2, exclusions, &model, &mut out_writer)
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        assert!(encoder
+            .encode_excluding(2, &[1, 3], &model, &mut out_writer)
+            .is_ok());
+    }
+
+    #[test]
+    fn precision_headroom() {
+        let mut encoder = ArithmeticEncoder::new(10);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+
+        let model = Model::builder().num_symbols(256).build();
+        assert!(encoder.encode(3, &model, &mut out_writer).is_ok());
+        let model = Model::builder().num_symbols(257).build();
+        assert!(encoder.encode(3, &model, &mut out_writer).is_err());
+    }
+
+    #[test]
+    fn cost_log() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        encoder.enable_rate_tracking();
+        encoder.enable_cost_log();
+        let models = [
+            Model::builder().num_symbols(2).build(),
+            Model::builder().num_symbols(8).build(),
+        ];
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for (context, symbol) in [(1, 5), (0, 1), (1, 2)] {
+            encoder.set_context(context);
+            encoder
+                .encode(symbol, &models[context], &mut out_writer)
+                .unwrap();
+        }
+
+        let log = encoder.take_cost_log().unwrap();
+        assert_eq!(
+            log.iter()
+                .map(|entry| (entry.context, entry.symbol))
+                .collect::<Vec<_>>(),
+            [(1, 5), (0, 1), (1, 2)]
+        );
+        let logged: u64 = log.iter().map(|entry| u64::from(entry.cost)).sum();
+        assert_eq!(logged, encoder.rate_tracker().unwrap().total_cost());
+        assert_eq!(encoder.cost_log(), Some(&[][..]));
+    }
+
+    #[test]
+    fn symbol_out_of_range() {
+        let mut encoder = ArithmeticEncoder::new(30);
+        let model = Model::builder().num_symbols(4).build();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+
+        assert!(encoder.encode(4, &model, &mut out_writer).is_err());
+        assert!(encoder
+            .encode_excluding(1, &[9], &model, &mut out_writer)
+            .is_err());
+    }
 }
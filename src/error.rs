@@ -0,0 +1,377 @@
+//! Typed errors. Coder methods return [`std::io::Error`]; these convert into
+//! it (with the typed error as the inner error) so they can be propagated
+//! with `?` and recovered with [`std::io::Error::get_ref`].
+//!
+//! # Strict use
+//! Services handling untrusted data can avoid every panic in the crate by
+//! sticking to the fallible operations:
+//! - [`ArithmeticEncoder::try_new`](crate::ArithmeticEncoder::try_new),
+//!   [`ArithmeticDecoder::try_new`](crate::ArithmeticDecoder::try_new) and
+//!   [`Range::try_new`](crate::Range::try_new) instead of `new`
+//! - [`Builder::try_build`](crate::model::Builder::try_build) and
+//!   [`Builder::try_counts_map`](crate::model::Builder::try_counts_map)
+//! - [`Model::try_update_symbol`](crate::Model::try_update_symbol), which
+//!   also refuses to overflow the total count
+//!
+//! Encoding and decoding already validate symbols, exclusions, model totals
+//! and the input, returning errors rather than panicking: decoding arbitrary
+//! bytes with a valid model yields symbols or an error, never a panic. Each
+//! decode does work bounded by the precision, and
+//! [`set_limits`](crate::ArithmeticDecoder::set_limits) bounds the work of a
+//! whole stream. Restoring a corrupted
+//! [`CoderState`](crate::state::CoderState) is an error too.
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use crate::precision::{MAX_PRECISION, MIN_PRECISION};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModelError {
+    /// The symbol is not in `[0, num_symbols)`
+    SymbolOutOfRange { symbol: u32, num_symbols: u32 },
+    /// Updating the model would overflow its total count
+    CountOverflow,
+    /// More symbols have a non-zero count than fit in the total the model
+    /// was being normalized to
+    NormalizeTooSmall { symbols: u32, total: u32 },
+    /// The symbol has a count of 0, so it has no interval to be coded in
+    ZeroCount { symbol: u32 },
+    /// Decrementing the symbol would take its count below 0
+    CountUnderflow { symbol: u32 },
+    /// The counts given to replace the model's, or those of a model mixed
+    /// into it, are not one per symbol
+    CountsLength { counts: usize, num_symbols: u32 },
+    /// The model is [frozen](crate::Model::freeze), so updates are ignored
+    Frozen,
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SymbolOutOfRange {
+                symbol,
+                num_symbols,
+            } => write!(
+                f,
+                "symbol {} is out of range for a model with {} symbols",
+                symbol, num_symbols
+            ),
+            Self::CountOverflow => write!(f, "the total count would overflow a u32"),
+            Self::NormalizeTooSmall { symbols, total } => write!(
+                f,
+                "{} symbols with a non-zero count do not fit in a total of {}",
+                symbols, total
+            ),
+            Self::ZeroCount { symbol } => {
+                write!(f, "symbol {} has a count of 0 and can not be coded", symbol)
+            }
+            Self::CountUnderflow { symbol } => {
+                write!(f, "symbol {} has too small a count to decrement", symbol)
+            }
+            Self::CountsLength {
+                counts,
+                num_symbols,
+            } => write!(
+                f,
+                "{} counts given for a model with {} symbols",
+                counts, num_symbols
+            ),
+            Self::Frozen => write!(f, "the model is frozen and can not be updated"),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+impl From<ModelError> for io::Error {
+    fn from(error: ModelError) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// Invalid configuration found by [`Builder::try_build`](crate::model::Builder::try_build).
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    /// A probability is NaN, infinite, negative or greater than 1
+    InvalidProbability { index: usize, value: f32 },
+    /// The probabilities are not within
+    /// [`PDF_SUM_TOLERANCE`](crate::model::PDF_SUM_TOLERANCE) of 1
+    PdfSum { sum: f32 },
+    /// The model would not have any symbols
+    NoSymbols,
+    /// `num_bits` is too large for the number of symbols to fit in a `u32`
+    TooManyBits { num_bits: u32 },
+    /// [`EOFKind::Specify`](crate::EOFKind::Specify) is not in
+    /// `[0, num_symbols)`
+    EofOutOfRange { eof: u32, num_symbols: u32 },
+    /// The counts (plus any added EOF and control symbols) sum past
+    /// `u32::MAX`
+    TotalOverflow,
+    /// More than one of counts, pdf, num_bits, num_symbols and binary was
+    /// set
+    ConflictingPaths,
+    /// [`Builder::rescale_at`](crate::model::Builder::rescale_at) is below
+    /// twice the number of symbols plus the increment, so halving could not
+    /// bring the total under it
+    RescaleTooSmall { max_total: u32, num_symbols: u32 },
+    /// [`Builder::decay`](crate::model::Builder::decay) would never decay or
+    /// would not shrink the counts
+    InvalidDecay {
+        every: u32,
+        numerator: u32,
+        denominator: u32,
+    },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidProbability { index, value } => {
+                write!(f, "probability {} at index {} is invalid", value, index)
+            }
+            Self::PdfSum { sum } => write!(f, "probabilities sum to {} instead of 1", sum),
+            Self::NoSymbols => write!(f, "the model has no symbols"),
+            Self::TooManyBits { num_bits } => {
+                write!(f, "{} bits is too many symbols for a model", num_bits)
+            }
+            Self::EofOutOfRange { eof, num_symbols } => write!(
+                f,
+                "EOF {} is out of range for a model with {} symbols",
+                eof, num_symbols
+            ),
+            Self::TotalOverflow => write!(f, "the total count overflows a u32"),
+            Self::ConflictingPaths => write!(f, "more than one build path was set"),
+            Self::RescaleTooSmall {
+                max_total,
+                num_symbols,
+            } => write!(
+                f,
+                "a maximum total of {} is too small to rescale {} symbols",
+                max_total, num_symbols
+            ),
+            Self::InvalidDecay {
+                every,
+                numerator,
+                denominator,
+            } => write!(
+                f,
+                "decaying by {}/{} every {} updates is invalid",
+                numerator, denominator, every
+            ),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<BuildError> for io::Error {
+    fn from(error: BuildError) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// The precision can not be used by a coder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrecisionError {
+    /// The precision is not between
+    /// [`MIN_PRECISION`] and
+    /// [`MAX_PRECISION`]
+    OutOfRange { precision: u64 },
+    /// The precision leaves symbols of a model with `total_count` without an
+    /// interval, see [`check_compatible`](crate::precision::check_compatible)
+    TooLowForModel { precision: u64, total_count: u32 },
+}
+
+impl Display for PrecisionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange { precision } => write!(
+                f,
+                "precision {} is outside of {}..={}",
+                precision, MIN_PRECISION, MAX_PRECISION
+            ),
+            Self::TooLowForModel {
+                precision,
+                total_count,
+            } => write!(
+                f,
+                "precision {} is too low for a model total count of {}",
+                precision, total_count
+            ),
+        }
+    }
+}
+
+impl Error for PrecisionError {}
+
+impl From<PrecisionError> for io::Error {
+    fn from(error: PrecisionError) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// A symbol would get an empty interval of the coder's range, because the
+/// range has become too narrow for the model or the model's cumulative
+/// counts are inconsistent. Coding it would make the stream undecodable.
+/// Symbols with a count of 0 fail earlier with [`ModelError::ZeroCount`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmptyIntervalError {
+    pub symbol: u32,
+    /// Width of the range the interval was taken from
+    pub range_width: u64,
+}
+
+impl Display for EmptyIntervalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "symbol {} has an empty interval in a range {} wide",
+            self.symbol, self.range_width
+        )
+    }
+}
+
+impl Error for EmptyIntervalError {}
+
+impl From<EmptyIntervalError> for io::Error {
+    fn from(error: EmptyIntervalError) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// [`ArithmeticEncoder::finish_encode`](crate::ArithmeticEncoder::finish_encode)
+/// was already called, so nothing more can be added to the stream until the
+/// encoder is [`reset`](crate::ArithmeticEncoder::reset).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinishedError;
+
+impl Display for FinishedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "the encoder has already finished its stream")
+    }
+}
+
+impl Error for FinishedError {}
+
+impl From<FinishedError> for io::Error {
+    fn from(error: FinishedError) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// A limit passed to
+/// [`ArithmeticDecoder::decode_with_limit`](crate::ArithmeticDecoder::decode_with_limit)
+/// or [`ArithmeticDecoder::set_limits`](crate::ArithmeticDecoder::set_limits)
+/// was reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    /// `limit` symbols have already been decoded
+    Symbols { limit: u64 },
+    /// Decoding would read past `limit` bytes of input
+    Bytes { limit: u64 },
+}
+
+impl Display for LimitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Symbols { limit } => write!(f, "decoded symbol limit of {} reached", limit),
+            Self::Bytes { limit } => write!(f, "input limit of {} bytes reached", limit),
+        }
+    }
+}
+
+impl Error for LimitError {}
+
+impl From<LimitError> for io::Error {
+    fn from(error: LimitError) -> Self {
+        Self::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Why decoding failed, see [`DecodeError`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeFailure {
+    /// No symbol's interval contains the input, so the stream is corrupt or
+    /// is being decoded with the wrong model
+    NoMatchingSymbol,
+    /// The input ended before the EOF symbol was decoded
+    MissingEof,
+    /// A limit passed to
+    /// [`ArithmeticDecoder::decode_with_limit`](crate::ArithmeticDecoder::decode_with_limit)
+    /// or [`ArithmeticDecoder::set_limits`](crate::ArithmeticDecoder::set_limits)
+    /// was reached
+    Limit(LimitError),
+    /// There is no segment or section after the current one
+    NoNextSegment,
+    /// A section starts with a precision the decoder can not use
+    InvalidPrecision(PrecisionError),
+    /// The EOF was already decoded or the decoder was marked finished, so
+    /// there is nothing left to decode in this stream
+    Finished,
+}
+
+impl Display for DecodeFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatchingSymbol => write!(f, "no symbol matches the input"),
+            Self::MissingEof => write!(
+                f,
+                "the input ended before the EOF symbol was decoded, was it encoded?"
+            ),
+            Self::Limit(limit) => limit.fmt(f),
+            Self::NoNextSegment => write!(f, "there is no segment after the current one"),
+            Self::InvalidPrecision(precision) => {
+                write!(f, "the section header is invalid: {}", precision)
+            }
+            Self::Finished => write!(f, "the stream has already been decoded to its end"),
+        }
+    }
+}
+
+/// A decoding failure along with where in the stream it happened, for
+/// triaging corrupt inputs without parsing messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub failure: DecodeFailure,
+    /// Number of bits read from the input when decoding failed
+    pub bit_offset: u64,
+    /// Number of symbols successfully decoded before the failure
+    pub symbols_decoded: u64,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (at bit {} after {} symbols)",
+            self.failure, self.bit_offset, self.symbols_decoded
+        )
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.failure {
+            DecodeFailure::Limit(limit) => Some(limit),
+            DecodeFailure::InvalidPrecision(precision) => Some(precision),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(error: DecodeError) -> Self {
+        let kind = match error.failure {
+            DecodeFailure::MissingEof | DecodeFailure::NoNextSegment => {
+                io::ErrorKind::UnexpectedEof
+            }
+            DecodeFailure::Finished => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        Self::new(kind, error)
+    }
+}
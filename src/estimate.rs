@@ -0,0 +1,229 @@
+//! Estimating the compressed size of a symbol stream without encoding it.
+
+use std::io::Error;
+
+use crate::{
+    cost,
+    encode::{check_headroom, renormalize},
+    error::ModelError,
+    Model, Range, SourceModel,
+};
+
+/// Consumes symbols, updating its model exactly as an adaptive encoding
+/// loop would, and accumulates the ideal `-log2(p)` cost of each symbol. No
+/// bit I/O or range arithmetic is done, so this is much cheaper than a trial
+/// encode.
+pub struct Estimator {
+    model: Model,
+    cost: u64,
+    symbols: u64,
+}
+
+impl Estimator {
+    pub fn new(model: Model) -> Self {
+        Self {
+            model,
+            cost: 0,
+            symbols: 0,
+        }
+    }
+
+    /// Fails without consuming `symbol` if it is out of range or has a
+    /// count of 0, which the encoder could not code.
+    pub fn push(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.model.check_codable(symbol)?;
+        let freq = self.model.counts()[symbol as usize];
+        self.cost += u64::from(cost::cost(freq.into(), self.model.total_count().into()));
+        self.symbols += 1;
+        self.model.update_symbol(symbol);
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = u32>>(&mut self, symbols: I) -> Result<(), ModelError> {
+        symbols.into_iter().try_for_each(|symbol| self.push(symbol))
+    }
+
+    /// Number of symbols consumed.
+    pub const fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Fixed-point (see [`cost`](mod@crate::cost)) cost of the consumed symbols.
+    pub const fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    /// Cost of the consumed symbols in bits.
+    pub fn bits(&self) -> f64 {
+        cost::to_bits(self.cost)
+    }
+
+    /// Estimated size of the stream in bytes, including the bits written by
+    /// [`finish_encode`](crate::ArithmeticEncoder::finish_encode) and padding
+    /// to a byte.
+    pub fn bytes(&self) -> u64 {
+        let bits = (self.cost + u64::from(cost::COST_ONE_BIT) - 1) >> cost::COST_FRACTIONAL_BITS;
+        (bits + 2 + 7) / 8
+    }
+
+    pub const fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn into_model(self) -> Model {
+        self.model
+    }
+}
+
+/// Runs the encoder's range arithmetic over symbols, updating its model
+/// exactly as an adaptive encoding loop would, without writing anything.
+/// Unlike [`Estimator`] the result is the exact size
+/// [`ArithmeticEncoder`](crate::ArithmeticEncoder) at the same precision
+/// produces, at about the cost of encoding to a sink.
+pub struct DryRun {
+    model: Model,
+    range: Range,
+    bits: u64,
+    symbols: u64,
+}
+
+impl DryRun {
+    pub fn new(model: Model, precision: u64) -> Self {
+        Self {
+            model,
+            range: Range::new(precision),
+            bits: 0,
+            symbols: 0,
+        }
+    }
+
+    /// Fails where [`ArithmeticEncoder::encode`](crate::ArithmeticEncoder::encode)
+    /// would.
+    pub fn push(&mut self, symbol: u32) -> Result<(), Error> {
+        check_headroom(&self.range, &self.model)?;
+        self.model.check_codable(symbol)?;
+        let low_high = self.range.calculate_range(symbol, &self.model);
+        self.range.update_range(low_high);
+        self.bits += renormalize(&mut self.range);
+        self.symbols += 1;
+        self.model.update_symbol(symbol);
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = u32>>(&mut self, symbols: I) -> Result<(), Error> {
+        symbols.into_iter().try_for_each(|symbol| self.push(symbol))
+    }
+
+    /// Number of symbols consumed.
+    pub const fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Number of bits the consumed symbols take, including the two written
+    /// by [`finish_encode`](crate::ArithmeticEncoder::finish_encode).
+    pub const fn bits(&self) -> u64 {
+        self.bits + 2
+    }
+
+    /// Size of the finished stream in bytes, padded to a byte.
+    pub const fn bytes(&self) -> u64 {
+        (self.bits() + 7) / 8
+    }
+
+    pub const fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn into_model(self) -> Model {
+        self.model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::BitWriter;
+
+    use super::{DryRun, Estimator};
+    use crate::{error::ModelError, ArithmeticEncoder, EOFKind, Model};
+
+    #[test]
+    fn matches_encoded_size() {
+        let data: Vec<u32> = b"abracadabra, abracadabra, abracadabra"
+            .iter()
+            .map(|&b| u32::from(b))
+            .collect();
+        let model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+
+        let mut estimator = Estimator::new(model.clone());
+        estimator.extend(data.iter().copied()).unwrap();
+        estimator.push(model.eof()).unwrap();
+        assert_eq!(estimator.symbols(), data.len() as u64 + 1);
+
+        let mut model = model;
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in data.iter().chain(Some(&model.eof())) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref().len() as u64;
+
+        assert!(estimator.bytes().max(encoded) - estimator.bytes().min(encoded) <= 1);
+        assert_eq!(estimator.model().counts(), model.counts());
+    }
+
+    #[test]
+    fn estimator_rejects_uncodable() {
+        let mut estimator = Estimator::new(Model::builder().counts(vec![3, 0, 2]).build());
+        assert_eq!(
+            estimator.push(3),
+            Err(ModelError::SymbolOutOfRange {
+                symbol: 3,
+                num_symbols: 3
+            })
+        );
+        assert_eq!(
+            estimator.extend([0, 1, 2]),
+            Err(ModelError::ZeroCount { symbol: 1 })
+        );
+        assert_eq!(estimator.symbols(), 1);
+        assert_eq!(estimator.model().counts(), &[4, 0, 2]);
+    }
+
+    #[test]
+    fn dry_run_is_exact() {
+        let data: Vec<u32> = include_str!("../tests/sherlock.rs")
+            .bytes()
+            .map(u32::from)
+            .collect();
+        let model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+
+        for &precision in &[20, 48] {
+            let mut dry_run = DryRun::new(model.clone(), precision);
+            dry_run.extend(data.iter().copied()).unwrap();
+            dry_run.push(model.eof()).unwrap();
+
+            let mut source_model = model.clone();
+            let mut encoder = ArithmeticEncoder::new(precision);
+            let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+            for &symbol in data.iter().chain(Some(&model.eof())) {
+                encoder
+                    .encode(symbol, &source_model, &mut out_writer)
+                    .unwrap();
+                source_model.update_symbol(symbol);
+            }
+            encoder.finish_encode(&mut out_writer).unwrap();
+            assert_eq!(dry_run.bits(), encoder.bits_written());
+            out_writer.pad_to_byte().unwrap();
+            assert_eq!(dry_run.bytes(), out_writer.get_ref().get_ref().len() as u64);
+            assert_eq!(dry_run.model().counts(), source_model.counts());
+        }
+
+        let mut dry_run = DryRun::new(model, 20);
+        assert!(dry_run.push(300).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Scoring candidate models against held-out corpora.
+//!
+//! Every corpus is coded by a fresh candidate, so adaptive models are scored
+//! on how quickly they learn as well as where they end up, exactly as they
+//! would be in an encoder. Costs are the ideal `-log2(p)` of every symbol
+//! (see [`cost`](mod@crate::cost)); nothing is encoded. For byte corpora
+//! [`bits_per_symbol`](Evaluation::bits_per_symbol) is bits per byte.
+
+use std::io::Error;
+
+use crate::{cost, Model};
+
+/// A model, or a configuration of several, that can be scored.
+pub trait Candidate {
+    /// Returns the fixed-point cost of `symbol` as the next symbol, then
+    /// learns from it as the encoder and decoder would.
+    fn observe(&mut self, symbol: u32) -> Result<u32, Error>;
+}
+
+/// An adaptive order-0 model, updated with every symbol.
+impl Candidate for Model {
+    fn observe(&mut self, symbol: u32) -> Result<u32, Error> {
+        self.check_symbol(symbol)?;
+        let freq = self.counts()[symbol as usize];
+        let cost = cost::cost(freq.into(), self.total_count().into());
+        self.update_symbol(symbol);
+        Ok(cost)
+    }
+}
+
+/// Symbols and cost of a group of symbols.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub symbols: u64,
+    /// Fixed-point cost
+    pub cost: u64,
+}
+
+impl Score {
+    fn add(&mut self, cost: u32) {
+        self.symbols += 1;
+        self.cost += u64::from(cost);
+    }
+
+    pub fn bits(&self) -> f64 {
+        cost::to_bits(self.cost)
+    }
+
+    /// Average bits per symbol, 0 if there are no symbols.
+    pub fn bits_per_symbol(&self) -> f64 {
+        if self.symbols == 0 {
+            0.0
+        } else {
+            self.bits() / self.symbols as f64
+        }
+    }
+}
+
+/// The result of [`evaluate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Evaluation {
+    pub total: Score,
+    /// One score per corpus, in order.
+    pub corpora: Vec<Score>,
+    /// One score per symbol class, indexed by the classifier's result.
+    pub classes: Vec<Score>,
+}
+
+impl Evaluation {
+    pub fn bits_per_symbol(&self) -> f64 {
+        self.total.bits_per_symbol()
+    }
+}
+
+/// Scores the candidates made by `make` on `corpora`, using a new candidate
+/// for every corpus. `classify` puts every symbol in a class (such as
+/// literals, punctuation and control symbols) for the per-class breakdown;
+/// use `|_| 0` if only the totals matter.
+pub fn evaluate<C, M, F>(make: M, corpora: &[&[u32]], classify: F) -> Result<Evaluation, Error>
+where
+    C: Candidate,
+    M: Fn() -> C,
+    F: Fn(u32) -> usize,
+{
+    let mut evaluation = Evaluation {
+        total: Score::default(),
+        corpora: Vec::with_capacity(corpora.len()),
+        classes: Vec::new(),
+    };
+
+    for corpus in corpora {
+        let mut candidate = make();
+        let mut score = Score::default();
+        for &symbol in corpus.iter() {
+            let cost = candidate.observe(symbol)?;
+            score.add(cost);
+            evaluation.total.add(cost);
+
+            let class = classify(symbol);
+            if class >= evaluation.classes.len() {
+                evaluation.classes.resize(class + 1, Score::default());
+            }
+            evaluation.classes[class].add(cost);
+        }
+        evaluation.corpora.push(score);
+    }
+    Ok(evaluation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, Candidate};
+    use crate::{
+        context::{ContextSwitchingCoder, History},
+        Model,
+    };
+
+    fn corpus(text: &str) -> Vec<u32> {
+        text.bytes().map(u32::from).collect()
+    }
+
+    #[test]
+    fn order0_against_order1() {
+        let first = corpus(&"the quick brown fox jumps over the lazy dog. ".repeat(20));
+        let second = corpus(&"pack my box with five dozen liquor jugs. ".repeat(20));
+        let corpora = [first.as_slice(), second.as_slice()];
+        let vowel = |symbol: u32| usize::from(b"aeiou".contains(&(symbol as u8)));
+
+        let order0 = evaluate(|| Model::builder().num_bits(8).build(), &corpora, vowel).unwrap();
+        let order1 = evaluate(
+            || {
+                let models = vec![Model::builder().num_bits(8).build(); 256];
+                ContextSwitchingCoder::new(models, 1, |history: &History| {
+                    history.get(0).unwrap_or(0) as usize
+                })
+            },
+            &corpora,
+            vowel,
+        )
+        .unwrap();
+
+        for evaluation in &[&order0, &order1] {
+            assert_eq!(
+                evaluation.total.symbols,
+                (first.len() + second.len()) as u64
+            );
+            assert_eq!(evaluation.corpora.len(), 2);
+            assert_eq!(evaluation.classes.len(), 2);
+            assert_eq!(
+                evaluation.classes[0].cost + evaluation.classes[1].cost,
+                evaluation.total.cost
+            );
+        }
+        // the text repeats, so the previous byte predicts the next one well
+        assert!(order1.bits_per_symbol() < order0.bits_per_symbol());
+        assert!(order0.bits_per_symbol() < 8.0);
+    }
+
+    #[test]
+    fn invalid_symbol() {
+        let corpus = [0, 1, 4];
+        assert!(evaluate(
+            || Model::builder().num_symbols(4).build(),
+            &[&corpus],
+            |_| 0
+        )
+        .is_err());
+        let mut model = Model::builder().num_symbols(2).build();
+        assert_eq!(model.observe(0).unwrap(), crate::cost::COST_ONE_BIT);
+    }
+}
@@ -0,0 +1,398 @@
+//! An arithmetic coder that keeps its interval as exact rationals, for
+//! comparing against the finite precision coder.
+//!
+//! After coding symbols with frequencies `f_i` out of totals `T_i` the
+//! interval is `[low / D, (low + width) / D)` with `D = T_1 * ... * T_n` and
+//! `width = f_1 * ... * f_n`, so no rounding ever happens and the output is
+//! the shortest binary fraction inside the final interval. Everything grows
+//! with the length of the stream, so this is only meant for short
+//! experiments. The stream format is not compatible with
+//! [`ArithmeticEncoder`](crate::ArithmeticEncoder).
+
+use std::{
+    cmp::Ordering,
+    io::{Error, ErrorKind, Read, Write},
+};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{Model, SourceModel};
+
+/// Minimal arbitrary precision unsigned integer, little-endian base 2^32
+/// limbs without trailing zero limbs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn from_u64(value: u64) -> Self {
+        let mut number = Self {
+            limbs: vec![value as u32, (value >> 32) as u32],
+        };
+        number.trim();
+        number
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let sum = u64::from(*self.limbs.get(i).unwrap_or(&0))
+                + u64::from(*other.limbs.get(i).unwrap_or(&0))
+                + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        let mut number = Self { limbs };
+        number.trim();
+        number
+    }
+
+    /// `self - other`, which must not be negative.
+    fn sub(&self, other: &Self) -> Self {
+        debug_assert!(*self >= *other);
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0;
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            let subtrahend = i64::from(*other.limbs.get(i).unwrap_or(&0)) + borrow;
+            let mut difference = i64::from(limb) - subtrahend;
+            borrow = 0;
+            if difference < 0 {
+                difference += 1 << 32;
+                borrow = 1;
+            }
+            limbs.push(difference as u32);
+        }
+        let mut number = Self { limbs };
+        number.trim();
+        number
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let product = u64::from(a) * u64::from(b) + u64::from(limbs[i + j]) + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        let mut number = Self { limbs };
+        number.trim();
+        number
+    }
+
+    fn mul_u64(&self, value: u64) -> Self {
+        self.mul(&Self::from_u64(value))
+    }
+
+    fn shl(&self, bits: u64) -> Self {
+        let limb_shift = (bits / 32) as usize;
+        let bit_shift = (bits % 32) as u32;
+        let mut limbs = vec![0; limb_shift];
+        let mut carry = 0;
+        for &limb in &self.limbs {
+            let shifted = u64::from(limb) << bit_shift | carry;
+            limbs.push(shifted as u32);
+            carry = shifted >> 32;
+        }
+        limbs.push(carry as u32);
+        let mut number = Self { limbs };
+        number.trim();
+        number
+    }
+
+    fn bit(&self, index: u64) -> bool {
+        self.limbs
+            .get((index / 32) as usize)
+            .map_or(false, |limb| limb >> (index % 32) & 1 == 1)
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+/// `[low / denominator, (low + width) / denominator)`
+#[derive(Clone, Debug)]
+struct Interval {
+    low: BigUint,
+    width: BigUint,
+    denominator: BigUint,
+}
+
+impl Interval {
+    fn new() -> Self {
+        Self {
+            low: BigUint::from_u64(0),
+            width: BigUint::from_u64(1),
+            denominator: BigUint::from_u64(1),
+        }
+    }
+
+    /// Cumulative count below `symbol`, its count and the model total.
+    fn counts(symbol: u32, model: &Model) -> Result<(u64, u64, u64), Error> {
+        model.check_codable(symbol)?;
+        let count = u64::from(model.counts()[symbol as usize]);
+        let cumulative = u64::from(model.cdf()[symbol as usize]);
+        Ok((cumulative, count, u64::from(model.total_count())))
+    }
+
+    fn narrow(&mut self, cumulative: u64, count: u64, total: u64) {
+        self.low = self.low.mul_u64(total).add(&self.width.mul_u64(cumulative));
+        self.width = self.width.mul_u64(count);
+        self.denominator = self.denominator.mul_u64(total);
+    }
+}
+
+/// Exact counterpart of [`ArithmeticEncoder`](crate::ArithmeticEncoder).
+pub struct ExactEncoder {
+    interval: Interval,
+}
+
+impl Default for ExactEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExactEncoder {
+    pub fn new() -> Self {
+        Self {
+            interval: Interval::new(),
+        }
+    }
+
+    pub fn encode(&mut self, symbol: u32, source_model: &Model) -> Result<(), Error> {
+        let (cumulative, count, total) = Interval::counts(symbol, source_model)?;
+        self.interval.narrow(cumulative, count, total);
+        Ok(())
+    }
+
+    /// Writes the shortest binary fraction in the interval and returns its
+    /// length in bits. Nothing is written before this is called.
+    pub fn finish_encode<W: Write>(&self, output: &mut BitWriter<W>) -> Result<u64, Error> {
+        let Interval {
+            low,
+            width,
+            denominator,
+        } = &self.interval;
+        let high = low.add(width);
+
+        // fraction = ceil(low * 2^bits / denominator), the smallest `bits`
+        // bit fraction at or above low. Each extra bit doubles it, minus one
+        // if that is still at or above low.
+        let mut bits = 0;
+        let mut fraction = BigUint::from_u64(u64::from(!low.is_zero()));
+        while fraction.mul(denominator) >= high.shl(bits) {
+            bits += 1;
+            let doubled = fraction.shl(1);
+            fraction = if doubled.is_zero() {
+                doubled
+            } else {
+                let below = doubled.sub(&BigUint::from_u64(1));
+                if below.mul(denominator) >= low.shl(bits) {
+                    below
+                } else {
+                    doubled
+                }
+            };
+        }
+
+        for bit in (0..bits).rev() {
+            output.write_bit(fraction.bit(bit))?;
+        }
+        Ok(bits)
+    }
+}
+
+/// Exact counterpart of [`ArithmeticDecoder`](crate::ArithmeticDecoder).
+/// Reads the whole input up front.
+pub struct ExactDecoder {
+    interval: Interval,
+    /// The input as `value / 2^bits`
+    value: BigUint,
+    bits: u64,
+    finished: bool,
+}
+
+impl ExactDecoder {
+    pub fn new<R: Read, B: Bit>(bit_source: &mut BitReader<R, B>) -> Self {
+        let mut value = BigUint::from_u64(0);
+        let mut bits = 0;
+        while let Ok(bit) = bit_source.read_bit() {
+            value = value.shl(1).add(&BigUint::from_u64(u64::from(bit)));
+            bits += 1;
+        }
+
+        Self {
+            interval: Interval::new(),
+            value,
+            bits,
+            finished: false,
+        }
+    }
+
+    pub fn decode(&mut self, source_model: &Model) -> Result<u32, Error> {
+        let cdf = source_model.cdf();
+        let total = u64::from(source_model.total_count());
+        let Interval {
+            low,
+            width,
+            denominator,
+        } = &self.interval;
+
+        // symbol s starts at (low * total + cdf[s] * width) / (denominator *
+        // total); find the last start at or below the value
+        let target = self.value.mul(&denominator.mul_u64(total));
+        let scaled_low = low.mul_u64(total);
+        let starts_below = |index: usize| {
+            scaled_low
+                .add(&width.mul_u64(u64::from(cdf[index])))
+                .shl(self.bits)
+                <= target
+        };
+
+        let (mut lo, mut hi) = (0, source_model.num_symbols() as usize);
+        while lo + 1 < hi {
+            let mid = (lo + hi) / 2;
+            if starts_below(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        // symbols with a count of 0 share their start with the next symbol,
+        // so the last start at or below the value is never one of them
+        let symbol = lo;
+        if !starts_below(symbol) || starts_below(symbol + 1) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "no symbol matches the input, the stream is corrupt",
+            ));
+        }
+
+        let symbol = symbol as u32;
+        let count = u64::from(source_model.counts()[symbol as usize]);
+        self.interval
+            .narrow(u64::from(cdf[symbol as usize]), count, total);
+        if symbol == source_model.eof() {
+            self.finished = true;
+        }
+        Ok(symbol)
+    }
+
+    pub const fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{BigUint, ExactDecoder, ExactEncoder};
+    use crate::{ArithmeticEncoder, EOFKind, Model};
+
+    #[test]
+    fn big_uint() {
+        let a = BigUint::from_u64(u64::MAX);
+        let b = a.mul(&a).add(&BigUint::from_u64(1));
+        assert_eq!(b.limbs, [2, 0, 0xFFFF_FFFE, 0xFFFF_FFFF]);
+        assert_eq!(
+            b.sub(&BigUint::from_u64(3)).limbs,
+            [0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFD, 0xFFFF_FFFF]
+        );
+        assert_eq!(BigUint::from_u64(3).shl(33).limbs, [0, 6]);
+        assert!(b > a);
+        assert!(b.bit(1) && !b.bit(0));
+    }
+
+    fn encode_exact(data: &[u32]) -> (Vec<u8>, u64) {
+        let mut model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut encoder = ExactEncoder::new();
+        for &symbol in data.iter().chain(Some(&9)) {
+            encoder.encode(symbol, &model).unwrap();
+            model.update_symbol(symbol);
+        }
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        let bits = encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        (out_writer.get_ref().get_ref().clone(), bits)
+    }
+
+    #[test]
+    fn e2e() {
+        let data = [7, 2, 2, 2, 7, 0, 1, 8, 8, 8, 8, 8, 3];
+        let (encoded, bits) = encode_exact(&data);
+
+        let mut model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(encoded));
+        let mut decoder = ExactDecoder::new(&mut in_reader);
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = decoder.decode(&model).unwrap();
+            model.update_symbol(symbol);
+            decoded.push(symbol);
+        }
+        decoded.pop();
+        assert_eq!(decoded, data);
+
+        // never worse than the finite precision coder
+        let mut model = Model::builder().num_symbols(10).eof(EOFKind::End).build();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in data.iter().chain(Some(&9)) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        assert!(bits <= out_writer.get_ref().get_ref().len() as u64 * 8);
+    }
+
+    #[test]
+    fn zero_counts() {
+        let model = Model::builder().counts(vec![1, 0, 0, 1]).build();
+        let mut encoder = ExactEncoder::new();
+        assert!(encoder.encode(1, &model).is_err());
+        encoder.encode(3, &model).unwrap();
+        encoder.encode(0, &model).unwrap();
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut in_reader: BitReader<_, MSB> =
+            BitReader::new(Cursor::new(out_writer.get_ref().get_ref().clone()));
+        let mut decoder = ExactDecoder::new(&mut in_reader);
+        assert_eq!(decoder.decode(&model).unwrap(), 3);
+        assert_eq!(decoder.decode(&model).unwrap(), 0);
+    }
+}
@@ -0,0 +1,366 @@
+//! Corruption detection for raw streams: a checksum of the coded symbols is
+//! written as a 4 byte trailer after the padded payload and checked once
+//! decoding is done.
+//!
+//! Symbols are checksummed as little-endian `u32`s, so the result does not
+//! depend on the models used to code them.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Length of the trailer in bytes.
+pub const TRAILER_LEN: usize = 4;
+
+/// Checksum algorithm used for the trailer. Both sides must agree on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumKind {
+    /// CRC-32 (IEEE)
+    Crc32,
+    /// 32 bit xxHash with a seed of 0
+    XxHash32,
+}
+
+/// Incremental checksum of a byte stream.
+#[derive(Clone, Debug)]
+pub struct Checksum {
+    state: State,
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Crc32(u32),
+    XxHash32(XxHash32),
+}
+
+impl Checksum {
+    pub fn new(kind: ChecksumKind) -> Self {
+        let state = match kind {
+            ChecksumKind::Crc32 => State::Crc32(!0),
+            ChecksumKind::XxHash32 => State::XxHash32(XxHash32::new()),
+        };
+        Self { state }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match &mut self.state {
+            State::Crc32(crc) => {
+                for &byte in bytes {
+                    *crc = CRC32_TABLE[((*crc ^ u32::from(byte)) & 0xFF) as usize] ^ (*crc >> 8);
+                }
+            }
+            State::XxHash32(hash) => hash.update(bytes),
+        }
+    }
+
+    pub fn update_symbol(&mut self, symbol: u32) {
+        self.update(&symbol.to_le_bytes());
+    }
+
+    /// Checksum of everything passed to [`update`](Self::update) so far.
+    pub fn value(&self) -> u32 {
+        match &self.state {
+            State::Crc32(crc) => !crc,
+            State::XxHash32(hash) => hash.value(),
+        }
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const PRIME_1: u32 = 2_654_435_761;
+const PRIME_2: u32 = 2_246_822_519;
+const PRIME_3: u32 = 3_266_489_917;
+const PRIME_4: u32 = 668_265_263;
+const PRIME_5: u32 = 374_761_393;
+
+#[derive(Clone, Debug)]
+struct XxHash32 {
+    lanes: [u32; 4],
+    buffer: [u8; 16],
+    buffered: usize,
+    len: u64,
+}
+
+impl XxHash32 {
+    fn new() -> Self {
+        Self {
+            lanes: [
+                PRIME_1.wrapping_add(PRIME_2),
+                PRIME_2,
+                0,
+                0u32.wrapping_sub(PRIME_1),
+            ],
+            buffer: [0; 16],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn round(lane: u32, input: u32) -> u32 {
+        lane.wrapping_add(input.wrapping_mul(PRIME_2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME_1)
+    }
+
+    fn word(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        for &byte in bytes {
+            self.buffer[self.buffered] = byte;
+            self.buffered += 1;
+            if self.buffered == self.buffer.len() {
+                for (i, lane) in self.lanes.iter_mut().enumerate() {
+                    *lane = Self::round(*lane, Self::word(&self.buffer[i * 4..]));
+                }
+                self.buffered = 0;
+            }
+        }
+    }
+
+    fn value(&self) -> u32 {
+        let mut hash = if self.len >= 16 {
+            let [v1, v2, v3, v4] = self.lanes;
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            PRIME_5
+        };
+        hash = hash.wrapping_add(self.len as u32);
+
+        let rest = &self.buffer[..self.buffered];
+        let mut words = rest.chunks_exact(4);
+        for word in &mut words {
+            hash = hash
+                .wrapping_add(Self::word(word).wrapping_mul(PRIME_3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME_4);
+        }
+        for &byte in words.remainder() {
+            hash = hash
+                .wrapping_add(u32::from(byte).wrapping_mul(PRIME_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME_1);
+        }
+
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^ (hash >> 16)
+    }
+}
+
+/// An [`ArithmeticEncoder`] that checksums every symbol it encodes and
+/// writes the checksum after the payload in [`finish`](Self::finish).
+pub struct ChecksumEncoder {
+    encoder: ArithmeticEncoder,
+    checksum: Checksum,
+}
+
+impl ChecksumEncoder {
+    pub fn new(precision: u64, kind: ChecksumKind) -> Self {
+        Self {
+            encoder: ArithmeticEncoder::new(precision),
+            checksum: Checksum::new(kind),
+        }
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        symbol: u32,
+        source_model: &Model,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        self.encoder.encode(symbol, source_model, output)?;
+        self.checksum.update_symbol(symbol);
+        Ok(())
+    }
+
+    /// Finishes the payload, pads it to a byte and writes the big-endian
+    /// checksum trailer.
+    pub fn finish<W: Write>(mut self, output: &mut BitWriter<W>) -> Result<(), Error> {
+        self.encoder.finish_encode(output)?;
+        output.pad_to_byte()?;
+        for byte in self.checksum.value().to_be_bytes() {
+            output.write_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    pub const fn encoder(&self) -> &ArithmeticEncoder {
+        &self.encoder
+    }
+}
+
+/// Splits a stream written by [`ChecksumEncoder`] into its payload and the
+/// checksum stored in the trailer.
+pub fn split_trailer(stream: &[u8]) -> Result<(&[u8], u32), Error> {
+    if stream.len() < TRAILER_LEN {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "the stream is too short to have a checksum trailer",
+        ));
+    }
+    let (payload, trailer) = stream.split_at(stream.len() - TRAILER_LEN);
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    Ok((payload, expected))
+}
+
+/// An [`ArithmeticDecoder`] that checksums every symbol it decodes so the
+/// result can be compared with the trailer once decoding is done.
+///
+/// Decode from the payload returned by [`split_trailer`] so the trailer is
+/// not read as coded data.
+pub struct ChecksumDecoder {
+    decoder: ArithmeticDecoder,
+    checksum: Checksum,
+    expected: u32,
+}
+
+impl ChecksumDecoder {
+    pub fn new(precision: u64, kind: ChecksumKind, expected: u32) -> Self {
+        Self {
+            decoder: ArithmeticDecoder::new(precision),
+            checksum: Checksum::new(kind),
+            expected,
+        }
+    }
+
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        source_model: &Model,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        let symbol = self.decoder.decode(source_model, bit_source)?;
+        self.checksum.update_symbol(symbol);
+        Ok(symbol)
+    }
+
+    pub const fn finished(&self) -> bool {
+        self.decoder.finished()
+    }
+
+    /// Errors with [`ErrorKind::InvalidData`] if the symbols decoded so far
+    /// do not match the trailer.
+    pub fn verify(&self) -> Result<(), Error> {
+        let actual = self.checksum.value();
+        if actual == self.expected {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "checksum {:#010x} does not match the trailer {:#010x}",
+                    actual, self.expected
+                ),
+            ))
+        }
+    }
+
+    pub const fn decoder(&self) -> &ArithmeticDecoder {
+        &self.decoder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{split_trailer, Checksum, ChecksumDecoder, ChecksumEncoder, ChecksumKind};
+    use crate::{EOFKind, Model};
+
+    fn checksum(kind: ChecksumKind, bytes: &[u8]) -> u32 {
+        let mut checksum = Checksum::new(kind);
+        checksum.update(bytes);
+        checksum.value()
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(checksum(ChecksumKind::Crc32, b"123456789"), 0xCBF4_3926);
+        assert_eq!(checksum(ChecksumKind::XxHash32, b""), 0x02CC_5D05);
+        assert_eq!(checksum(ChecksumKind::XxHash32, b"abc"), 0x32D1_53FF);
+
+        let long = b"Nobody inspects the spammish repetition";
+        let mut split = Checksum::new(ChecksumKind::XxHash32);
+        split.update(&long[..7]);
+        split.update(&long[7..]);
+        assert_eq!(split.value(), checksum(ChecksumKind::XxHash32, long));
+        assert_eq!(split.value(), 0xE229_3B2F);
+    }
+
+    fn encode(kind: ChecksumKind, data: &[u32]) -> Vec<u8> {
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut encoder = ChecksumEncoder::new(48, kind);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in data.iter().chain(Some(&model.eof())) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish(&mut out_writer).unwrap();
+        out_writer.get_ref().get_ref().clone()
+    }
+
+    fn decode(kind: ChecksumKind, stream: &[u8]) -> (Vec<u32>, bool) {
+        let (payload, expected) = split_trailer(stream).unwrap();
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut decoder = ChecksumDecoder::new(48, kind, expected);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(payload);
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = decoder.decode(&model, &mut in_reader).unwrap();
+            model.update_symbol(symbol);
+            decoded.push(symbol);
+        }
+        decoded.pop();
+        (decoded, decoder.verify().is_ok())
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u32> = b"integrity".iter().map(|&b| u32::from(b)).collect();
+        for &kind in &[ChecksumKind::Crc32, ChecksumKind::XxHash32] {
+            let stream = encode(kind, &data);
+            assert_eq!(decode(kind, &stream), (data.clone(), true));
+        }
+    }
+
+    #[test]
+    fn detects_corrupt_trailer() {
+        let data = [1, 2, 3];
+        let mut stream = encode(ChecksumKind::Crc32, &data);
+        *stream.last_mut().unwrap() ^= 1;
+        assert_eq!(decode(ChecksumKind::Crc32, &stream), (data.to_vec(), false));
+        assert!(split_trailer(&stream[..3]).is_err());
+    }
+}
@@ -153,14 +153,44 @@
 //! }
 //! ```
 
+pub mod alphabet;
 pub mod binary;
+pub mod carry;
+pub mod context;
+pub mod cost;
 mod decode;
+pub mod digram;
 mod encode;
+pub mod error;
+pub mod estimate;
+pub mod evaluate;
+#[cfg(feature = "exact")]
+pub mod exact;
+pub mod integrity;
+pub mod lookup;
 pub mod model;
+pub mod narrow;
+pub mod order2;
+pub mod parallel;
+pub mod precision;
+pub mod quantize;
 mod range;
+pub mod rangecoder;
+pub mod rewind;
+pub mod runlevel;
+pub mod sample;
+pub mod segment;
+pub mod state;
+pub mod switcher;
+pub mod varint;
+pub mod verify;
+pub mod wide;
+pub mod word;
 
 pub use bitbit;
-pub use decode::ArithmeticDecoder;
+pub use decode::{ArithmeticDecoder, DecodeInfo, DecodeLimits, SliceDecoder};
 pub use encode::ArithmeticEncoder;
-pub use model::{EOFKind, Model};
-pub use range::Range;
+pub use model::{ByteModel, ControlKind, EOFKind, LargeModel, Model, SourceModel, StaticModel};
+pub use precision::recommended_precision;
+pub use range::{ConstRange, Range};
+pub use verify::verify;
@@ -0,0 +1,206 @@
+//! Table driven symbol lookup for the decoder, see
+//! [`ArithmeticDecoder::decode_with_table`](crate::ArithmeticDecoder::decode_with_table).
+//!
+//! Small alphabets such as nibbles or the nodes of a binary tree are decoded
+//! by indexing a table with the top bits of the input's cumulative count,
+//! which lands on the symbol or a step or two before it. Larger alphabets,
+//! such as bytes, are searched in a flat cumulative array, first for the
+//! block of [`BLOCK`] symbols and then within it. With the `simd` feature
+//! both steps are a few SSE2 compares on x86_64. Other targets search with
+//! `partition_point`: the NEON intrinsics aarch64 would need are newer than
+//! the crate's minimum supported Rust version.
+
+use crate::Model;
+
+/// The most symbols a model may have to be looked up by slices of its
+/// total, larger models are searched in blocks.
+pub const MAX_SLICED_SYMBOLS: u32 = 16;
+
+/// Number of symbols searched together in the flat cumulative array.
+pub const BLOCK: usize = 16;
+
+/// Number of top bits of the cumulative count the table is indexed by.
+const TABLE_BITS: u32 = 8;
+
+#[derive(Clone, Debug, Default)]
+pub struct LookupTable {
+    /// The counts the table was built from.
+    counts: Vec<u32>,
+    /// `cumulative[s]` is the sum of the counts before symbol `s`.
+    cumulative: Vec<u32>,
+    total: u32,
+    /// The first symbol whose interval reaches into each slice of the total.
+    starts: Vec<u8>,
+    /// The cumulative count after every symbol, padded to whole blocks with
+    /// `u32::MAX`.
+    highs: Vec<u32>,
+    /// The last of `highs` in every block, padded the same way.
+    block_highs: Vec<u32>,
+}
+
+impl LookupTable {
+    /// An empty table, built on first use.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds the table if `source_model`'s counts changed since it was
+    /// last built. Rebuilding takes time linear in the number of symbols, so
+    /// large adaptive models are better decoded without a table.
+    pub fn refresh(&mut self, source_model: &Model) {
+        if self.counts == *source_model.counts() {
+            return;
+        }
+
+        self.counts.clone_from(source_model.counts());
+        self.cumulative.clear();
+        self.cumulative.push(0);
+        for &count in &self.counts {
+            self.cumulative
+                .push(self.cumulative.last().unwrap() + count);
+        }
+        self.total = source_model.total_count();
+
+        if source_model.num_symbols() <= MAX_SLICED_SYMBOLS {
+            self.build_slices();
+        } else {
+            self.build_blocks();
+        }
+    }
+
+    fn build_slices(&mut self) {
+        let last = self.counts.len().saturating_sub(1);
+        let total = u64::from(self.total);
+        self.starts = (0..1u64 << TABLE_BITS)
+            .map(|slice| {
+                // the smallest count in this slice
+                let count = ((slice * total + (1 << TABLE_BITS) - 1) >> TABLE_BITS) as u32;
+                let symbol = self.cumulative[1..].partition_point(|&high| high <= count);
+                symbol.min(last) as u8
+            })
+            .collect();
+        self.highs.clear();
+        self.block_highs.clear();
+    }
+
+    fn build_blocks(&mut self) {
+        let blocks = (self.counts.len() + BLOCK - 1) / BLOCK;
+        self.highs.clear();
+        self.highs.extend_from_slice(&self.cumulative[1..]);
+        self.highs.resize(blocks * BLOCK, u32::MAX);
+
+        self.block_highs.clear();
+        self.block_highs
+            .extend(self.highs.chunks_exact(BLOCK).map(|block| block[BLOCK - 1]));
+        // whole vectors of four
+        let padded = (blocks + 3) / 4 * 4;
+        self.block_highs.resize(padded, u32::MAX);
+        self.starts.clear();
+    }
+
+    /// The symbol whose cumulative count interval contains `count`, which
+    /// must be less than the total of the model the table was built from.
+    pub fn symbol_at(&self, count: u32) -> u32 {
+        debug_assert!(count < self.total);
+        if self.highs.is_empty() {
+            let slice = (u64::from(count) << TABLE_BITS) / u64::from(self.total);
+            let mut symbol = usize::from(self.starts[slice as usize]);
+            while self.cumulative[symbol + 1] <= count {
+                symbol += 1;
+            }
+            symbol as u32
+        } else {
+            // blocks that end at or before `count` come before its block
+            let block = count_at_most(&self.block_highs, count);
+            let highs = &self.highs[block * BLOCK..(block + 1) * BLOCK];
+            (block * BLOCK + count_at_most(highs, count)) as u32
+        }
+    }
+}
+
+/// Number of `values`, which are sorted and a multiple of four long, that
+/// are at most `count`.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn count_at_most(values: &[u32], count: u32) -> usize {
+    use std::arch::x86_64::{
+        __m128i, _mm_castsi128_ps, _mm_cmpgt_epi32, _mm_loadu_si128, _mm_movemask_ps,
+        _mm_set1_epi32, _mm_xor_si128,
+    };
+
+    debug_assert_eq!(values.len() % 4, 0);
+    let mut at_most = 0;
+    // SAFETY: SSE2 is part of x86_64 and every load reads four in-bounds
+    // values of a `chunks_exact(4)` chunk.
+    unsafe {
+        // flipping the sign bits turns the signed compare into an unsigned one
+        let bias = _mm_set1_epi32(i32::MIN);
+        let target = _mm_xor_si128(_mm_set1_epi32(count as i32), bias);
+        for chunk in values.chunks_exact(4) {
+            let chunk = _mm_xor_si128(_mm_loadu_si128(chunk.as_ptr() as *const __m128i), bias);
+            let greater = _mm_movemask_ps(_mm_castsi128_ps(_mm_cmpgt_epi32(chunk, target)));
+            let below = 4 - greater.count_ones() as usize;
+            at_most += below;
+            if below < 4 {
+                break;
+            }
+        }
+    }
+    at_most
+}
+
+/// Number of `values`, which are sorted, that are at most `count`.
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn count_at_most(values: &[u32], count: u32) -> usize {
+    values.partition_point(|&value| value <= count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_at_most, LookupTable};
+    use crate::Model;
+
+    #[test]
+    fn symbol_at() {
+        let mut model = Model::builder().counts(vec![1, 0, 6, 3, 0, 2, 1]).build();
+        let mut table = LookupTable::new();
+        for _ in 0..2 {
+            table.refresh(&model);
+            for count in 0..model.total_count() {
+                assert_eq!(table.symbol_at(count), model.symbol_at(count));
+            }
+            (0..500).for_each(|i| model.update_symbol([0, 3, 6][i % 3]));
+        }
+    }
+
+    #[test]
+    fn symbol_at_blocks() {
+        for &num_symbols in &[17, 64, 257] {
+            let counts = (0..num_symbols).map(|s| (s * 7 + 3) % 11).collect();
+            let model = Model::builder().counts(counts).build();
+            let mut table = LookupTable::new();
+            table.refresh(&model);
+            for count in 0..model.total_count() {
+                assert_eq!(table.symbol_at(count), model.symbol_at(count));
+            }
+        }
+    }
+
+    #[test]
+    fn count_at_most_unsigned() {
+        let values = [
+            0,
+            5,
+            1 << 31,
+            u32::MAX - 1,
+            u32::MAX,
+            u32::MAX,
+            u32::MAX,
+            u32::MAX,
+        ];
+        assert_eq!(count_at_most(&values, 0), 1);
+        assert_eq!(count_at_most(&values, 4), 1);
+        assert_eq!(count_at_most(&values, 5), 2);
+        assert_eq!(count_at_most(&values, 1 << 31), 3);
+        assert_eq!(count_at_most(&values, u32::MAX - 1), 4);
+    }
+}
@@ -1,18 +1,179 @@
-use fenwick::array::{prefix_sum, update};
+use fenwick::{
+    array::{prefix_sum, update},
+    index::zero_based::up,
+};
+
+use crate::error::ModelError;
+
+mod approximate;
+pub use approximate::ApproximateModel;
 
 mod builder;
-pub use builder::{Builder, EOFKind};
+pub use builder::{Builder, ControlKind, EOFKind, PDF_SUM_TOLERANCE};
+
+mod byte;
+pub use byte::ByteModel;
+
+mod compact;
+pub use compact::CompactModel;
+
+mod fixed;
+pub use fixed::StaticModel;
+
+mod floor;
+pub use floor::Floored;
+
+mod sparse;
+pub use sparse::SparseModel;
+
+mod tree;
+pub use tree::FenwickModel;
+
+mod large;
+pub use large::LargeModel;
+
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "stats")]
+pub use stats::ModelStats;
+
+/// What the encoder and decoder need from a model: the integer cumulative
+/// counts of its symbols. Implemented by [`Model`] and the specialized
+/// [`ByteModel`], [`StaticModel`], [`CompactModel`], [`FenwickModel`],
+/// [`SparseModel`] and [`ApproximateModel`].
+///
+/// The coders take any implementation, so models from outside the crate,
+/// such as a fixed table or the quantized output of a neural predictor,
+/// code without a [`Model`]:
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use arcode::bitbit::{BitReader, BitWriter, MSB};
+/// use arcode::{ArithmeticDecoder, ArithmeticEncoder, SourceModel};
+///
+/// /// Symbol `s` of `n` has a count of `s + 1`.
+/// struct Triangular(u32);
+///
+/// impl SourceModel for Triangular {
+///     fn num_symbols(&self) -> u32 {
+///         self.0
+///     }
+///
+///     fn total_count(&self) -> u32 {
+///         self.0 * (self.0 + 1) / 2
+///     }
+///
+///     fn eof(&self) -> u32 {
+///         self.0
+///     }
+///
+///     fn cumulative(&self, symbol: u32) -> (u32, u32) {
+///         (symbol * (symbol + 1) / 2, (symbol + 1) * (symbol + 2) / 2)
+///     }
+///
+///     fn symbol_at(&self, count: u32) -> u32 {
+///         (0..self.0).find(|&symbol| count < self.cumulative(symbol).1).unwrap()
+///     }
+/// }
+///
+/// let model = Triangular(10);
+/// let mut encoder = ArithmeticEncoder::new(32);
+/// let mut output = BitWriter::new(Cursor::new(vec![]));
+/// for &symbol in &[9, 3, 0, 9] {
+///     encoder.encode(symbol, &model, &mut output).unwrap();
+/// }
+/// encoder.finish_encode(&mut output).unwrap();
+/// output.pad_to_byte().unwrap();
+///
+/// let compressed = output.get_ref().get_ref();
+/// let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+/// let mut decoder = ArithmeticDecoder::new(32);
+/// for &symbol in &[9, 3, 0, 9] {
+///     assert_eq!(decoder.decode(&model, &mut input).unwrap(), symbol);
+/// }
+/// ```
+pub trait SourceModel {
+    fn num_symbols(&self) -> u32;
+
+    fn total_count(&self) -> u32;
+
+    /// The EOF symbol, or [`num_symbols`](Self::num_symbols) if there is
+    /// none.
+    fn eof(&self) -> u32;
+
+    /// The cumulative counts `[low, high)` of `symbol` out of
+    /// [`total_count`](Self::total_count).
+    fn cumulative(&self, symbol: u32) -> (u32, u32);
+
+    /// The symbol whose cumulative count interval contains `count`, which
+    /// must be less than [`total_count`](Self::total_count).
+    fn symbol_at(&self, count: u32) -> u32;
+
+    /// A symbol holding more than half of the total count, as
+    /// `(symbol, low, high)`, which the decoder tests before searching.
+    /// Models that do not track one return `None`.
+    fn dominant(&self) -> Option<(u32, u32, u32)> {
+        None
+    }
+
+    /// Errors if `symbol` is not a valid index into this model.
+    fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        if symbol < self.num_symbols() {
+            Ok(())
+        } else {
+            Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: self.num_symbols(),
+            })
+        }
+    }
+
+    /// Errors if `symbol` is out of range or has a count of 0, which the
+    /// coders can not code.
+    fn check_codable(&self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        let (low, high) = self.cumulative(symbol);
+        if low == high {
+            Err(ModelError::ZeroCount { symbol })
+        } else {
+            Ok(())
+        }
+    }
+}
 
 /// Symbol table for the encoder/decoder.
 /// Used to store the probabilities as a vector of counts
 /// (number of occurrences). Uniform would be every symbol has
 /// a count of 0.
+#[derive(Clone)]
 pub struct Model {
     counts: Vec<u32>,
     fenwick_counts: Vec<u32>,
     total_count: u32,
     eof: u32,
     num_symbols: u32,
+    controls: Vec<(ControlKind, u32)>,
+    /// The symbol with the largest count, and the sum of the counts before
+    /// it, tracked with every update for [`dominant`](Self::dominant).
+    dominant: u32,
+    dominant_low: u32,
+    /// See [`Builder::batch`].
+    batch: Option<u32>,
+    /// Updates not yet folded into the counts.
+    pending: Vec<u32>,
+    /// See [`Builder::rescale_at`].
+    rescale_at: Option<u32>,
+    /// See [`Builder::increment`].
+    increment: u32,
+    /// `(every, numerator, denominator)`, see [`Builder::decay`].
+    decay: Option<(u32, u32, u32)>,
+    /// Updates since the counts last decayed.
+    since_decay: u32,
+    /// See [`freeze`](Self::freeze).
+    frozen: bool,
+    #[cfg(feature = "stats")]
+    stats: ModelStats,
 }
 
 impl Model {
@@ -29,19 +190,373 @@ impl Model {
         total_count: u32,
         eof: u32,
     ) -> Self {
-        Self {
+        let mut model = Self {
             num_symbols: counts.len() as u32,
             counts,
             fenwick_counts,
             total_count,
             eof,
-        }
+            controls: Vec::new(),
+            dominant: 0,
+            dominant_low: 0,
+            batch: None,
+            pending: Vec::new(),
+            rescale_at: None,
+            increment: 1,
+            decay: None,
+            since_decay: 0,
+            frozen: false,
+            #[cfg(feature = "stats")]
+            stats: ModelStats::default(),
+        };
+        model.find_dominant();
+        model
     }
 
+    /// Adds the [`increment`](Self::increment) to `symbol`'s count, unless
+    /// the model is [frozen](Self::freeze).
     pub fn update_symbol(&mut self, symbol: u32) {
-        self.total_count += 1;
-        self.counts[symbol as usize] += 1;
-        update(&mut self.fenwick_counts, symbol as usize, 1);
+        #[cfg(feature = "stats")]
+        self.stats
+            .record(self.counts[symbol as usize], self.total_count);
+        if self.frozen {
+            return;
+        }
+        if let Some(batch) = self.batch {
+            self.pending.push(symbol);
+            if self.pending.len() >= batch as usize {
+                self.flush_updates()
+                    .expect("the batched updates overflow the total count");
+            }
+            return;
+        }
+        self.apply_update(symbol);
+    }
+
+    fn apply_update(&mut self, symbol: u32) {
+        if let Some(max_total) = self.rescale_at {
+            if self.total_count > max_total.saturating_sub(self.increment) {
+                self.halve();
+            }
+        }
+        self.total_count += self.increment;
+        self.counts[symbol as usize] += self.increment;
+        update(&mut self.fenwick_counts, symbol as usize, self.increment);
+
+        if symbol < self.dominant {
+            self.dominant_low += self.increment;
+        }
+        if self.counts[symbol as usize] > self.counts[self.dominant as usize] {
+            self.dominant = symbol;
+            self.dominant_low = self.cumulative(symbol).0;
+        }
+
+        if let Some((every, numerator, denominator)) = self.decay {
+            self.since_decay += 1;
+            if self.since_decay >= every {
+                self.since_decay = 0;
+                self.scale_counts(numerator, denominator);
+            }
+        }
+    }
+
+    /// Folds the updates a [batching](Builder::batch) model has queued into
+    /// its counts. Encoder and decoder must flush at the same point of the
+    /// stream, if at all. Errors, leaving the updates queued, if they would
+    /// overflow the total count.
+    pub fn flush_updates(&mut self) -> Result<(), ModelError> {
+        if self.rescale_at.is_some() || self.decay.is_some() {
+            // rescaling and decay depend on the total after every update
+            let pending = std::mem::take(&mut self.pending);
+            pending.iter().for_each(|&symbol| self.apply_update(symbol));
+            self.pending = pending;
+        } else if !self.pending.is_empty() {
+            let total_count = u32::try_from(self.pending.len())
+                .ok()
+                .and_then(|updates| self.increment.checked_mul(updates))
+                .and_then(|added| self.total_count.checked_add(added))
+                .ok_or(ModelError::CountOverflow)?;
+            let pending = std::mem::take(&mut self.pending);
+            for &symbol in &pending {
+                self.counts[symbol as usize] += self.increment;
+            }
+            self.total_count = total_count;
+            // rebuilding in one pass beats a descent per update once the
+            // batch is large for the alphabet
+            if pending.len() >= self.counts.len() / 8 {
+                self.fenwick_counts = build_fenwick(&self.counts);
+            } else {
+                for &symbol in &pending {
+                    update(&mut self.fenwick_counts, symbol as usize, self.increment);
+                }
+            }
+            for &symbol in &pending {
+                if self.counts[symbol as usize] > self.counts[self.dominant as usize] {
+                    self.dominant = symbol;
+                }
+            }
+            self.dominant_low = self.cumulative(self.dominant).0;
+            self.pending = pending;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// The updates a [batching](Builder::batch) model has queued but not
+    /// yet folded into its counts.
+    pub fn pending_updates(&self) -> &[u32] {
+        &self.pending
+    }
+
+    /// See [`Builder::batch`].
+    pub const fn batch(&self) -> Option<u32> {
+        self.batch
+    }
+
+    /// Halves every count, rounding up so non-zero counts stay non-zero.
+    fn halve(&mut self) {
+        self.scale_counts(1, 2);
+    }
+
+    /// Multiplies every count by `numerator / denominator`, at most 1,
+    /// rounding up so non-zero counts stay non-zero.
+    fn scale_counts(&mut self, numerator: u32, denominator: u32) {
+        let (numerator, denominator) = (u64::from(numerator), u64::from(denominator));
+        let counts = self
+            .counts
+            .iter()
+            .map(|&count| ((u64::from(count) * numerator + denominator - 1) / denominator) as u32)
+            .collect();
+        self.replace_counts(counts);
+    }
+
+    /// Replaces every count at once, rebuilding the Fenwick tree in one
+    /// linear pass. Errors if `counts` is not one per symbol or they sum
+    /// past `u32::MAX`, leaving the model unchanged.
+    pub fn set_counts(&mut self, counts: &[u32]) -> Result<(), ModelError> {
+        if counts.len() != self.counts.len() {
+            return Err(ModelError::CountsLength {
+                counts: counts.len(),
+                num_symbols: self.num_symbols,
+            });
+        }
+        counts
+            .iter()
+            .try_fold(0u32, |total, &count| total.checked_add(count))
+            .ok_or(ModelError::CountOverflow)?;
+        self.replace_counts(counts.to_vec());
+        Ok(())
+    }
+
+    /// Sets the count of `symbol`, updating the Fenwick tree in place.
+    /// Errors if `symbol` is out of range or the total would pass
+    /// `u32::MAX`.
+    pub fn set_count(&mut self, symbol: u32, count: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        let index = symbol as usize;
+        let old = self.counts[index];
+        let total_count = (self.total_count - old)
+            .checked_add(count)
+            .ok_or(ModelError::CountOverflow)?;
+        for node in up(index, self.fenwick_counts.len()) {
+            self.fenwick_counts[node] = self.fenwick_counts[node] - old + count;
+        }
+        self.counts[index] = count;
+        self.total_count = total_count;
+
+        let dominant_count = self.counts[self.dominant as usize];
+        if symbol == self.dominant && count < old {
+            self.find_dominant();
+        } else if symbol != self.dominant
+            && (count > dominant_count || (count == dominant_count && symbol < self.dominant))
+        {
+            self.dominant = symbol;
+            self.dominant_low = self.cumulative(symbol).0;
+        } else if symbol < self.dominant {
+            self.dominant_low = self.dominant_low - old + count;
+        }
+        Ok(())
+    }
+
+    /// Adds the counts of `other`, for models trained on different corpora
+    /// or threads. The same as [`mix`](Self::mix) with weights of 1.
+    pub fn merge(&mut self, other: &Model) -> Result<(), ModelError> {
+        self.mix(other, 1, 1)
+    }
+
+    /// Replaces every count with `self_weight * count + other_weight *
+    /// other_count`, such as to blend a generic prior into a domain model.
+    /// Integer weights keep the mix exact, so both sides of a stream
+    /// compute the same table; [`normalize`](Self::normalize) scales the
+    /// result back down. Errors if the models have different numbers of
+    /// symbols or the total would pass `u32::MAX`, leaving the model
+    /// unchanged.
+    pub fn mix(
+        &mut self,
+        other: &Model,
+        self_weight: u32,
+        other_weight: u32,
+    ) -> Result<(), ModelError> {
+        if other.num_symbols != self.num_symbols {
+            return Err(ModelError::CountsLength {
+                counts: other.counts.len(),
+                num_symbols: self.num_symbols,
+            });
+        }
+        let counts = self
+            .counts
+            .iter()
+            .zip(&other.counts)
+            .map(|(&count, &other_count)| {
+                let mixed = u64::from(self_weight) * u64::from(count)
+                    + u64::from(other_weight) * u64::from(other_count);
+                u32::try_from(mixed).map_err(|_| ModelError::CountOverflow)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.set_counts(&counts)
+    }
+
+    /// Replaces the counts, rebuilding everything derived from them.
+    fn replace_counts(&mut self, counts: Vec<u32>) {
+        self.fenwick_counts = build_fenwick(&counts);
+        self.total_count = counts.iter().sum();
+        self.counts = counts;
+        self.find_dominant();
+    }
+
+    /// The total count at which [`update_symbol`](Self::update_symbol)
+    /// halves the counts, see [`Builder::rescale_at`].
+    pub const fn rescale_at(&self) -> Option<u32> {
+        self.rescale_at
+    }
+
+    /// How much [`update_symbol`](Self::update_symbol) adds to a count, see
+    /// [`Builder::increment`].
+    pub const fn increment(&self) -> u32 {
+        self.increment
+    }
+
+    /// `(every, numerator, denominator)`, see [`Builder::decay`].
+    pub const fn decay(&self) -> Option<(u32, u32, u32)> {
+        self.decay
+    }
+
+    /// Stops adapting: [`update_symbol`](Self::update_symbol) leaves the
+    /// counts as they are until [`unfreeze`](Self::unfreeze), so a model
+    /// trained over a header can code the payload as a static model.
+    /// Encoder and decoder must freeze at the same point of the stream.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// Resumes adapting after [`freeze`](Self::freeze).
+    pub fn unfreeze(&mut self) {
+        self.frozen = false;
+    }
+
+    pub const fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Whether [`revert_symbol`](Self::revert_symbol) can undo updates, which
+    /// it can not across a rescale, decay or batch.
+    pub(crate) const fn is_revertible(&self) -> bool {
+        self.rescale_at.is_none() && self.decay.is_none() && self.batch.is_none()
+    }
+
+    /// Undoes a previous [`update_symbol`](Self::update_symbol) of `symbol`,
+    /// see [`is_revertible`](Self::is_revertible).
+    pub(crate) fn revert_symbol(&mut self, symbol: u32) {
+        if self.frozen {
+            return;
+        }
+        let index = symbol as usize;
+        self.counts[index] -= self.increment;
+        for node in up(index, self.fenwick_counts.len()) {
+            self.fenwick_counts[node] -= self.increment;
+        }
+        self.total_count -= self.increment;
+
+        if symbol == self.dominant {
+            self.find_dominant();
+        } else if symbol < self.dominant {
+            self.dominant_low -= self.increment;
+        }
+    }
+
+    fn find_dominant(&mut self) {
+        let largest = self
+            .counts
+            .iter()
+            .enumerate()
+            .max_by_key(|&(index, &count)| (count, std::cmp::Reverse(index)));
+        if let Some((index, _)) = largest {
+            self.dominant = index as u32;
+            self.dominant_low = self.cumulative(self.dominant).0;
+        }
+    }
+
+    /// The symbol holding more than half of the total count, as
+    /// `(symbol, low, high)` with its cumulative counts, if there is one.
+    /// Tracked with every update, so this is constant time; the decoder
+    /// tests it before searching for the symbol.
+    pub fn dominant(&self) -> Option<(u32, u32, u32)> {
+        let count = *self.counts.get(self.dominant as usize)?;
+        if u64::from(count) * 2 > u64::from(self.total_count) {
+            Some((self.dominant, self.dominant_low, self.dominant_low + count))
+        } else {
+            None
+        }
+    }
+
+    /// Like [`update_symbol`](Self::update_symbol) but returns an error
+    /// instead of panicking when `symbol` is out of range or overflowing
+    /// the total count, and instead of doing nothing when the model is
+    /// [frozen](Self::freeze).
+    pub fn try_update_symbol(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        if self.frozen {
+            return Err(ModelError::Frozen);
+        }
+        // including the updates a batch has queued
+        let updates = self.pending.len() as u64 + 1;
+        let total = u64::from(self.total_count) + updates * u64::from(self.increment);
+        if total > u32::MAX.into() && self.rescale_at.is_none() {
+            return Err(ModelError::CountOverflow);
+        }
+        self.update_symbol(symbol);
+        Ok(())
+    }
+
+    /// The inverse of [`update_symbol`](Self::update_symbol): subtracts the
+    /// [`increment`](Self::increment) from the count of `symbol`, for
+    /// sliding windows and speculative updates. Errors instead of
+    /// underflowing when the count is smaller than the increment, and like
+    /// an update leaves a frozen model unchanged.
+    ///
+    /// It does not undo a rescale or decay the update triggered.
+    pub fn decrement_symbol(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        let count = self.counts[symbol as usize];
+        if count < self.increment {
+            return Err(ModelError::CountUnderflow { symbol });
+        }
+        if self.frozen {
+            return Ok(());
+        }
+        self.set_count(symbol, count - self.increment)
+    }
+
+    /// Errors if `symbol` is not a valid index into this model.
+    pub const fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        if symbol < self.num_symbols {
+            Ok(())
+        } else {
+            Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: self.num_symbols,
+            })
+        }
     }
 
     pub const fn num_symbols(&self) -> u32 {
@@ -60,18 +575,107 @@ impl Model {
     }
 
     pub fn probability(&self, symbol: u32) -> (f64, f64) {
-        let total = f64::from(self.total_count);
+        let (low, high, total) = self.probability_counts(symbol);
+        let total = f64::from(total);
+        (f64::from(low) / total, f64::from(high) / total)
+    }
+
+    /// [`probability`](Self::probability) as the exact fractions
+    /// `low / total` and `high / total`, returned as `(low, high, total)`.
+    /// These are the integers the coder works with, for coders built on top
+    /// of `Model` that have to agree with it bit for bit.
+    pub fn probability_counts(&self, symbol: u32) -> (u32, u32, u32) {
+        let (low, high) = self.cumulative(symbol);
+        (low, high, self.total_count)
+    }
+
+    /// Number of bits (`-log2(p)`) coding `symbol` takes under the current
+    /// counts, ignoring the coder's rounding. A symbol with a count of 0 can
+    /// not be coded and costs infinitely many bits. See
+    /// [`cost`](crate::cost) for a fixed-point version that is identical on
+    /// every platform.
+    ///
+    /// # Panics
+    /// If `symbol` is not less than [`num_symbols`](Self::num_symbols).
+    pub fn cost_bits(&self, symbol: u32) -> f64 {
+        let count = self.counts[symbol as usize];
+        (f64::from(self.total_count) / f64::from(count)).log2()
+    }
 
+    /// The cumulative counts `[low, high)` of `symbol` out of
+    /// [`total_count`](Self::total_count), which the coder scales to its
+    /// range with integer arithmetic only.
+    pub fn cumulative(&self, symbol: u32) -> (u32, u32) {
         let high = prefix_sum(&self.fenwick_counts, symbol as usize);
-        let low = high - self.counts[symbol as usize];
+        (high - self.counts[symbol as usize], high)
+    }
 
+    /// Like [`probability`](Self::probability) but as if the symbols in
+    /// `exclusions` had a count of 0. `exclusions` must be sorted without
+    /// duplicates. An excluded `symbol` gets an empty interval.
+    pub fn probability_excluding(&self, symbol: u32, exclusions: &[u32]) -> (f64, f64) {
+        let (low, high, total) = self.cumulative_excluding(symbol, exclusions);
+        let total = f64::from(total);
         (f64::from(low) / total, f64::from(high) / total)
     }
 
+    /// Like [`cumulative`](Self::cumulative) but as if the symbols in
+    /// `exclusions` had a count of 0. Returns `(low, high, total)`.
+    pub fn cumulative_excluding(&self, symbol: u32, exclusions: &[u32]) -> (u32, u32, u32) {
+        let (mut low, mut high) = self.cumulative(symbol);
+        let mut total = self.total_count;
+
+        for &excluded in exclusions {
+            let count = self.counts[excluded as usize];
+            total -= count;
+            if excluded < symbol {
+                low -= count;
+            }
+            if excluded <= symbol {
+                high -= count;
+            }
+        }
+
+        (low, high, total)
+    }
+
     pub const fn eof(&self) -> u32 {
         self.eof
     }
 
+    /// The symbol reserved for `kind`, if the model was built with it.
+    pub fn control(&self, kind: ControlKind) -> Option<u32> {
+        self.controls
+            .iter()
+            .find(|(control, _)| *control == kind)
+            .map(|&(_, symbol)| symbol)
+    }
+
+    /// The kind of control symbol `symbol` is, if any.
+    pub fn control_kind(&self, symbol: u32) -> Option<ControlKind> {
+        self.controls
+            .iter()
+            .find(|&&(_, control)| control == symbol)
+            .map(|&(kind, _)| kind)
+    }
+
+    /// Whether `symbol` is the EOF or a control symbol.
+    pub fn is_reserved(&self, symbol: u32) -> bool {
+        symbol == self.eof || self.control_kind(symbol).is_some()
+    }
+
+    /// Prediction quality statistics of the symbols this model was updated
+    /// with.
+    #[cfg(feature = "stats")]
+    pub const fn stats(&self) -> &ModelStats {
+        &self.stats
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = ModelStats::default();
+    }
+
     pub const fn counts(&self) -> &Vec<u32> {
         &self.counts
     }
@@ -83,11 +687,387 @@ impl Model {
     pub const fn total_count(&self) -> u32 {
         self.total_count
     }
+
+    /// Cumulative counts: `cdf[i]` is the sum of the counts of the symbols
+    /// before `i`, so symbol `i` occupies `[cdf[i], cdf[i + 1])` and the last
+    /// entry is the total count. Has `num_symbols + 1` entries.
+    pub fn cdf(&self) -> Vec<u32> {
+        let mut cdf = Vec::with_capacity(self.counts.len() + 1);
+        let mut sum = 0;
+        cdf.push(sum);
+        for &count in &self.counts {
+            sum += count;
+            cdf.push(sum);
+        }
+        cdf
+    }
+
+    /// Rescales the counts so the total is exactly `2^bits`, which lets the
+    /// coder scale intervals with shifts instead of divisions. Every symbol
+    /// with a non-zero count keeps a count of at least 1, and symbols with a
+    /// count of 0 stay uncodable. Updating the model afterwards moves the
+    /// total off the power of two, so adaptive users normalize again
+    /// periodically.
+    pub fn normalize(&mut self, bits: u32) -> Result<(), ModelError> {
+        if bits >= 32 {
+            return Err(ModelError::CountOverflow);
+        }
+        let target = 1u32 << bits;
+        let symbols = self.counts.iter().filter(|&&count| count > 0).count() as u32;
+        if symbols > target || self.total_count == 0 {
+            return Err(ModelError::NormalizeTooSmall {
+                symbols,
+                total: target,
+            });
+        }
+
+        let total = u64::from(self.total_count);
+        let mut counts: Vec<u32> = self
+            .counts
+            .iter()
+            .map(|&count| match count {
+                0 => 0,
+                count => ((u64::from(count) << bits) / total).max(1) as u32,
+            })
+            .collect();
+
+        // rounding leaves the sum off the target, settle it on the largest
+        // counts, which it distorts the least
+        let mut sum: u32 = counts.iter().sum();
+        while sum != target {
+            let (largest, &count) = counts
+                .iter()
+                .enumerate()
+                .max_by_key(|&(index, &count)| (count, std::cmp::Reverse(index)))
+                .unwrap();
+            if sum < target {
+                counts[largest] += target - sum;
+                sum = target;
+            } else {
+                let taken = (sum - target).min(count - 1);
+                counts[largest] -= taken;
+                sum -= taken;
+            }
+        }
+
+        self.replace_counts(counts);
+        Ok(())
+    }
+
+    /// Draws a symbol with the probability the model gives it. `rng` must
+    /// return uniformly distributed `u64`s; any generator works, e.g.
+    /// `|| rng.gen()` with the `rand` crate.
+    pub fn sample<R: FnMut() -> u64>(&self, rng: &mut R) -> u32 {
+        // scale to [0, total) without modulo bias
+        let target = ((u128::from(rng()) * u128::from(self.total_count)) >> 64) as u32;
+
+        self.symbol_at(target)
+    }
+
+    /// The symbol whose cumulative count interval contains `count`, found
+    /// by descending the Fenwick tree in a single `O(log n)` pass. Symbols
+    /// with a count of 0 are never returned. `count` must be less than
+    /// [`total_count`](Self::total_count).
+    pub fn symbol_at(&self, count: u32) -> u32 {
+        debug_assert!(count < self.total_count);
+        descend(&self.fenwick_counts, count)
+    }
+
+    /// The inverse of [`cumulative`](Self::cumulative): the symbol whose
+    /// interval `[low, high)` contains `count`, or `None` if `count` is not
+    /// below [`total_count`](Self::total_count). A checked
+    /// [`symbol_at`](Self::symbol_at) for counts that come from outside.
+    pub fn symbol_from_cumulative(&self, count: u32) -> Option<u32> {
+        if count < self.total_count {
+            Some(self.symbol_at(count))
+        } else {
+            None
+        }
+    }
+}
+
+/// The Fenwick tree of `counts` in one pass, each node adding itself to its
+/// parent.
+fn build_fenwick(counts: &[u32]) -> Vec<u32> {
+    let mut fenwick_counts = counts.to_vec();
+    for index in 0..fenwick_counts.len() {
+        let parent = index | (index + 1);
+        if parent < fenwick_counts.len() {
+            fenwick_counts[parent] += fenwick_counts[index];
+        }
+    }
+    fenwick_counts
+}
+
+/// The index whose cumulative count interval in the Fenwick tree
+/// `fenwick_counts` contains `count`, in a single descent.
+fn descend(fenwick_counts: &[u32], mut count: u32) -> u32 {
+    let len = fenwick_counts.len();
+    // number of leading symbols whose counts sum to at most `count`
+    let mut position = 0;
+    // the largest power of two not above `len`
+    let mut step = (len + 1).next_power_of_two() / 2;
+    while step > 0 {
+        let next = position + step;
+        if next <= len && fenwick_counts[next - 1] <= count {
+            count -= fenwick_counts[next - 1];
+            position = next;
+        }
+        step >>= 1;
+    }
+    position as u32
+}
+
+impl SourceModel for Model {
+    fn num_symbols(&self) -> u32 {
+        self.num_symbols
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        Self::cumulative(self, symbol)
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        Self::symbol_at(self, count)
+    }
+
+    fn dominant(&self) -> Option<(u32, u32, u32)> {
+        Self::dominant(self)
+    }
+
+    fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        Self::check_symbol(self, symbol)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{EOFKind, Model};
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{ControlKind, EOFKind, Model, SourceModel};
+    use crate::{error::ModelError, ArithmeticDecoder, ArithmeticEncoder};
+
+    /// Asserts `model` gives every symbol and count the interval `reference`
+    /// does, so the two code identically.
+    pub(super) fn assert_same_intervals<M: SourceModel + ?Sized>(model: &M, reference: &Model) {
+        assert_eq!(model.num_symbols(), reference.num_symbols());
+        assert_eq!(model.total_count(), reference.total_count());
+        assert_eq!(model.eof(), reference.eof());
+        for symbol in 0..reference.num_symbols() {
+            assert_eq!(model.cumulative(symbol), reference.cumulative(symbol));
+        }
+        for count in 0..reference.total_count() {
+            assert_eq!(model.symbol_at(count), reference.symbol_at(count));
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let symbols: Vec<u32> = (0..500u32).map(|i| (i * i) % 37 % 9).collect();
+        let mut model = Model::builder().num_symbols(9).batch(16).build();
+        let mut reference = Model::builder().num_symbols(9).build();
+        assert_eq!(model.batch(), Some(16));
+        for (i, &symbol) in symbols.iter().enumerate() {
+            model.update_symbol(symbol);
+            assert_eq!(model.pending_updates().len(), (i + 1) % 16);
+            if model.pending_updates().is_empty() {
+                // a fold catches up with updating every symbol
+                symbols[i + 1 - 16..=i]
+                    .iter()
+                    .for_each(|&symbol| reference.update_symbol(symbol));
+                assert_eq!(model.counts(), reference.counts());
+                assert_eq!(model.fenwick_counts(), reference.fenwick_counts());
+                assert_eq!(model.total_count(), reference.total_count());
+                assert_eq!(model.dominant(), reference.dominant());
+            }
+        }
+        model.flush_updates().unwrap();
+        assert_eq!(model.total_count(), 9 + 500);
+
+        // a flush that would overflow the total keeps its updates queued
+        let mut full = Model::builder()
+            .counts(vec![u32::MAX - 2, 1])
+            .batch(4)
+            .build();
+        (0..3).for_each(|_| full.update_symbol(1));
+        assert_eq!(full.flush_updates(), Err(ModelError::CountOverflow));
+        assert_eq!(full.pending_updates(), &[1, 1, 1]);
+        assert_eq!(full.total_count(), u32::MAX - 1);
+        assert_eq!(Model::builder().batch(1).build().batch(), None);
+
+        // a fold can not be taken back symbol by symbol
+        assert!(!model.is_revertible());
+        assert!(Model::builder().batch(1).build().is_revertible());
+
+        // a skewed stream over a large alphabet, through the coders
+        let mut model = Model::builder()
+            .num_symbols(5000)
+            .eof(EOFKind::EndAddOne)
+            .batch(64)
+            .build();
+        let symbols: Vec<u32> = (0..3000u32).map(|i| (i % 7) * (i % 11) * 50).collect();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        let initial = model.clone();
+        for &symbol in &symbols {
+            encoder.encode(symbol, &model, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.encode(model.eof(), &model, &mut output).unwrap();
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+
+        let mut model = initial;
+        let mut decoder = ArithmeticDecoder::new(32);
+        let compressed = output.get_ref().get_ref();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &symbol in &symbols {
+            assert_eq!(decoder.decode(&model, &mut input).unwrap(), symbol);
+            model.update_symbol(symbol);
+        }
+        assert_eq!(decoder.decode(&model, &mut input).unwrap(), model.eof());
+    }
+
+    #[test]
+    fn cost_bits() {
+        let model = Model::builder().counts(vec![1, 3, 0, 4]).build();
+        assert_eq!(model.cost_bits(0), 3.0);
+        assert_eq!(model.cost_bits(3), 1.0);
+        assert!((model.cost_bits(1) - (8.0f64 / 3.0).log2()).abs() < 1e-12);
+        assert_eq!(model.cost_bits(2), f64::INFINITY);
+    }
+
+    #[test]
+    #[should_panic]
+    fn cost_bits_out_of_range() {
+        Model::builder().num_symbols(4).build().cost_bits(4);
+    }
+
+    #[test]
+    fn probability_counts() {
+        let model = Model::builder().counts(vec![1, 3, 0, 4]).build();
+        assert_eq!(model.probability_counts(0), (0, 1, 8));
+        assert_eq!(model.probability_counts(1), (1, 4, 8));
+        assert_eq!(model.probability_counts(2), (4, 4, 8));
+        assert_eq!(model.probability_counts(3), (4, 8, 8));
+        assert_eq!(model.probability(1), (0.125, 0.5));
+    }
+
+    #[test]
+    fn symbol_from_cumulative() {
+        let model = Model::builder().counts(vec![1, 3, 0, 4]).build();
+        let symbols: Vec<_> = (0..9)
+            .map(|count| model.symbol_from_cumulative(count))
+            .collect();
+        assert_eq!(
+            symbols,
+            [0, 1, 1, 1, 3, 3, 3, 3]
+                .iter()
+                .map(|&symbol| Some(symbol))
+                .chain(Some(None))
+                .collect::<Vec<_>>()
+        );
+        for count in 0..8 {
+            let (low, high) = model.cumulative(model.symbol_from_cumulative(count).unwrap());
+            assert!((low..high).contains(&count));
+        }
+    }
+
+    #[test]
+    fn rescale() {
+        let mut model = Model::builder()
+            .counts(vec![5, 0, 1, 2])
+            .rescale_at(10)
+            .build();
+        model.update_symbol(0);
+        model.update_symbol(0);
+        assert_eq!(model.total_count(), 10);
+        // the next update would pass the maximum
+        model.update_symbol(3);
+        assert_eq!(model.counts(), &vec![4, 0, 1, 2]);
+        assert_eq!(model.total_count(), 7);
+        assert_eq!(model.fenwick_counts(), &vec![4, 4, 1, 7]);
+        assert_eq!(model.dominant(), Some((0, 0, 4)));
+
+        // never past the maximum, and never overflowing
+        let mut model = Model::builder().num_symbols(2).rescale_at(u32::MAX).build();
+        model.set_counts(&[u32::MAX - 1, 1]).unwrap();
+        model.try_update_symbol(1).unwrap();
+        model.try_update_symbol(1).unwrap();
+        assert_eq!(model.counts(), &vec![u32::MAX / 2, 3]);
+    }
+
+    #[test]
+    fn increment() {
+        let mut model = Model::builder().num_symbols(3).increment(32).build();
+        model.update_symbol(2);
+        model.update_symbol(0);
+        model.update_symbol(2);
+        assert_eq!(model.counts(), &vec![33, 1, 65]);
+        assert_eq!(model.total_count(), 99);
+        assert_eq!(model.dominant(), Some((2, 34, 99)));
+        model.revert_symbol(2);
+        assert_eq!(model.counts(), &vec![33, 1, 33]);
+        assert_eq!(model.fenwick_counts(), &vec![33, 34, 33]);
+
+        let mut model = Model::builder()
+            .num_symbols(2)
+            .increment(32)
+            .rescale_at(100)
+            .build();
+        for _ in 0..10 {
+            model.update_symbol(1);
+            assert!(model.total_count() <= 100);
+        }
+        let mut model = Model::builder().num_symbols(2).increment(8).build();
+        model.set_counts(&[u32::MAX - 6, 1]).unwrap();
+        assert_eq!(model.try_update_symbol(0), Err(ModelError::CountOverflow));
+    }
+
+    #[test]
+    fn decay() {
+        let mut model = Model::builder()
+            .counts(vec![40, 0, 7, 1])
+            .decay(3, 1, 4)
+            .build();
+        model.update_symbol(2);
+        model.update_symbol(2);
+        assert_eq!(model.counts(), &vec![40, 0, 9, 1]);
+        model.update_symbol(3);
+        assert_eq!(model.counts(), &vec![10, 0, 3, 1]);
+        assert_eq!(model.total_count(), 14);
+        assert_eq!(model.fenwick_counts(), &vec![10, 10, 3, 14]);
+        for _ in 0..3 {
+            model.update_symbol(3);
+        }
+        assert_eq!(model.counts(), &vec![3, 0, 1, 1]);
+    }
+
+    #[test]
+    fn freeze() {
+        let mut model = Model::builder().num_symbols(3).build();
+        model.update_symbol(1);
+        model.freeze();
+        assert!(model.is_frozen());
+        model.update_symbol(1);
+        assert_eq!(model.try_update_symbol(2), Err(ModelError::Frozen));
+        model.revert_symbol(1);
+        assert_eq!(model.counts(), &vec![1, 2, 1]);
+        model.unfreeze();
+        model.update_symbol(2);
+        assert_eq!(model.counts(), &vec![1, 2, 2]);
+        assert_eq!(model.fenwick_counts(), &vec![1, 3, 2]);
+    }
 
     #[test]
     fn constructor() {
@@ -218,4 +1198,286 @@ mod tests {
         assert_eq!(model.probability(2), (0.3, 0.7));
         assert_eq!(model.probability(3), (0.7, 1.0));
     }
+
+    #[test]
+    fn cdf() {
+        let mut model = Model::builder().counts(vec![3, 1, 4, 1]).build();
+        model.update_symbol(1);
+        let cdf = model.cdf();
+
+        assert_eq!(cdf, [0, 3, 5, 9, 10]);
+        for symbol in 0..model.num_symbols() {
+            let (low, high) = model.probability(symbol);
+            assert_eq!(low, f64::from(cdf[symbol as usize]) / 10.0);
+            assert_eq!(high, f64::from(cdf[symbol as usize + 1]) / 10.0);
+        }
+    }
+
+    #[test]
+    fn control_symbols() {
+        let model = Model::builder()
+            .num_symbols(4)
+            .eof(EOFKind::End)
+            .control(ControlKind::ModelSwitch)
+            .control(ControlKind::Custom(7))
+            .build();
+
+        assert_eq!(model.num_symbols(), 6);
+        assert_eq!(model.control(ControlKind::ModelSwitch), Some(4));
+        assert_eq!(model.control(ControlKind::Custom(7)), Some(5));
+        assert_eq!(model.control(ControlKind::Flush), None);
+        assert_eq!(model.control_kind(5), Some(ControlKind::Custom(7)));
+        assert_eq!(model.control_kind(2), None);
+        assert!(model.is_reserved(3));
+        assert!(model.is_reserved(4));
+        assert!(!model.is_reserved(0));
+    }
+
+    #[test]
+    fn probability_excluding() {
+        let model = Model::builder().counts(vec![1, 2, 3, 4]).build();
+
+        assert_eq!(model.probability_excluding(2, &[]), model.probability(2));
+        assert_eq!(model.probability_excluding(0, &[2]), (0.0, 1.0 / 7.0));
+        assert_eq!(model.probability_excluding(3, &[0, 2]), (2.0 / 6.0, 1.0));
+        assert_eq!(model.probability_excluding(1, &[3]), (1.0 / 6.0, 3.0 / 6.0));
+        assert_eq!(model.probability_excluding(1, &[1]), (1.0 / 8.0, 1.0 / 8.0));
+
+        assert_eq!(model.cumulative(2), (3, 6));
+        assert_eq!(model.cumulative_excluding(3, &[0, 2]), (2, 6, 6));
+        assert_eq!(model.cumulative_excluding(1, &[1]), (1, 1, 8));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let mut model = Model::builder().counts(vec![1, 3]).build();
+        assert_eq!(model.stats().cross_entropy(), 0.0);
+
+        // coded with probabilities 3/4 and 1/5
+        model.update_symbol(1);
+        model.update_symbol(0);
+
+        let stats = model.stats();
+        assert_eq!(stats.symbols(), 2);
+        assert!((stats.average_probability() - 0.475).abs() < 1e-9);
+        let expected = -((0.75f64).log2() + (0.2f64).log2()) / 2.0;
+        assert!((stats.cross_entropy() - expected).abs() < 1e-3);
+
+        model.reset_stats();
+        assert_eq!(model.stats().symbols(), 0);
+    }
+
+    #[test]
+    fn sample() {
+        let model = Model::builder().counts(vec![1, 0, 6, 3]).build();
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut rng = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut histogram = [0u32; 4];
+        for _ in 0..10_000 {
+            histogram[model.sample(&mut rng) as usize] += 1;
+        }
+        assert_eq!(histogram[1], 0);
+        for (&observed, &expected) in histogram.iter().zip(&[1_000, 0, 6_000, 3_000]) {
+            assert!((observed as i32 - expected).abs() < 200, "{:?}", histogram);
+        }
+
+        assert_eq!(model.sample(&mut || 0), 0);
+        assert_eq!(model.sample(&mut || u64::MAX), 3);
+    }
+
+    #[test]
+    fn normalize() {
+        let mut model = Model::builder().counts(vec![5, 0, 90, 1, 4]).build();
+        model.normalize(4).unwrap();
+        assert_eq!(model.counts(), &vec![1, 0, 13, 1, 1]);
+        assert_eq!(model.total_count(), 16);
+        assert_eq!(model.cumulative(4), (15, 16));
+
+        let mut model = Model::builder().counts(vec![1, 1, 1]).build();
+        model.normalize(10).unwrap();
+        assert_eq!(model.counts(), &vec![342, 341, 341]);
+
+        let mut model = Model::builder().num_symbols(5).build();
+        assert_eq!(
+            model.normalize(2),
+            Err(ModelError::NormalizeTooSmall {
+                symbols: 5,
+                total: 4
+            })
+        );
+        assert_eq!(model.normalize(32), Err(ModelError::CountOverflow));
+        assert_eq!(model.total_count(), 5);
+    }
+
+    #[test]
+    fn decrement_symbol() {
+        let mut model = Model::builder().counts(vec![2, 0, 1]).increment(2).build();
+        model.update_symbol(2);
+        model.update_symbol(0);
+        assert_eq!(model.counts(), &vec![4, 0, 3]);
+        model.decrement_symbol(0).unwrap();
+        model.decrement_symbol(2).unwrap();
+        assert_eq!(model.counts(), &vec![2, 0, 1]);
+        assert_eq!(model.fenwick_counts(), &vec![2, 2, 1]);
+        assert_eq!(model.total_count(), 3);
+        assert_eq!(model.dominant(), Some((0, 0, 2)));
+
+        assert_eq!(
+            model.decrement_symbol(2),
+            Err(ModelError::CountUnderflow { symbol: 2 })
+        );
+        assert_eq!(
+            model.decrement_symbol(1),
+            Err(ModelError::CountUnderflow { symbol: 1 })
+        );
+        assert!(model.decrement_symbol(3).is_err());
+        model.decrement_symbol(0).unwrap();
+        assert_eq!(model.counts(), &vec![0, 0, 1]);
+        assert_eq!(model.dominant(), Some((2, 0, 1)));
+
+        model.update_symbol(2);
+        model.freeze();
+        model.decrement_symbol(2).unwrap();
+        assert_eq!(model.counts(), &vec![0, 0, 3]);
+    }
+
+    #[test]
+    fn merge() {
+        let mut model = Model::builder().counts(vec![3, 0, 1, 2]).build();
+        let other = Model::builder().counts(vec![1, 5, 0, 2]).build();
+        model.merge(&other).unwrap();
+        assert_eq!(model.counts(), &vec![4, 5, 1, 4]);
+        assert_eq!(model.total_count(), 14);
+        assert_eq!(
+            model.fenwick_counts(),
+            Model::builder()
+                .counts(vec![4, 5, 1, 4])
+                .build()
+                .fenwick_counts()
+        );
+
+        model.mix(&other, 3, 2).unwrap();
+        assert_eq!(model.counts(), &vec![14, 25, 3, 16]);
+        assert_eq!(model.dominant(), None);
+        model.mix(&other, 0, 1).unwrap();
+        assert_eq!(model.counts(), other.counts());
+
+        let wide = Model::builder().num_symbols(5).build();
+        assert_eq!(
+            model.merge(&wide),
+            Err(ModelError::CountsLength {
+                counts: 5,
+                num_symbols: 4
+            })
+        );
+        assert_eq!(
+            model.mix(&other, u32::MAX, 1),
+            Err(ModelError::CountOverflow)
+        );
+        assert_eq!(model.counts(), other.counts());
+    }
+
+    #[test]
+    fn set_counts() {
+        let counts = [5, 0, 3, 9, 1, 1, 2, 7, 4, 0, 6];
+        let reference = Model::builder().counts(counts.to_vec()).build();
+        let mut model = Model::builder().num_symbols(11).build();
+        model.set_counts(&counts).unwrap();
+        assert_eq!(model.counts(), reference.counts());
+        assert_eq!(model.fenwick_counts(), reference.fenwick_counts());
+        assert_eq!(model.total_count(), reference.total_count());
+
+        assert_eq!(
+            model.set_counts(&[1, 2]),
+            Err(ModelError::CountsLength {
+                counts: 2,
+                num_symbols: 11
+            })
+        );
+        let mut overflowing = [1; 11];
+        overflowing[0] = u32::MAX;
+        assert_eq!(
+            model.set_counts(&overflowing),
+            Err(ModelError::CountOverflow)
+        );
+        assert_eq!(model.counts(), reference.counts());
+
+        let mut counts = counts.to_vec();
+        for &(symbol, count) in &[(3, 0), (7, 40), (0, 40), (7, 2), (0, 1), (10, 0)] {
+            model.set_count(symbol, count).unwrap();
+            counts[symbol as usize] = count;
+            let reference = Model::builder().counts(counts.clone()).build();
+            assert_eq!(model.fenwick_counts(), reference.fenwick_counts());
+            assert_eq!(model.total_count(), reference.total_count());
+            assert_eq!(model.dominant(), reference.dominant());
+        }
+        assert_eq!(model.set_count(4, u32::MAX), Err(ModelError::CountOverflow));
+        assert!(model.set_count(11, 1).is_err());
+    }
+
+    #[test]
+    fn dominant() {
+        let mut model = Model::builder().counts(vec![2, 1, 1, 3]).build();
+        assert_eq!(model.dominant(), None);
+
+        (0..8).for_each(|_| model.update_symbol(2));
+        assert_eq!(model.dominant(), Some((2, 3, 12)));
+        model.update_symbol(0);
+        model.update_symbol(3);
+        assert_eq!(model.dominant(), Some((2, 4, 13)));
+
+        model.revert_symbol(0);
+        model.revert_symbol(2);
+        assert_eq!(model.dominant(), Some((2, 3, 11)));
+        (0..3).for_each(|_| model.revert_symbol(2));
+        assert_eq!(model.dominant(), None);
+        (0..5).for_each(|_| model.update_symbol(3));
+        assert_eq!(model.dominant(), Some((3, 8, 17)));
+
+        model.normalize(4).unwrap();
+        let (symbol, low, high) = model.dominant().unwrap();
+        assert_eq!((symbol, low, high), (3, model.cumulative(3).0, 16));
+    }
+
+    #[test]
+    fn symbol_at() {
+        let model = Model::builder().counts(vec![1, 0, 6, 3, 0, 2, 1]).build();
+        let expected = [0, 2, 2, 2, 2, 2, 2, 3, 3, 3, 5, 5, 6];
+        for (count, &symbol) in expected.iter().enumerate() {
+            assert_eq!(model.symbol_at(count as u32), symbol);
+        }
+
+        let model = Model::builder().num_symbols(256).build();
+        for symbol in 0..256 {
+            let (low, high) = model.cumulative(symbol);
+            assert_eq!(model.symbol_at(low), symbol);
+            assert_eq!(model.symbol_at(high - 1), symbol);
+        }
+    }
+
+    #[test]
+    fn try_update_symbol() {
+        let mut model = Model::builder().num_symbols(4).build();
+
+        assert_eq!(model.try_update_symbol(3), Ok(()));
+        assert_eq!(
+            model.try_update_symbol(4),
+            Err(ModelError::SymbolOutOfRange {
+                symbol: 4,
+                num_symbols: 4
+            })
+        );
+        assert_eq!(model.counts(), &[1, 1, 1, 2]);
+
+        let mut full = Model::from_values(vec![u32::MAX, 0], vec![u32::MAX, u32::MAX], u32::MAX, 2);
+        assert_eq!(full.try_update_symbol(1), Err(ModelError::CountOverflow));
+        assert_eq!(full.counts(), &[u32::MAX, 0]);
+    }
 }
@@ -0,0 +1,259 @@
+use std::io::{Error, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+use fenwick::array::{prefix_sum, update};
+
+use crate::{
+    error::ModelError,
+    model::{descend, SourceModel},
+    ArithmeticDecoder, ArithmeticEncoder,
+};
+
+/// The exponent a counter saturates at, where it stands for a count of
+/// `2^16`.
+const MAX_EXPONENT: u8 = 208;
+
+/// The count an exponent stands for: exact below 16, then 16 steps per
+/// doubling, like a float with a 4-bit mantissa.
+const fn value(exponent: u8) -> u32 {
+    if exponent < 16 {
+        exponent as u32
+    } else {
+        (16 + (exponent & 15) as u32) << ((exponent >> 4) - 1)
+    }
+}
+
+/// An adaptive model of a massive alphabet in a fixed memory budget of five
+/// bytes per bucket, however many symbols it sees.
+///
+/// Every symbol hashes to one of `num_buckets` buckets, which is coded with
+/// the bucket's count, followed by the symbol's position among the bucket's
+/// symbols, uniformly. Each bucket counts in one byte with a Morris
+/// counter: past 16, an update increments it only with the probability of
+/// one over the step to the next value it can stand for. The coin flips
+/// come from a generator in the model that only
+/// [`update_symbol`](Self::update_symbol) advances, so an encoder and a
+/// decoder making the same updates compute identical approximations.
+///
+/// As a [`SourceModel`] its symbols are the buckets, each with a count of
+/// its counter's value plus 1 and a total of at most `2^32 - 1`, so the
+/// precision must be at least 34 and cover the bucket's symbols too.
+#[derive(Clone, Debug)]
+pub struct ApproximateModel {
+    alphabet_size: u32,
+    exponents: Vec<u8>,
+    fenwick_counts: Vec<u32>,
+    total_count: u32,
+    /// xorshift state.
+    state: u64,
+}
+
+impl ApproximateModel {
+    /// The largest number of buckets, whose saturated counts still sum
+    /// within a `u32`.
+    pub const MAX_BUCKETS: u32 = u32::MAX / ((1 << 16) + 1);
+
+    /// A model of the symbols `0..alphabet_size` in `num_buckets` buckets,
+    /// which must be between 1 and [`MAX_BUCKETS`](Self::MAX_BUCKETS) and at most
+    /// `alphabet_size`.
+    pub fn new(alphabet_size: u32, num_buckets: u32) -> Self {
+        assert!(
+            (1..=Self::MAX_BUCKETS.min(alphabet_size)).contains(&num_buckets),
+            "num_buckets must be between 1 and the smaller of MAX_BUCKETS and alphabet_size"
+        );
+        let mut fenwick_counts = vec![0; num_buckets as usize];
+        for bucket in 0..num_buckets as usize {
+            update(&mut fenwick_counts, bucket, 1);
+        }
+        Self {
+            alphabet_size,
+            exponents: vec![0; num_buckets as usize],
+            fenwick_counts,
+            total_count: num_buckets,
+            state: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub const fn alphabet_size(&self) -> u32 {
+        self.alphabet_size
+    }
+
+    pub fn num_buckets(&self) -> u32 {
+        self.exponents.len() as u32
+    }
+
+    /// The bucket `symbol` hashes to.
+    pub fn bucket(&self, symbol: u32) -> u32 {
+        symbol % self.num_buckets()
+    }
+
+    /// The number of symbols in `bucket`.
+    pub fn bucket_size(&self, bucket: u32) -> u32 {
+        (self.alphabet_size - bucket - 1) / self.num_buckets() + 1
+    }
+
+    /// The approximate number of updates of the symbols in `symbol`'s
+    /// bucket.
+    pub fn estimate(&self, symbol: u32) -> u32 {
+        value(self.exponents[self.bucket(symbol) as usize])
+    }
+
+    /// Errors if `symbol` is outside the alphabet.
+    pub fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        if symbol < self.alphabet_size {
+            Ok(())
+        } else {
+            Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: self.alphabet_size,
+            })
+        }
+    }
+
+    /// Counts `symbol` in its bucket's counter, which may stay unchanged.
+    pub fn update_symbol(&mut self, symbol: u32) {
+        let bucket = self.bucket(symbol) as usize;
+        let exponent = self.exponents[bucket];
+        if exponent == MAX_EXPONENT {
+            return;
+        }
+        let step = value(exponent + 1) - value(exponent);
+        if step > 1 && self.next_random() % u64::from(step) != 0 {
+            return;
+        }
+        self.exponents[bucket] += 1;
+        self.total_count += step;
+        update(&mut self.fenwick_counts, bucket, step);
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Encodes `symbol` as its bucket and its position in the bucket.
+    pub fn encode<W: Write>(
+        &self,
+        symbol: u32,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        self.check_symbol(symbol)?;
+        let bucket = self.bucket(symbol);
+        encoder.encode(bucket, self, output)?;
+        let size = self.bucket_size(bucket);
+        if size > 1 {
+            let position = symbol / self.num_buckets();
+            encoder.encode_interval(position, position + 1, size, output)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a symbol encoded by [`encode`](Self::encode).
+    pub fn decode<R: Read, B: Bit>(
+        &self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        let bucket = decoder.decode(self, input)?;
+        let size = self.bucket_size(bucket);
+        if size == 1 {
+            return Ok(bucket);
+        }
+        let position = decoder.decode_target(size, input)?;
+        decoder.decode_interval(position, position + 1, size, input)?;
+        Ok(position * self.num_buckets() + bucket)
+    }
+}
+
+impl SourceModel for ApproximateModel {
+    fn num_symbols(&self) -> u32 {
+        self.num_buckets()
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    /// There is no EOF bucket.
+    fn eof(&self) -> u32 {
+        self.num_buckets()
+    }
+
+    fn cumulative(&self, bucket: u32) -> (u32, u32) {
+        let high = prefix_sum(&self.fenwick_counts, bucket as usize);
+        (high - value(self.exponents[bucket as usize]) - 1, high)
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        descend(&self.fenwick_counts, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{value, ApproximateModel, MAX_EXPONENT};
+    use crate::{model::SourceModel, ArithmeticDecoder, ArithmeticEncoder};
+
+    #[test]
+    fn counters() {
+        assert_eq!(value(15), 15);
+        assert_eq!(value(16), 16);
+        assert_eq!(value(32), 32);
+        assert_eq!(value(MAX_EXPONENT), 1 << 16);
+        assert!((0..MAX_EXPONENT).all(|exponent| value(exponent) < value(exponent + 1)));
+        assert!(u64::from(ApproximateModel::MAX_BUCKETS) * ((1 << 16) + 1) <= u64::from(u32::MAX));
+
+        let mut model = ApproximateModel::new(1 << 24, 64);
+        for _ in 0..100_000 {
+            model.update_symbol(5);
+        }
+        model.update_symbol(7);
+        let estimate = model.estimate(5 + 64 * 1000);
+        assert!((60_000..140_000).contains(&estimate), "{}", estimate);
+        assert_eq!(model.estimate(7), 1);
+        assert_eq!(model.estimate(8), 0);
+        let (low, high) = model.cumulative(5);
+        assert_eq!(high - low, estimate.min(1 << 16) + 1);
+        assert_eq!(model.total_count(), 64 + 1 + high - low - 1);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let symbols: Vec<u32> = (0..20_000u32)
+            .map(|i| match i % 4 {
+                0 => 123_456,
+                1 => i * 7919 % 3_000_000,
+                _ => 2_999_999 - i % 9,
+            })
+            .collect();
+        let mut model = ApproximateModel::new(3_000_000, 1000);
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &symbols {
+            model.encode(symbol, &mut encoder, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        assert!(model.encode(3_000_000, &mut encoder, &mut output).is_err());
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+
+        let mut model = ApproximateModel::new(3_000_000, 1000);
+        let mut decoder = ArithmeticDecoder::new(48);
+        let compressed = output.get_ref().get_ref();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &symbol in &symbols {
+            assert_eq!(model.decode(&mut decoder, &mut input).unwrap(), symbol);
+            model.update_symbol(symbol);
+        }
+        // below the 22 bits a raw symbol takes, though a position in a
+        // bucket of 3000 symbols still costs over 11
+        assert!(compressed.len() < symbols.len() * 22 / 8);
+    }
+}
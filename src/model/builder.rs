@@ -1,9 +1,15 @@
-use std::cmp::max;
+use std::{cmp::max, collections::HashMap};
 
-use fenwick::array::update;
+use crate::{
+    error::{BuildError, ModelError},
+    model::{build_fenwick, LargeModel},
+    Model,
+};
 
-use crate::Model;
+/// How far the sum of a pdf may be from 1 for [`Builder::try_build`].
+pub const PDF_SUM_TOLERANCE: f32 = 0.01;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EOFKind {
     /// Choose a valid index as the EOF `[0, counts.len())`
     Specify(u32),
@@ -17,6 +23,18 @@ pub enum EOFKind {
     None,
 }
 
+/// Reserved control symbols that can be added to a model on top of the
+/// EOF. See [`Builder::control`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlKind {
+    EndOfBlock,
+    Flush,
+    ModelSwitch,
+    Escape,
+    /// Application defined control symbol
+    Custom(u32),
+}
+
 /// options in precedence order:
 ///
 /// **As of 0.2.0**: Specify the number symbols
@@ -37,7 +55,13 @@ pub enum EOFKind {
 /// - binary - default but can also be explicit
 ///
 ///
-/// You should only use one of the build paths
+/// You should only use one of the build paths. The entry methods
+/// [`from_counts`](Builder::from_counts), [`from_pdf`](Builder::from_pdf),
+/// [`uniform`](Builder::uniform) and [`from_bits`](Builder::from_bits) each
+/// start a builder on exactly one path. A builder with more than one path
+/// set is rejected instead of silently applying the precedence above:
+/// [`build`](Builder::build) panics and [`try_build`](Builder::try_build)
+/// returns an error.
 #[derive(Default)]
 pub struct Builder {
     counts: Option<Vec<u32>>,
@@ -47,6 +71,11 @@ pub struct Builder {
     pdf: Option<Vec<f32>>,
     scale: Option<u32>,
     binary: bool,
+    controls: Vec<ControlKind>,
+    batch: Option<u32>,
+    rescale_at: Option<u32>,
+    increment: Option<u32>,
+    decay: Option<(u32, u32, u32)>,
 }
 
 impl Builder {
@@ -54,6 +83,40 @@ impl Builder {
         Self::default()
     }
 
+    /// Starts a builder for a model with the given counts.
+    pub fn from_counts(counts: Vec<u32>) -> Self {
+        Self {
+            counts: Some(counts),
+            ..Self::default()
+        }
+    }
+
+    /// Starts a builder for a model scaled from the given probabilities.
+    pub fn from_pdf(pdf: Vec<f32>) -> Self {
+        Self {
+            pdf: Some(pdf),
+            ..Self::default()
+        }
+    }
+
+    /// Starts a builder for a model with `num_symbols` equally likely
+    /// symbols.
+    pub fn uniform(num_symbols: u32) -> Self {
+        Self {
+            num_symbols: Some(num_symbols),
+            ..Self::default()
+        }
+    }
+
+    /// Starts a builder for a model with `2^num_bits` equally likely
+    /// symbols.
+    pub fn from_bits(num_bits: u32) -> Self {
+        Self {
+            num_bits: Some(num_bits),
+            ..Self::default()
+        }
+    }
+
     pub fn num_symbols(&mut self, count: u32) -> &mut Self {
         self.num_symbols = Some(count);
         self
@@ -65,23 +128,70 @@ impl Builder {
     }
 
     /// Constructs new model if you already have counts present.
-    /// Implied number of symbols from length of `counts`.
+    /// Implied number of symbols from length of `counts`. Counts may be 0,
+    /// as in an exact histogram, but encoding such a symbol fails with
+    /// [`ModelError::ZeroCount`](crate::error::ModelError::ZeroCount).
     pub fn counts(&mut self, counts: Vec<u32>) -> &mut Self {
         self.counts = Some(counts);
         self
     }
 
+    /// Counts from a sparse histogram, such as one made by counting symbols
+    /// into a `HashMap`. Symbols in `[0, alphabet_size)` missing from `map`
+    /// get a count of 1 so they can still be coded.
+    ///
+    /// # Panics
+    /// If a symbol in `map` is not less than `alphabet_size`.
+    pub fn counts_map(&mut self, map: &HashMap<u32, u32>, alphabet_size: u32) -> &mut Self {
+        let mut counts = vec![1; alphabet_size as usize];
+        for (&symbol, &count) in map {
+            assert!(
+                symbol < alphabet_size,
+                "symbol {} is outside the alphabet of {} symbols",
+                symbol,
+                alphabet_size
+            );
+            counts[symbol as usize] = count;
+        }
+        self.counts(counts)
+    }
+
+    /// Like [`counts_map`](Self::counts_map) but returns an error instead
+    /// of panicking when a symbol is outside the alphabet.
+    pub fn try_counts_map(
+        &mut self,
+        map: &HashMap<u32, u32>,
+        alphabet_size: u32,
+    ) -> Result<&mut Self, ModelError> {
+        if let Some(&symbol) = map.keys().find(|&&symbol| symbol >= alphabet_size) {
+            return Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: alphabet_size,
+            });
+        }
+        Ok(self.counts_map(map, alphabet_size))
+    }
+
     /// - Specify(u32): Choose a valid index as the EOF `[0, counts.len())`
     /// - Start: index 0
     /// - End: index `counts.len()` - 1
     /// - EndAddOne: adds an element to `counts` and sets EOF to `counts.len() -
     ///   1`
-    /// - None: Same as not specifying. Sets to value outside symbol rangec
+    /// - None: Same as not specifying. Sets to value outside symbol range
     pub fn eof(&mut self, eof: EOFKind) -> &mut Self {
         self.eof = Some(eof);
         self
     }
 
+    /// Reserves an extra symbol for `kind`, added after every other symbol
+    /// (including an EOF added by [`EOFKind::EndAddOne`]). Can be called
+    /// multiple times; look the symbols up with
+    /// [`Model::control`](crate::Model::control).
+    pub fn control(&mut self, kind: ControlKind) -> &mut Self {
+        self.controls.push(kind);
+        self
+    }
+
     /// `value = (p * scale)`
     ///
     /// Therefore besides determining the accuracy, scale is
@@ -113,19 +223,182 @@ impl Builder {
         self
     }
 
+    /// Queues updates and folds them into the counts every `every` calls of
+    /// [`Model::update_symbol`](crate::Model::update_symbol), instead of
+    /// walking the Fenwick tree on every symbol. Symbols are coded with the
+    /// counts as of the last fold, so the model adapts up to `every` symbols
+    /// late, in exchange for faster updates over large alphabets. Encoder
+    /// and decoder must batch by the same `every`; 0 and 1 update
+    /// immediately.
+    pub fn batch(&mut self, every: u32) -> &mut Self {
+        self.batch = Some(every);
+        self
+    }
+
+    /// Halves every count, rounding up so no codable symbol becomes
+    /// uncodable, whenever an update would take the total count past
+    /// `max_total`. This keeps a long running adaptive model from
+    /// overflowing its total and lets it follow changing statistics. Encoder
+    /// and decoder must use the same `max_total`; pick the precision with
+    /// [`Rescale::AtTotal`](crate::precision::Rescale::AtTotal).
+    /// `max_total` must be at least twice the number of symbols plus the
+    /// [`increment`](Self::increment).
+    pub fn rescale_at(&mut self, max_total: u32) -> &mut Self {
+        self.rescale_at = Some(max_total);
+        self
+    }
+
+    /// The amount [`Model::update_symbol`](crate::Model::update_symbol) adds
+    /// to a symbol's count, 1 by default. Larger increments weigh new
+    /// symbols more against the initial counts, so the model adapts faster;
+    /// combine them with [`rescale_at`](Self::rescale_at) to keep adapting.
+    /// Encoder and decoder must use the same increment, and without
+    /// rescaling the counts grow `increment` times faster, which
+    /// [`recommended_precision`](crate::recommended_precision) needs to know
+    /// through its `expected_len`.
+    pub fn increment(&mut self, increment: u32) -> &mut Self {
+        self.increment = Some(increment);
+        self
+    }
+
+    /// Ages the counts: every `every` updates, each count is multiplied by
+    /// `numerator / denominator` (rounding up, so no codable symbol becomes
+    /// uncodable) and older statistics fade out. `decay(4096, 1, 2)` halves
+    /// the counts every 4096 symbols. Integer arithmetic only, so encoder and
+    /// decoder stay in step when they use the same decay. `every` and
+    /// `numerator` must not be 0 and `numerator` must be below `denominator`.
+    pub fn decay(&mut self, every: u32, numerator: u32, denominator: u32) -> &mut Self {
+        self.decay = Some((every, numerator, denominator));
+        self
+    }
+
+    /// Like [`build`](Self::build) but validates the configuration first,
+    /// returning an error instead of panicking or building an unusable
+    /// model.
+    ///
+    /// Only one build path may be set. A pdf must only contain finite
+    /// probabilities in `[0, 1]` that sum to 1 (within
+    /// [`PDF_SUM_TOLERANCE`]). Every symbol of a pdf keeps a count of at
+    /// least 1 after scaling, so none become uncodable.
+    pub fn try_build(&self) -> Result<Model, BuildError> {
+        if self.paths_set() > 1 {
+            return Err(BuildError::ConflictingPaths);
+        }
+
+        let num_symbols: u64 = match (&self.counts, &self.pdf, self.num_bits, self.num_symbols) {
+            (Some(counts), ..) => counts.len() as u64,
+            (None, Some(pdf), ..) => pdf.len() as u64,
+            (None, None, Some(num_bits), _) if num_bits >= 32 => {
+                return Err(BuildError::TooManyBits { num_bits });
+            }
+            (None, None, Some(num_bits), _) => 1 << num_bits,
+            (None, None, None, Some(num_symbols)) => num_symbols.into(),
+            (None, None, None, None) => 2,
+        };
+        if num_symbols == 0 {
+            return Err(BuildError::NoSymbols);
+        }
+
+        if let Some(EOFKind::Specify(eof)) = self.eof {
+            if u64::from(eof) >= num_symbols {
+                return Err(BuildError::EofOutOfRange {
+                    eof,
+                    num_symbols: num_symbols as u32,
+                });
+            }
+        }
+
+        if let Some(pdf) = &self.pdf {
+            for (index, &value) in pdf.iter().enumerate() {
+                if !value.is_finite() || !(0.0..=1.0).contains(&value) {
+                    return Err(BuildError::InvalidProbability { index, value });
+                }
+            }
+
+            let sum: f32 = pdf.iter().sum();
+            if (sum - 1.0).abs() > PDF_SUM_TOLERANCE {
+                return Err(BuildError::PdfSum { sum });
+            }
+        }
+
+        let added = match self.eof {
+            Some(EOFKind::EndAddOne) => 1,
+            _ => 0,
+        } + self.controls.len() as u64;
+        let total = match (&self.counts, &self.pdf) {
+            (Some(counts), _) => counts.iter().map(|&count| u64::from(count)).sum(),
+            // a large scale can take the scaled counts past u32::MAX
+            (None, Some(pdf)) => self
+                .scaled_pdf(pdf)
+                .iter()
+                .try_fold(0u32, |total, &count| total.checked_add(count))
+                .map_or(u64::MAX, u64::from),
+            (None, None) => num_symbols,
+        };
+        if num_symbols + added > u64::from(u32::MAX) || total + added > u64::from(u32::MAX) {
+            return Err(BuildError::TotalOverflow);
+        }
+        if let Some((every, numerator, denominator)) = self.decay {
+            if every == 0 || numerator == 0 || numerator >= denominator {
+                return Err(BuildError::InvalidDecay {
+                    every,
+                    numerator,
+                    denominator,
+                });
+            }
+        }
+        if let Some(max_total) = self.rescale_at {
+            let increment = u64::from(self.increment.unwrap_or(1));
+            if u64::from(max_total) < 2 * (num_symbols + added + increment) {
+                return Err(BuildError::RescaleTooSmall {
+                    max_total,
+                    num_symbols: (num_symbols + added) as u32,
+                });
+            }
+        }
+
+        Ok(self.build())
+    }
+
+    /// The counts of `pdf` scaled to [`scale`](Self::scale), each at least 1.
+    fn scaled_pdf(&self, pdf: &[f32]) -> Vec<u32> {
+        let scale = self.scale.unwrap_or_else(|| max(pdf.len() as u32, 10));
+        let scale = scale as f32;
+
+        pdf.iter()
+            .map(|p| max((p * scale) as i32, 1))
+            .map(|c| c as u32)
+            .collect()
+    }
+
+    /// Number of build paths set, of which there may be at most one.
+    fn paths_set(&self) -> usize {
+        let paths = [
+            self.counts.is_some(),
+            self.pdf.is_some(),
+            self.num_bits.is_some(),
+            self.num_symbols.is_some(),
+            self.binary,
+        ];
+        paths.iter().filter(|&&set| set).count()
+    }
+
+    /// Builds a [`LargeModel`] with 64-bit counts starting from the counts
+    /// [`build`](Self::build) would give. It only ever grows by one per
+    /// update, ignoring any increment, rescaling or decay.
+    pub fn build_large(&self) -> LargeModel {
+        LargeModel::from(&self.build())
+    }
+
+    /// # Panics
+    /// If more than one build path is set or [`EOFKind::Specify`] is out of
+    /// range. Use [`try_build`](Self::try_build) to get an error instead.
     pub fn build(&self) -> Model {
+        assert!(self.paths_set() <= 1, "conflicting build paths");
         let mut counts = match &self.counts {
             Some(counts) => counts.clone(),
             None => match &self.pdf {
-                Some(pdf) => {
-                    let scale = self.scale.unwrap_or_else(|| max(pdf.len() as u32, 10));
-                    let scale = scale as f32;
-
-                    pdf.iter()
-                        .map(|p| max((p * scale) as i32, 1))
-                        .map(|c| c as u32)
-                        .collect()
-                }
+                Some(pdf) => self.scaled_pdf(pdf),
                 None => match self.num_bits {
                     Some(num_bits) => vec![1; 1 << num_bits as usize],
                     None => match self.num_symbols {
@@ -153,20 +426,37 @@ impl Builder {
             },
         };
 
-        let mut fenwick_counts = vec![0u32; counts.len()];
-
-        for (i, count) in counts.iter().enumerate() {
-            update(&mut fenwick_counts, i, *count);
+        let eof_outside = eof == counts.len() as u32;
+        let mut controls = Vec::with_capacity(self.controls.len());
+        for &kind in &self.controls {
+            controls.push((kind, counts.len() as u32));
+            counts.push(1);
         }
+        // keep the EOF outside of the symbol range if it was
+        let eof = if eof_outside {
+            counts.len() as u32
+        } else {
+            eof
+        };
 
+        let fenwick_counts = build_fenwick(&counts);
         let total_count = counts.iter().sum();
-        Model::from_values(counts, fenwick_counts, total_count, eof)
+        let mut model = Model::from_values(counts, fenwick_counts, total_count, eof);
+        model.controls = controls;
+        model.batch = self.batch.filter(|&every| every > 1);
+        model.rescale_at = self.rescale_at;
+        model.increment = self.increment.unwrap_or(1);
+        model.decay = self.decay;
+        model
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{EOFKind, Model};
+    use std::collections::HashMap;
+
+    use super::{Builder, ControlKind, EOFKind, Model};
+    use crate::error::{BuildError, ModelError};
 
     fn model_eq(a: &Model, b: &Model) {
         assert_eq!(a.eof(), b.eof(), "EOF not equal");
@@ -175,6 +465,55 @@ mod tests {
         assert_eq!(a.total_count(), b.total_count(), "total not equal");
     }
 
+    #[test]
+    fn rescale_at() {
+        let model = Model::builder().num_symbols(4).rescale_at(8).build();
+        assert_eq!(model.rescale_at(), Some(8));
+        assert_eq!(Model::builder().build().rescale_at(), None);
+        assert_eq!(
+            Model::builder()
+                .num_symbols(4)
+                .eof(EOFKind::EndAddOne)
+                .rescale_at(9)
+                .try_build()
+                .err(),
+            Some(BuildError::RescaleTooSmall {
+                max_total: 9,
+                num_symbols: 5
+            })
+        );
+    }
+
+    #[test]
+    fn decay() {
+        let model = Model::builder().num_symbols(4).decay(100, 3, 4).build();
+        assert_eq!(model.decay(), Some((100, 3, 4)));
+        for &(every, numerator, denominator) in &[(0, 1, 2), (10, 0, 2), (10, 2, 2)] {
+            assert_eq!(
+                Model::builder()
+                    .decay(every, numerator, denominator)
+                    .try_build()
+                    .err(),
+                Some(BuildError::InvalidDecay {
+                    every,
+                    numerator,
+                    denominator
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn build_large() {
+        let large = Model::builder()
+            .counts(vec![4, 0, 2])
+            .eof(EOFKind::EndAddOne)
+            .build_large();
+        assert_eq!(large.counts(), &vec![4, 0, 2, 1]);
+        assert_eq!(large.eof(), 3);
+        assert_eq!(large.total_count(), 7);
+    }
+
     #[test]
     fn num_symbols() {
         let sut = Model::builder().num_symbols(4).build();
@@ -318,4 +657,204 @@ mod tests {
 
         model_eq(&reference, &sut);
     }
+
+    #[test]
+    fn controls() {
+        let sut = Model::builder()
+            .num_symbols(4)
+            .eof(EOFKind::EndAddOne)
+            .control(ControlKind::Flush)
+            .control(ControlKind::Escape)
+            .build();
+
+        let reference =
+            Model::from_values(vec![1, 1, 1, 1, 1, 1, 1], vec![1, 2, 1, 4, 1, 2, 1], 7, 4);
+
+        model_eq(&reference, &sut);
+        assert_eq!(sut.control(ControlKind::Flush), Some(5));
+        assert_eq!(sut.control(ControlKind::Escape), Some(6));
+    }
+
+    #[test]
+    fn controls_eof_none() {
+        let sut = Model::builder()
+            .num_symbols(4)
+            .control(ControlKind::EndOfBlock)
+            .build();
+
+        let reference = Model::from_values(vec![1, 1, 1, 1, 1], vec![1, 2, 1, 4, 1], 5, 5);
+
+        model_eq(&reference, &sut);
+        assert_eq!(sut.control(ControlKind::EndOfBlock), Some(4));
+    }
+
+    #[test]
+    fn try_build_pdf() {
+        let sut = Model::builder()
+            .pdf(vec![0.4, 0.2, 0.3, 0.1])
+            .try_build()
+            .unwrap();
+        let reference = Model::builder().pdf(vec![0.4, 0.2, 0.3, 0.1]).build();
+        model_eq(&reference, &sut);
+
+        let sut = Model::builder()
+            .pdf(vec![0.999, 0.001])
+            .try_build()
+            .unwrap();
+        assert!(sut.counts().iter().all(|&count| count > 0));
+    }
+
+    #[test]
+    fn try_build_pdf_invalid() {
+        for &(index, value) in &[(1, f32::NAN), (0, f32::INFINITY), (2, -0.1), (3, 1.5)] {
+            let mut pdf = vec![0.25; 4];
+            pdf[index] = value;
+            let err = Model::builder().pdf(pdf).try_build().err().unwrap();
+            match err {
+                BuildError::InvalidProbability { index: i, .. } => assert_eq!(i, index),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            Model::builder().pdf(vec![0.5, 0.2]).try_build().err(),
+            Some(BuildError::PdfSum { sum: 0.7 })
+        );
+        assert_eq!(
+            Model::builder()
+                .pdf(vec![0.5, 0.5, 0.005])
+                .scale(u32::MAX)
+                .try_build()
+                .err(),
+            Some(BuildError::TotalOverflow)
+        );
+    }
+
+    #[test]
+    fn try_build_invariants() {
+        assert_eq!(
+            Model::builder()
+                .num_symbols(4)
+                .eof(EOFKind::Specify(4))
+                .try_build()
+                .err(),
+            Some(BuildError::EofOutOfRange {
+                eof: 4,
+                num_symbols: 4
+            })
+        );
+        assert_eq!(
+            Model::builder().num_symbols(0).try_build().err(),
+            Some(BuildError::NoSymbols)
+        );
+        assert_eq!(
+            Model::builder().counts(vec![]).try_build().err(),
+            Some(BuildError::NoSymbols)
+        );
+        assert_eq!(
+            Model::builder().num_bits(32).try_build().err(),
+            Some(BuildError::TooManyBits { num_bits: 32 })
+        );
+        assert_eq!(
+            Model::builder().counts(vec![u32::MAX, 1]).try_build().err(),
+            Some(BuildError::TotalOverflow)
+        );
+        assert_eq!(
+            Model::builder()
+                .counts(vec![u32::MAX])
+                .eof(EOFKind::EndAddOne)
+                .try_build()
+                .err(),
+            Some(BuildError::TotalOverflow)
+        );
+
+        let sut = Model::builder()
+            .num_symbols(4)
+            .eof(EOFKind::Specify(3))
+            .try_build()
+            .unwrap();
+        assert_eq!(sut.eof(), 3);
+    }
+
+    #[test]
+    fn entry_methods() {
+        model_eq(
+            &Model::builder().counts(vec![4, 1, 3, 1]).build(),
+            &Builder::from_counts(vec![4, 1, 3, 1]).build(),
+        );
+        model_eq(
+            &Model::builder().pdf(vec![0.4, 0.2, 0.3, 0.1]).build(),
+            &Builder::from_pdf(vec![0.4, 0.2, 0.3, 0.1]).build(),
+        );
+        model_eq(
+            &Model::builder().num_symbols(5).eof(EOFKind::End).build(),
+            &Builder::uniform(5).eof(EOFKind::End).build(),
+        );
+        model_eq(
+            &Model::builder().num_bits(3).build(),
+            &Builder::from_bits(3).try_build().unwrap(),
+        );
+    }
+
+    #[test]
+    fn try_build_conflicting_paths() {
+        assert_eq!(
+            Builder::from_counts(vec![1, 2])
+                .num_bits(8)
+                .try_build()
+                .err(),
+            Some(BuildError::ConflictingPaths)
+        );
+        assert_eq!(
+            Builder::uniform(4).binary().try_build().err(),
+            Some(BuildError::ConflictingPaths)
+        );
+        assert!(Builder::uniform(4).eof(EOFKind::End).try_build().is_ok());
+        assert!(Model::builder().try_build().is_ok());
+    }
+
+    #[test]
+    #[should_panic]
+    fn build_conflicting_paths() {
+        Builder::from_counts(vec![1, 2]).num_symbols(4).build();
+    }
+
+    #[test]
+    fn counts_map() {
+        let mut map = HashMap::new();
+        map.insert(1, 5);
+        map.insert(4, 2);
+
+        let model = Model::builder()
+            .counts_map(&map, 6)
+            .eof(EOFKind::EndAddOne)
+            .build();
+        assert_eq!(model.counts(), &[1, 5, 1, 1, 2, 1, 1]);
+        assert_eq!(model.eof(), 6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn counts_map_outside_alphabet() {
+        let mut map = HashMap::new();
+        map.insert(6, 1);
+        Model::builder().counts_map(&map, 6);
+    }
+
+    #[test]
+    fn try_counts_map() {
+        let mut map = HashMap::new();
+        map.insert(2, 3);
+        let model = Model::builder().try_counts_map(&map, 3).unwrap().build();
+        assert_eq!(model.counts(), &[1, 1, 3]);
+
+        map.insert(6, 1);
+        assert_eq!(
+            Model::builder().try_counts_map(&map, 6).err(),
+            Some(ModelError::SymbolOutOfRange {
+                symbol: 6,
+                num_symbols: 6
+            })
+        );
+    }
 }
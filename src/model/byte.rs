@@ -0,0 +1,114 @@
+use crate::model::SourceModel;
+
+/// Symbols of a byte, plus an EOF if built with
+/// [`with_eof`](Self::with_eof).
+const MAX_SYMBOLS: usize = 257;
+
+/// A model of exactly the 256 byte values, and optionally an EOF as symbol
+/// 256.
+///
+/// With the alphabet fixed, the cumulative counts fit in an inline array,
+/// so the model needs no allocation and a lookup is a binary search over at
+/// most 258 entries. An update adds 1 to every entry after the symbol.
+#[derive(Clone, Debug)]
+pub struct ByteModel {
+    /// `cumulative[s]` is the sum of the counts before symbol `s`, the last
+    /// used entry is the total.
+    cumulative: [u32; MAX_SYMBOLS + 1],
+    num_symbols: u32,
+}
+
+impl Default for ByteModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteModel {
+    /// Every byte with a count of 1 and no EOF.
+    pub fn new() -> Self {
+        Self::uniform(256)
+    }
+
+    /// Every byte and an EOF, symbol 256, with a count of 1.
+    pub fn with_eof() -> Self {
+        Self::uniform(257)
+    }
+
+    fn uniform(num_symbols: u32) -> Self {
+        let mut cumulative = [0; MAX_SYMBOLS + 1];
+        for (symbol, entry) in cumulative.iter_mut().enumerate() {
+            *entry = symbol.min(num_symbols as usize) as u32;
+        }
+        Self {
+            cumulative,
+            num_symbols,
+        }
+    }
+
+    pub fn update_symbol(&mut self, symbol: u32) {
+        let symbols = self.num_symbols as usize;
+        for entry in &mut self.cumulative[symbol as usize + 1..=symbols] {
+            *entry += 1;
+        }
+    }
+
+    pub fn count(&self, symbol: u32) -> u32 {
+        let symbol = symbol as usize;
+        self.cumulative[symbol + 1] - self.cumulative[symbol]
+    }
+}
+
+impl SourceModel for ByteModel {
+    fn num_symbols(&self) -> u32 {
+        self.num_symbols
+    }
+
+    fn total_count(&self) -> u32 {
+        self.cumulative[self.num_symbols as usize]
+    }
+
+    /// Symbol 256 if built with [`with_eof`](ByteModel::with_eof),
+    /// otherwise 256 is out of range and never decoded.
+    fn eof(&self) -> u32 {
+        256
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        let symbol = symbol as usize;
+        (self.cumulative[symbol], self.cumulative[symbol + 1])
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        let highs = &self.cumulative[1..=self.num_symbols as usize];
+        highs.partition_point(|&high| high <= count) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteModel;
+    use crate::{
+        model::{tests::assert_same_intervals, SourceModel},
+        EOFKind, Model,
+    };
+
+    #[test]
+    fn matches_model() {
+        let mut byte_model = ByteModel::with_eof();
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        for symbol in b"mississippi".iter().map(|&byte| u32::from(byte)) {
+            byte_model.update_symbol(symbol);
+            model.update_symbol(symbol);
+        }
+        byte_model.update_symbol(256);
+        model.update_symbol(256);
+
+        assert_same_intervals(&byte_model, &model);
+        assert_eq!(byte_model.count(u32::from(b's')), 5);
+
+        let byte_model = ByteModel::new();
+        assert_eq!(SourceModel::total_count(&byte_model), 256);
+        assert!(byte_model.check_symbol(256).is_err());
+    }
+}
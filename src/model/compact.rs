@@ -0,0 +1,167 @@
+use crate::{error::BuildError, model::SourceModel};
+
+/// An adaptive model that keeps only `u16` counts, a quarter of the memory
+/// of a [`Model`](crate::Model), for codecs that keep thousands of small
+/// context models alive and are bound by memory traffic.
+///
+/// Intervals are found by summing the counts, which for the small
+/// alphabets of context models is cheaper than the cache misses a Fenwick
+/// tree costs. When an update would saturate a count, every count is halved
+/// first, rounding up so no codable symbol becomes uncodable, which also
+/// keeps the model adapting to recent statistics.
+#[derive(Clone, Debug)]
+pub struct CompactModel {
+    counts: Vec<u16>,
+    total_count: u32,
+    eof: u32,
+}
+
+impl CompactModel {
+    /// Every symbol with a count of 1 and no EOF.
+    pub fn new(num_symbols: u32) -> Self {
+        Self::from_counts(vec![1; num_symbols as usize], num_symbols)
+            .expect("num_symbols must not be 0")
+    }
+
+    /// `eof` may be `counts.len()` for a model without one.
+    pub fn from_counts(counts: Vec<u16>, eof: u32) -> Result<Self, BuildError> {
+        if counts.is_empty() {
+            return Err(BuildError::NoSymbols);
+        }
+        if counts.len() > (u32::MAX / u32::from(u16::MAX)) as usize {
+            return Err(BuildError::TotalOverflow);
+        }
+        if eof as usize > counts.len() {
+            return Err(BuildError::EofOutOfRange {
+                eof,
+                num_symbols: counts.len() as u32,
+            });
+        }
+        let total_count = counts.iter().map(|&count| u32::from(count)).sum();
+        Ok(Self {
+            counts,
+            total_count,
+            eof,
+        })
+    }
+
+    pub fn update_symbol(&mut self, symbol: u32) {
+        if self.counts[symbol as usize] == u16::MAX {
+            self.halve();
+        }
+        self.counts[symbol as usize] += 1;
+        self.total_count += 1;
+    }
+
+    fn halve(&mut self) {
+        for count in &mut self.counts {
+            *count -= *count / 2;
+        }
+        self.total_count = self.counts.iter().map(|&count| u32::from(count)).sum();
+    }
+
+    pub fn count(&self, symbol: u32) -> u16 {
+        self.counts[symbol as usize]
+    }
+
+    pub fn counts(&self) -> &[u16] {
+        &self.counts
+    }
+}
+
+impl SourceModel for CompactModel {
+    fn num_symbols(&self) -> u32 {
+        self.counts.len() as u32
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        let low = self.counts[..symbol as usize]
+            .iter()
+            .map(|&count| u32::from(count))
+            .sum::<u32>();
+        (low, low + u32::from(self.counts[symbol as usize]))
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        let mut high = 0;
+        for (symbol, &symbol_count) in self.counts.iter().enumerate() {
+            high += u32::from(symbol_count);
+            if count < high {
+                return symbol as u32;
+            }
+        }
+        self.num_symbols() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::CompactModel;
+    use crate::{
+        error::BuildError,
+        model::{tests::assert_same_intervals, SourceModel},
+        ArithmeticDecoder, ArithmeticEncoder, Model,
+    };
+
+    #[test]
+    fn matches_model() {
+        let mut compact = CompactModel::new(5);
+        let mut model = Model::builder().num_symbols(5).build();
+        for &symbol in &[4, 0, 0, 2, 4, 4, 4] {
+            compact.update_symbol(symbol);
+            model.update_symbol(symbol);
+        }
+        assert_same_intervals(&compact, &model);
+        assert_eq!(
+            CompactModel::from_counts(vec![], 0).err(),
+            Some(BuildError::NoSymbols)
+        );
+    }
+
+    #[test]
+    fn saturation() {
+        let mut model = CompactModel::from_counts(vec![u16::MAX - 1, 0, 3], 3).unwrap();
+        model.update_symbol(0);
+        assert_eq!(model.count(0), u16::MAX);
+        model.update_symbol(0);
+        assert_eq!(model.counts(), &[u16::MAX / 2 + 2, 0, 2]);
+        assert_eq!(
+            SourceModel::total_count(&model),
+            u32::from(u16::MAX / 2) + 4
+        );
+
+        // coding through the halving
+        let symbols: Vec<u32> = (0..200_000u32).map(|i| u32::from(i % 7 == 0)).collect();
+        let mut model = CompactModel::new(2);
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &symbols {
+            encoder.encode(symbol, &model, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        assert!(model.count(0) < u16::MAX);
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+
+        let mut model = CompactModel::new(2);
+        let mut decoder = ArithmeticDecoder::new(32);
+        let compressed = output.get_ref().get_ref();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &symbol in &symbols {
+            assert_eq!(decoder.decode(&model, &mut input).unwrap(), symbol);
+            model.update_symbol(symbol);
+        }
+    }
+}
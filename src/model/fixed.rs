@@ -0,0 +1,165 @@
+use crate::{error::BuildError, model::SourceModel, Model};
+
+/// A model that never changes, for the second pass of two-pass coding.
+///
+/// Nothing is updated after construction, so the cumulative counts are
+/// summed once: a symbol's interval is two array reads and decoding is a
+/// binary search. The most frequent symbol is also found once, up front,
+/// for the decoder to try before searching.
+#[derive(Clone, Debug)]
+pub struct StaticModel {
+    /// `cumulative[s]` is the sum of the counts before symbol `s`, the last
+    /// entry is the total.
+    cumulative: Vec<u32>,
+    eof: u32,
+    dominant: Option<(u32, u32, u32)>,
+}
+
+impl StaticModel {
+    /// `eof` may be `counts.len()` for a model without one. Errors if there
+    /// are no symbols, the EOF is out of range or the counts sum past
+    /// `u32::MAX`.
+    pub fn new(counts: &[u32], eof: u32) -> Result<Self, BuildError> {
+        if counts.is_empty() {
+            return Err(BuildError::NoSymbols);
+        }
+        if counts.len() >= u32::MAX as usize {
+            return Err(BuildError::TotalOverflow);
+        }
+        if eof as usize > counts.len() {
+            return Err(BuildError::EofOutOfRange {
+                eof,
+                num_symbols: counts.len() as u32,
+            });
+        }
+        let mut cumulative = Vec::with_capacity(counts.len() + 1);
+        let mut sum = 0u32;
+        cumulative.push(sum);
+        for &count in counts {
+            sum = sum.checked_add(count).ok_or(BuildError::TotalOverflow)?;
+            cumulative.push(sum);
+        }
+
+        let dominant = counts
+            .iter()
+            .position(|&count| u64::from(count) * 2 > u64::from(sum))
+            .map(|symbol| (symbol as u32, cumulative[symbol], cumulative[symbol + 1]));
+        Ok(Self {
+            cumulative,
+            eof,
+            dominant,
+        })
+    }
+
+    pub fn count(&self, symbol: u32) -> u32 {
+        let symbol = symbol as usize;
+        self.cumulative[symbol + 1] - self.cumulative[symbol]
+    }
+}
+
+/// A snapshot of the model's current counts.
+impl From<&Model> for StaticModel {
+    fn from(model: &Model) -> Self {
+        Self::new(model.counts(), model.eof()).expect("a model's counts sum to its total")
+    }
+}
+
+impl SourceModel for StaticModel {
+    fn num_symbols(&self) -> u32 {
+        (self.cumulative.len() - 1) as u32
+    }
+
+    fn total_count(&self) -> u32 {
+        self.cumulative[self.cumulative.len() - 1]
+    }
+
+    fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        let symbol = symbol as usize;
+        (self.cumulative[symbol], self.cumulative[symbol + 1])
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        self.cumulative[1..].partition_point(|&high| high <= count) as u32
+    }
+
+    fn dominant(&self) -> Option<(u32, u32, u32)> {
+        self.dominant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::StaticModel;
+    use crate::{
+        error::{BuildError, ModelError},
+        model::{tests::assert_same_intervals, SourceModel},
+        ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model,
+    };
+
+    #[test]
+    fn matches_model() {
+        let data = include_str!("../../tests/sherlock.rs").as_bytes();
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        data.iter()
+            .for_each(|&byte| model.update_symbol(byte.into()));
+        let fixed = StaticModel::from(&model);
+
+        assert_same_intervals(&fixed, &model);
+        assert_eq!(SourceModel::dominant(&fixed), model.dominant());
+
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        let mut reference = ArithmeticEncoder::new(48);
+        let mut reference_output = BitWriter::new(Cursor::new(vec![]));
+        for symbol in data.iter().map(|&byte| u32::from(byte)).chain(Some(256)) {
+            encoder.encode(symbol, &fixed, &mut output).unwrap();
+            reference
+                .encode(symbol, &model, &mut reference_output)
+                .unwrap();
+        }
+        encoder.finish_encode(&mut output).unwrap();
+        reference.finish_encode(&mut reference_output).unwrap();
+        output.pad_to_byte().unwrap();
+        reference_output.pad_to_byte().unwrap();
+        let compressed = output.get_ref().get_ref();
+        assert_eq!(compressed, reference_output.get_ref().get_ref());
+
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &byte in data {
+            assert_eq!(decoder.decode(&fixed, &mut input).unwrap(), byte.into());
+        }
+        assert_eq!(decoder.decode(&fixed, &mut input).unwrap(), 256);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            StaticModel::new(&[u32::MAX, 1], 2).err(),
+            Some(BuildError::TotalOverflow)
+        );
+        assert_eq!(StaticModel::new(&[], 0).err(), Some(BuildError::NoSymbols));
+        assert_eq!(
+            StaticModel::new(&[1, 1], 3).err(),
+            Some(BuildError::EofOutOfRange {
+                eof: 3,
+                num_symbols: 2
+            })
+        );
+        let fixed = StaticModel::new(&[3, 0, 5], 3).unwrap();
+        assert_eq!(fixed.count(2), 5);
+        assert_eq!(SourceModel::dominant(&fixed), Some((2, 3, 8)));
+        assert_eq!(
+            fixed.check_codable(1),
+            Err(ModelError::ZeroCount { symbol: 1 })
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{error::ModelError, model::SourceModel};
+
+/// A view of any [`SourceModel`], static or adaptive, in which every symbol
+/// has a probability of at least `1 / 2^bits`, so rare and even zero-count
+/// symbols stay codable and no symbol costs more than `bits` bits.
+///
+/// Every symbol's count is raised by the same amount, the smallest that
+/// reaches the floor for the model's current total, so the view is built
+/// again for each symbol coded with an adaptive model. Encoder and decoder
+/// must use the same `bits`.
+pub struct Floored<'a, M: ?Sized> {
+    model: &'a M,
+    /// Added to every symbol's count.
+    extra: u32,
+    total_count: u32,
+}
+
+impl<'a, M: SourceModel + ?Sized> Floored<'a, M> {
+    /// Errors if `2^bits` is not larger than the number of symbols, which
+    /// could not all get `1 / 2^bits`, or the raised total overflows a `u32`.
+    pub fn new(model: &'a M, bits: u32) -> Result<Self, ModelError> {
+        let symbols = u64::from(model.num_symbols());
+        let slots = 1u64.checked_shl(bits).unwrap_or(u64::MAX);
+        if bits >= 32 || slots <= symbols {
+            return Err(ModelError::NormalizeTooSmall {
+                symbols: model.num_symbols(),
+                total: slots.min(u32::MAX.into()) as u32,
+            });
+        }
+
+        // the total grows to `total + symbols * extra`, of which each symbol
+        // has at least `extra`
+        let total = u64::from(model.total_count());
+        let extra = ((total + slots - symbols - 1) / (slots - symbols)).max(1);
+        let total_count =
+            u32::try_from(total + symbols * extra).map_err(|_| ModelError::CountOverflow)?;
+        Ok(Self {
+            model,
+            extra: extra as u32,
+            total_count,
+        })
+    }
+}
+
+impl<'a, M: SourceModel + ?Sized> SourceModel for Floored<'a, M> {
+    fn num_symbols(&self) -> u32 {
+        self.model.num_symbols()
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    fn eof(&self) -> u32 {
+        self.model.eof()
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        let (low, high) = self.model.cumulative(symbol);
+        (low + symbol * self.extra, high + (symbol + 1) * self.extra)
+    }
+
+    /// A binary search over [`cumulative`](Self::cumulative), as the
+    /// wrapped model's own search does not know about the raised counts.
+    fn symbol_at(&self, count: u32) -> u32 {
+        let (mut low, mut high) = (0, self.num_symbols() - 1);
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if self.cumulative(mid).0 <= count {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::Floored;
+    use crate::{
+        error::ModelError, model::SourceModel, ArithmeticDecoder, ArithmeticEncoder, Model,
+    };
+
+    #[test]
+    fn floor() {
+        let model = Model::builder().counts(vec![1000, 0, 1, 23]).build();
+        let floored = Floored::new(&model, 4).unwrap();
+        for symbol in 0..4 {
+            let (low, high) = floored.cumulative(symbol);
+            assert!(u64::from(high - low) * 16 >= u64::from(floored.total_count()));
+        }
+        assert_eq!(floored.cumulative(3).1, floored.total_count());
+        for count in 0..floored.total_count() {
+            let (low, high) = floored.cumulative(floored.symbol_at(count));
+            assert!((low..high).contains(&count));
+        }
+
+        assert_eq!(
+            Floored::new(&model, 2).err(),
+            Some(ModelError::NormalizeTooSmall {
+                symbols: 4,
+                total: 4
+            })
+        );
+    }
+
+    #[test]
+    fn e2e() {
+        let symbols = [0, 0, 1, 0, 2, 0, 0, 3, 1];
+        let build = || Model::builder().counts(vec![50, 0, 0, 0]).build();
+
+        let mut model = build();
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in &symbols {
+            let floored = Floored::new(&model, 6).unwrap();
+            encoder.encode(symbol, &floored, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut model = build();
+        let mut decoder = ArithmeticDecoder::new(32);
+        let compressed = out_writer.get_ref().get_ref();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &symbol in &symbols {
+            let floored = Floored::new(&model, 6).unwrap();
+            assert_eq!(decoder.decode(&floored, &mut in_reader).unwrap(), symbol);
+            model.update_symbol(symbol);
+        }
+    }
+}
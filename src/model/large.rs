@@ -0,0 +1,215 @@
+use fenwick::array::{prefix_sum, update};
+
+use crate::{error::ModelError, Model};
+
+/// A [`Model`] with `u64` counts and total, for corpora and adaptive runs
+/// that outgrow a `u32` total. Coded with
+/// [`ArithmeticEncoder::encode_large`](crate::ArithmeticEncoder::encode_large)
+/// and [`ArithmeticDecoder::decode_large`](crate::ArithmeticDecoder::decode_large),
+/// which need a precision of at least `log2(total) + 2`, so totals up to
+/// `2^60` at the maximum precision.
+#[derive(Clone, Debug)]
+pub struct LargeModel {
+    counts: Vec<u64>,
+    fenwick_counts: Vec<u64>,
+    total_count: u64,
+    eof: u32,
+}
+
+impl LargeModel {
+    /// `eof` may be `counts.len()` for a model without one.
+    ///
+    /// # Panics
+    /// If there are no counts, more than `u32::MAX` of them or they sum past
+    /// `u64::MAX`.
+    pub fn from_counts(counts: Vec<u64>, eof: u32) -> Self {
+        assert!(
+            !counts.is_empty() && counts.len() < u32::MAX as usize,
+            "a model needs between 1 and u32::MAX - 1 symbols"
+        );
+        let mut fenwick_counts = vec![0; counts.len()];
+        let mut total_count = 0u64;
+        for (index, &count) in counts.iter().enumerate() {
+            update(&mut fenwick_counts, index, count);
+            total_count = total_count.checked_add(count).expect("counts overflow u64");
+        }
+        Self {
+            counts,
+            fenwick_counts,
+            total_count,
+            eof,
+        }
+    }
+
+    pub fn update_symbol(&mut self, symbol: u32) {
+        self.total_count += 1;
+        self.counts[symbol as usize] += 1;
+        update(&mut self.fenwick_counts, symbol as usize, 1);
+    }
+
+    /// Like [`update_symbol`](Self::update_symbol) but returns an error
+    /// instead of panicking when `symbol` is out of range or overflowing
+    /// the total count.
+    pub fn try_update_symbol(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        if self.total_count == u64::MAX {
+            return Err(ModelError::CountOverflow);
+        }
+        self.update_symbol(symbol);
+        Ok(())
+    }
+
+    /// Errors if `symbol` is not a valid index into this model.
+    pub fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        if symbol < self.num_symbols() {
+            Ok(())
+        } else {
+            Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: self.num_symbols(),
+            })
+        }
+    }
+
+    /// Errors if `symbol` is out of range or has a count of 0.
+    pub fn check_codable(&self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        if self.counts[symbol as usize] == 0 {
+            Err(ModelError::ZeroCount { symbol })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn num_symbols(&self) -> u32 {
+        self.counts.len() as u32
+    }
+
+    pub const fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    pub const fn total_count(&self) -> u64 {
+        self.total_count
+    }
+
+    pub const fn counts(&self) -> &Vec<u64> {
+        &self.counts
+    }
+
+    /// The cumulative counts `[low, high)` of `symbol` out of
+    /// [`total_count`](Self::total_count).
+    pub fn cumulative(&self, symbol: u32) -> (u64, u64) {
+        let high = prefix_sum(&self.fenwick_counts, symbol as usize);
+        (high - self.counts[symbol as usize], high)
+    }
+
+    pub fn probability(&self, symbol: u32) -> (f64, f64) {
+        let (low, high) = self.cumulative(symbol);
+        let total = self.total_count as f64;
+        (low as f64 / total, high as f64 / total)
+    }
+
+    /// The symbol whose cumulative count interval contains `count`, see
+    /// [`Model::symbol_at`]. `count` must be less than
+    /// [`total_count`](Self::total_count).
+    pub fn symbol_at(&self, mut count: u64) -> u32 {
+        debug_assert!(count < self.total_count);
+        let len = self.fenwick_counts.len();
+        let mut position = 0;
+        let mut step = (len + 1).next_power_of_two() / 2;
+        while step > 0 {
+            let next = position + step;
+            if next <= len && self.fenwick_counts[next - 1] <= count {
+                count -= self.fenwick_counts[next - 1];
+                position = next;
+            }
+            step >>= 1;
+        }
+        position as u32
+    }
+}
+
+/// The model's current counts and EOF, which then keep growing past
+/// `u32::MAX`.
+impl From<&Model> for LargeModel {
+    fn from(model: &Model) -> Self {
+        let counts = model.counts().iter().map(|&count| count.into()).collect();
+        Self::from_counts(counts, model.eof())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::LargeModel;
+    use crate::{error::ModelError, ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    #[test]
+    fn matches_model() {
+        let mut model = Model::builder().num_symbols(6).eof(EOFKind::End).build();
+        let mut large = LargeModel::from(&model);
+        for &symbol in &[3, 1, 1, 4, 1, 5] {
+            model.update_symbol(symbol);
+            large.update_symbol(symbol);
+        }
+        assert_eq!(large.eof(), model.eof());
+        assert_eq!(large.total_count(), model.total_count().into());
+        for symbol in 0..6 {
+            let (low, high) = model.cumulative(symbol);
+            assert_eq!(large.cumulative(symbol), (low.into(), high.into()));
+            assert_eq!(large.probability(symbol), model.probability(symbol));
+        }
+
+        // the same intervals past a u32 total, looked up at both ends
+        let counts = model.counts().iter().map(|&count| u64::from(count) << 32);
+        let scaled = LargeModel::from_counts(counts.collect(), model.eof());
+        for symbol in 0..6 {
+            let (low, high) = scaled.cumulative(symbol);
+            assert_eq!(scaled.symbol_at(low), symbol);
+            assert_eq!(scaled.symbol_at(high - 1), symbol);
+        }
+    }
+
+    #[test]
+    fn past_u32() {
+        let counts = vec![3 << 32, 0, 1, 1 << 33, 5, 1];
+        let mut large = LargeModel::from_counts(counts, 5);
+        assert!(large.total_count() > u32::MAX.into());
+        assert_eq!(
+            large.check_codable(1),
+            Err(ModelError::ZeroCount { symbol: 1 })
+        );
+        let symbols = [0, 3, 2, 4, 0, 2, 2, 3, 4, 4];
+
+        let mut model = large.clone();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in symbols.iter().chain(&[5]) {
+            encoder.encode_large(symbol, &model, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        assert!(encoder.encode_large(1, &model, &mut output).is_err());
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+
+        let mut decoder = ArithmeticDecoder::new(48);
+        let compressed = output.get_ref().get_ref();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = decoder.decode_large(&large, &mut input).unwrap();
+            large.update_symbol(symbol);
+            decoded.push(symbol);
+        }
+        decoded.pop();
+        assert_eq!(decoded, symbols);
+
+        // a total the precision can not represent
+        let mut encoder = ArithmeticEncoder::new(32);
+        assert!(encoder.encode_large(0, &large, &mut output).is_err());
+    }
+}
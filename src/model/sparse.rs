@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    io::{Error, Read, Write},
+};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+use fenwick::array::{prefix_sum, update};
+
+use crate::{
+    error::ModelError,
+    model::{descend, SourceModel},
+    ArithmeticDecoder, ArithmeticEncoder,
+};
+
+/// The largest total a symbol is coded against after an escape, so an
+/// alphabet beyond it is coded as two halves.
+const RAW_TOTAL: u32 = 1 << 16;
+
+/// An adaptive model for alphabets too large for a dense count array, such
+/// as Unicode scalar values or token IDs in the millions. Only symbols that
+/// have been seen take memory; any other symbol is coded as an escape
+/// followed by the symbol itself, uniformly over the alphabet.
+///
+/// As a [`SourceModel`] its symbols are slots: slot 0 is the escape and
+/// slot `n` the `n`th distinct symbol seen. [`encode`](Self::encode) and
+/// [`decode`](Self::decode) translate between symbols and slots, and the
+/// model changes only through [`update_symbol`](Self::update_symbol), called
+/// on both sides after each symbol. Every new symbol also increments the
+/// escape, so its probability follows the rate new symbols appear at.
+///
+/// Coding a symbol after an escape needs a precision of at least 18.
+#[derive(Clone, Debug)]
+pub struct SparseModel {
+    alphabet_size: u32,
+    /// The slot of every seen symbol.
+    slots: HashMap<u32, u32>,
+    /// The symbol in every slot, with a placeholder for the escape.
+    symbols: Vec<u32>,
+    counts: Vec<u32>,
+    fenwick_counts: Vec<u32>,
+    total_count: u32,
+}
+
+impl SparseModel {
+    /// The slot of the escape.
+    pub const ESCAPE: u32 = 0;
+
+    /// A model of the symbols `0..alphabet_size` that has seen none of them.
+    pub fn new(alphabet_size: u32) -> Self {
+        assert!(alphabet_size > 0, "alphabet_size must not be 0");
+        Self {
+            alphabet_size,
+            slots: HashMap::new(),
+            symbols: vec![u32::MAX],
+            counts: vec![1],
+            fenwick_counts: vec![1],
+            total_count: 1,
+        }
+    }
+
+    pub const fn alphabet_size(&self) -> u32 {
+        self.alphabet_size
+    }
+
+    /// The number of distinct symbols seen.
+    pub fn num_seen(&self) -> u32 {
+        self.slots.len() as u32
+    }
+
+    /// The slot of `symbol`, or `None` if it has not been seen.
+    pub fn slot(&self, symbol: u32) -> Option<u32> {
+        self.slots.get(&symbol).copied()
+    }
+
+    /// The symbol in `slot`, or `None` for the escape.
+    pub fn symbol(&self, slot: u32) -> Option<u32> {
+        match slot {
+            Self::ESCAPE => None,
+            slot => Some(self.symbols[slot as usize]),
+        }
+    }
+
+    /// The count of `symbol`, 0 if it has not been seen.
+    pub fn count(&self, symbol: u32) -> u32 {
+        self.slot(symbol)
+            .map_or(0, |slot| self.counts[slot as usize])
+    }
+
+    /// Errors if `symbol` is outside the alphabet.
+    pub fn check_symbol(&self, symbol: u32) -> Result<(), ModelError> {
+        if symbol < self.alphabet_size {
+            Ok(())
+        } else {
+            Err(ModelError::SymbolOutOfRange {
+                symbol,
+                num_symbols: self.alphabet_size,
+            })
+        }
+    }
+
+    /// Counts `symbol`, giving it a slot and incrementing the escape the
+    /// first time it is seen.
+    pub fn update_symbol(&mut self, symbol: u32) {
+        match self.slot(symbol) {
+            Some(slot) => self.increment(slot),
+            None => {
+                self.increment(Self::ESCAPE);
+                self.slots.insert(symbol, self.symbols.len() as u32);
+                self.symbols.push(symbol);
+                self.push(1);
+            }
+        }
+    }
+
+    fn increment(&mut self, slot: u32) {
+        self.total_count += 1;
+        self.counts[slot as usize] += 1;
+        update(&mut self.fenwick_counts, slot as usize, 1);
+    }
+
+    /// Appends a slot holding `count`. Its node of the tree covers the slots
+    /// from `index & (index + 1)` to itself.
+    fn push(&mut self, count: u32) {
+        let index = self.counts.len();
+        let first = index & (index + 1);
+        let mut node = count;
+        if index > first {
+            node += prefix_sum(&self.fenwick_counts, index - 1);
+            if first > 0 {
+                node -= prefix_sum(&self.fenwick_counts, first - 1);
+            }
+        }
+        self.counts.push(count);
+        self.fenwick_counts.push(node);
+        self.total_count += count;
+    }
+
+    /// Encodes `symbol`, through the escape if it has not been seen.
+    pub fn encode<W: Write>(
+        &self,
+        symbol: u32,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        self.check_symbol(symbol)?;
+        if let Some(slot) = self.slot(symbol) {
+            return encoder.encode(slot, self, output);
+        }
+        encoder.encode(Self::ESCAPE, self, output)?;
+        if self.alphabet_size <= RAW_TOTAL {
+            return encoder.encode_interval(symbol, symbol + 1, self.alphabet_size, output);
+        }
+        let high = symbol / RAW_TOTAL;
+        let low = symbol % RAW_TOTAL;
+        encoder.encode_interval(high, high + 1, self.high_total(), output)?;
+        encoder.encode_interval(low, low + 1, RAW_TOTAL, output)
+    }
+
+    /// Decodes a symbol encoded by [`encode`](Self::encode).
+    pub fn decode<R: Read, B: Bit>(
+        &self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        let slot = decoder.decode(self, input)?;
+        if let Some(symbol) = self.symbol(slot) {
+            return Ok(symbol);
+        }
+        if self.alphabet_size <= RAW_TOTAL {
+            return decode_raw(self.alphabet_size, decoder, input);
+        }
+        let high = decode_raw(self.high_total(), decoder, input)?;
+        let low = decode_raw(RAW_TOTAL, decoder, input)?;
+        Ok(high * RAW_TOTAL + low)
+    }
+
+    /// The number of values of a symbol's upper half.
+    const fn high_total(&self) -> u32 {
+        (self.alphabet_size - 1) / RAW_TOTAL + 1
+    }
+}
+
+/// Decodes a value coded uniformly out of `total`.
+fn decode_raw<R: Read, B: Bit>(
+    total: u32,
+    decoder: &mut ArithmeticDecoder,
+    input: &mut BitReader<R, B>,
+) -> Result<u32, Error> {
+    let value = decoder.decode_target(total, input)?;
+    decoder.decode_interval(value, value + 1, total, input)?;
+    Ok(value)
+}
+
+impl SourceModel for SparseModel {
+    fn num_symbols(&self) -> u32 {
+        self.counts.len() as u32
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    /// There is no EOF slot.
+    fn eof(&self) -> u32 {
+        self.num_symbols()
+    }
+
+    fn cumulative(&self, slot: u32) -> (u32, u32) {
+        let high = prefix_sum(&self.fenwick_counts, slot as usize);
+        (high - self.counts[slot as usize], high)
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        descend(&self.fenwick_counts, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::SparseModel;
+    use crate::{model::SourceModel, ArithmeticDecoder, ArithmeticEncoder, Model};
+
+    fn roundtrip(symbols: &[u32], alphabet_size: u32) -> usize {
+        let mut model = SparseModel::new(alphabet_size);
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in symbols {
+            model.encode(symbol, &mut encoder, &mut output).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+
+        let mut model = SparseModel::new(alphabet_size);
+        let mut decoder = ArithmeticDecoder::new(48);
+        let compressed = output.get_ref().get_ref();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        for &symbol in symbols {
+            assert_eq!(model.decode(&mut decoder, &mut input).unwrap(), symbol);
+            model.update_symbol(symbol);
+        }
+        compressed.len()
+    }
+
+    #[test]
+    fn matches_dense_counts() {
+        let mut model = SparseModel::new(1 << 21);
+        for &symbol in &[0x1f600, 7, 7, 0x10ffff, 7, 42, 0x1f600, 99, 3, 3, 5] {
+            model.update_symbol(symbol);
+        }
+        assert_eq!(model.num_seen(), 7);
+        assert_eq!(model.count(7), 3);
+        assert_eq!(model.count(8), 0);
+        assert_eq!(model.symbol(model.slot(0x10ffff).unwrap()), Some(0x10ffff));
+        assert_eq!(model.symbol(SparseModel::ESCAPE), None);
+
+        // the slots code exactly like a dense model with the same counts
+        let counts: Vec<u32> = (0..model.num_symbols())
+            .map(|slot| {
+                let (low, high) = model.cumulative(slot);
+                high - low
+            })
+            .collect();
+        assert_eq!(counts, [8, 2, 3, 1, 1, 1, 2, 1]);
+        let dense = Model::builder().counts(counts).build();
+        assert_eq!(model.total_count(), dense.total_count());
+        for count in 0..dense.total_count() {
+            assert_eq!(model.symbol_at(count), dense.symbol_at(count));
+        }
+        assert!(model.check_symbol(1 << 21).is_err());
+    }
+
+    #[test]
+    fn huge_alphabets() {
+        let text: Vec<u32> = "naïve café — 東京 🦀🦀 naïve café 東京 🦀"
+            .chars()
+            .map(u32::from)
+            .collect();
+        roundtrip(&text, 0x11_0000);
+
+        let tokens: Vec<u32> = (0..2000u32)
+            .map(|i| [4_000_000, 17, 1_999_999, 17, 3_000_001][i as usize % 5] + i % 3)
+            .collect();
+        let size = roundtrip(&tokens, 5_000_000);
+        // 15 distinct tokens, far below the 23 bits a raw token takes
+        assert!(size < 1000, "{} bytes", size);
+
+        roundtrip(&[0, 255, 0, 0, 12], 256);
+    }
+}
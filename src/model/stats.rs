@@ -0,0 +1,51 @@
+use crate::cost;
+
+/// Prediction quality of a model, accumulated over every symbol passed to
+/// [`Model::update_symbol`](crate::Model::update_symbol) using the
+/// probability the model assigned to it just before the update.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ModelStats {
+    symbols: u64,
+    cost: u64,
+    probability: f64,
+}
+
+impl ModelStats {
+    pub(crate) fn record(&mut self, freq: u32, total: u32) {
+        self.symbols += 1;
+        self.cost += u64::from(cost::cost(freq.into(), total.into()));
+        self.probability += f64::from(freq) / f64::from(total);
+    }
+
+    /// Number of symbols recorded.
+    pub const fn symbols(&self) -> u64 {
+        self.symbols
+    }
+
+    /// Fixed-point (see [`cost`](crate::cost)) total cost of the recorded
+    /// symbols.
+    pub const fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    /// Total cost of the recorded symbols in bits.
+    pub fn bits(&self) -> f64 {
+        cost::to_bits(self.cost)
+    }
+
+    /// Realized cross-entropy in bits per symbol.
+    pub fn cross_entropy(&self) -> f64 {
+        if self.symbols == 0 {
+            return 0.0;
+        }
+        self.bits() / self.symbols as f64
+    }
+
+    /// Mean probability the model assigned to the recorded symbols.
+    pub fn average_probability(&self) -> f64 {
+        if self.symbols == 0 {
+            return 0.0;
+        }
+        self.probability / self.symbols as f64
+    }
+}
@@ -0,0 +1,149 @@
+use fenwick::array::{prefix_sum, update};
+
+use crate::{
+    error::{BuildError, ModelError},
+    model::{build_fenwick, descend, SourceModel},
+    Model,
+};
+
+/// An adaptive model that stores only its Fenwick tree, half the memory of
+/// a [`Model`], for applications with many contexts. A symbol's count is
+/// derived with two prefix sums instead of being read from an array.
+///
+/// It is built from counts and an EOF only: the EOF kinds, control symbols
+/// and other options of the [`Builder`](crate::model::Builder) are not
+/// available.
+#[derive(Clone, Debug)]
+pub struct FenwickModel {
+    fenwick_counts: Vec<u32>,
+    total_count: u32,
+    eof: u32,
+}
+
+impl FenwickModel {
+    /// `eof` may be `counts.len()` for a model without one.
+    pub fn from_counts(counts: &[u32], eof: u32) -> Result<Self, BuildError> {
+        if counts.is_empty() {
+            return Err(BuildError::NoSymbols);
+        }
+        if counts.len() >= u32::MAX as usize {
+            return Err(BuildError::TotalOverflow);
+        }
+        if eof as usize > counts.len() {
+            return Err(BuildError::EofOutOfRange {
+                eof,
+                num_symbols: counts.len() as u32,
+            });
+        }
+        let total_count = counts
+            .iter()
+            .try_fold(0u32, |total, &count| total.checked_add(count))
+            .ok_or(BuildError::TotalOverflow)?;
+        Ok(Self {
+            fenwick_counts: build_fenwick(counts),
+            total_count,
+            eof,
+        })
+    }
+
+    pub fn update_symbol(&mut self, symbol: u32) {
+        self.total_count += 1;
+        update(&mut self.fenwick_counts, symbol as usize, 1);
+    }
+
+    /// Like [`update_symbol`](Self::update_symbol) but returns an error
+    /// instead of panicking when `symbol` is out of range or overflowing
+    /// the total count.
+    pub fn try_update_symbol(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.check_symbol(symbol)?;
+        if self.total_count == u32::MAX {
+            return Err(ModelError::CountOverflow);
+        }
+        self.update_symbol(symbol);
+        Ok(())
+    }
+
+    pub fn count(&self, symbol: u32) -> u32 {
+        let (low, high) = self.cumulative(symbol);
+        high - low
+    }
+
+    /// Every symbol's count, derived from the tree.
+    pub fn counts(&self) -> Vec<u32> {
+        (0..self.num_symbols())
+            .map(|symbol| self.count(symbol))
+            .collect()
+    }
+
+    pub fn fenwick_counts(&self) -> &[u32] {
+        &self.fenwick_counts
+    }
+}
+
+/// The model's current counts and EOF, without its control symbols'
+/// kinds or other options.
+impl From<&Model> for FenwickModel {
+    fn from(model: &Model) -> Self {
+        Self {
+            fenwick_counts: model.fenwick_counts().clone(),
+            total_count: model.total_count(),
+            eof: model.eof(),
+        }
+    }
+}
+
+impl SourceModel for FenwickModel {
+    fn num_symbols(&self) -> u32 {
+        self.fenwick_counts.len() as u32
+    }
+
+    fn total_count(&self) -> u32 {
+        self.total_count
+    }
+
+    fn eof(&self) -> u32 {
+        self.eof
+    }
+
+    fn cumulative(&self, symbol: u32) -> (u32, u32) {
+        let high = prefix_sum(&self.fenwick_counts, symbol as usize);
+        let low = match symbol {
+            0 => 0,
+            symbol => prefix_sum(&self.fenwick_counts, symbol as usize - 1),
+        };
+        (low, high)
+    }
+
+    fn symbol_at(&self, count: u32) -> u32 {
+        descend(&self.fenwick_counts, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FenwickModel;
+    use crate::{error::BuildError, model::tests::assert_same_intervals, EOFKind, Model};
+
+    #[test]
+    fn matches_model() {
+        let mut model = Model::builder()
+            .counts(vec![2, 0, 7, 1, 1, 0, 3])
+            .eof(EOFKind::EndAddOne)
+            .build();
+        let mut tree = FenwickModel::from(&model);
+        for &symbol in &[1, 6, 6, 0, 7, 2] {
+            model.update_symbol(symbol);
+            tree.update_symbol(symbol);
+        }
+        assert_eq!(&tree.counts(), model.counts());
+        assert_eq!(tree.fenwick_counts(), model.fenwick_counts().as_slice());
+        assert_same_intervals(&tree, &model);
+
+        let built = FenwickModel::from_counts(model.counts(), model.eof()).unwrap();
+        assert_eq!(built.fenwick_counts(), tree.fenwick_counts());
+        assert_eq!(
+            FenwickModel::from_counts(&[u32::MAX, 1], 2).err(),
+            Some(BuildError::TotalOverflow)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Coders whose state is `u32` instead of `u64`, for 32-bit
+//! microcontrollers where 64-bit arithmetic is emulated.
+//!
+//! Precision is limited to [`MAX_NARROW_PRECISION`] bits, so models may total
+//! at most a quarter of `2^precision`. Intervals are computed exactly like
+//! [`Range`](crate::Range) does (the product of the width and a count still
+//! needs 64 bits), so at the same precision the streams are identical to
+//! those of [`ArithmeticEncoder`](crate::ArithmeticEncoder) and either
+//! decoder reads either encoder's output.
+
+use crate::word::{WordDecoder, WordEncoder, WordRange};
+
+/// The largest precision a [`NarrowRange`] can hold: `2^precision` has to
+/// fit in a `u32`.
+pub const MAX_NARROW_PRECISION: u32 = 31;
+
+/// [`Range`](crate::Range) on `u32`, see the [module](self) docs.
+pub type NarrowRange = WordRange<u32>;
+
+/// [`ArithmeticEncoder`](crate::ArithmeticEncoder) on a [`NarrowRange`].
+pub type NarrowEncoder = WordEncoder<u32>;
+
+/// [`ArithmeticDecoder`](crate::ArithmeticDecoder) on a [`NarrowRange`].
+pub type NarrowDecoder = WordDecoder<u32>;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{NarrowDecoder, NarrowEncoder, NarrowRange};
+    use crate::{error::PrecisionError, ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    fn model() -> Model {
+        Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build()
+    }
+
+    #[test]
+    fn matches_wide_coder() {
+        let data = include_str!("../tests/sherlock.rs").as_bytes();
+        let symbols: Vec<u32> = data.iter().map(|&byte| byte.into()).collect();
+
+        for &precision in &[24, 31] {
+            let mut narrow = BitWriter::new(Cursor::new(vec![]));
+            let mut wide = BitWriter::new(Cursor::new(vec![]));
+            let mut narrow_encoder = NarrowEncoder::new(precision);
+            let mut wide_encoder = ArithmeticEncoder::new(precision.into());
+            let mut source_model = model();
+            for &symbol in symbols.iter().chain(&[256]) {
+                narrow_encoder
+                    .encode(symbol, &source_model, &mut narrow)
+                    .unwrap();
+                wide_encoder
+                    .encode(symbol, &source_model, &mut wide)
+                    .unwrap();
+                source_model.update_symbol(symbol);
+            }
+            narrow_encoder.finish_encode(&mut narrow).unwrap();
+            wide_encoder.finish_encode(&mut wide).unwrap();
+            narrow.pad_to_byte().unwrap();
+            wide.pad_to_byte().unwrap();
+            let compressed = narrow.get_ref().get_ref().clone();
+            assert_eq!(&compressed, wide.get_ref().get_ref());
+
+            let mut decoder = NarrowDecoder::new(precision);
+            let mut wide_decoder = ArithmeticDecoder::new(precision.into());
+            let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+            let mut wide_input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+            let mut source_model = model();
+            let mut decoded = vec![];
+            while !decoder.finished() {
+                let symbol = decoder.decode(&source_model, &mut input).unwrap();
+                let wide_symbol = wide_decoder.decode(&source_model, &mut wide_input).unwrap();
+                assert_eq!(symbol, wide_symbol);
+                source_model.update_symbol(symbol);
+                decoded.push(symbol);
+            }
+            decoded.pop();
+            assert_eq!(decoded, symbols);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert!(NarrowRange::try_new(31).is_ok());
+        assert_eq!(
+            NarrowRange::try_new(32).err(),
+            Some(PrecisionError::OutOfRange { precision: 32 })
+        );
+        let mut encoder = NarrowEncoder::new(10);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        assert!(encoder.encode(0, &model(), &mut output).is_err());
+
+        let mut decoder = NarrowDecoder::new(16);
+        let error = decoder
+            .decode(&model(), &mut BitReader::<_, MSB>::new(&[][..]))
+            .unwrap_err();
+        assert!(error.to_string().contains("EOF"));
+    }
+}
@@ -0,0 +1,165 @@
+//! A ready-made byte coder that predicts each byte from the two before it.
+
+use std::io::{Error, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+/// Codes bytes with an adaptive model per order-2 context (the previous two
+/// bytes), ending the stream with an EOF symbol.
+///
+/// The 65536 possible contexts are hashed into `2^context_bits` slots, so
+/// fewer bits trade prediction quality for memory. Models are only created
+/// for slots that are used, and once `max_models` exist any new slot falls
+/// back to a single shared order-0 model. Every model has 257 symbols and
+/// takes a few kilobytes.
+///
+/// Encoder and decoder must be created with the same parameters.
+pub struct Order2Coder {
+    models: Vec<Option<Model>>,
+    fallback: Model,
+    context_bits: u32,
+    max_models: usize,
+    allocated: usize,
+    history: u16,
+}
+
+impl Order2Coder {
+    /// `context_bits` must be in `[1, 16]`. 16 gives every context its own
+    /// slot.
+    pub fn new(context_bits: u32, max_models: usize) -> Self {
+        assert!(
+            (1..=16).contains(&context_bits),
+            "context_bits must be between 1 and 16"
+        );
+
+        Self {
+            models: vec![None; 1 << context_bits],
+            fallback: Self::new_model(),
+            context_bits,
+            max_models,
+            allocated: 0,
+            history: 0,
+        }
+    }
+
+    fn new_model() -> Model {
+        Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build()
+    }
+
+    fn slot(&self) -> usize {
+        if self.context_bits == 16 {
+            usize::from(self.history)
+        } else {
+            // Fibonacci hashing spreads similar contexts apart
+            let hash = u32::from(self.history).wrapping_mul(0x9E37_79B9);
+            (hash >> (32 - self.context_bits)) as usize
+        }
+    }
+
+    /// The model for the current context, creating it if allowed.
+    fn model(&mut self) -> &mut Model {
+        let slot = self.slot();
+        if self.models[slot].is_none() && self.allocated < self.max_models {
+            self.models[slot] = Some(Self::new_model());
+            self.allocated += 1;
+        }
+        match &mut self.models[slot] {
+            Some(model) => model,
+            None => &mut self.fallback,
+        }
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        byte: u8,
+    ) -> Result<(), Error> {
+        let model = self.model();
+        encoder.encode(u32::from(byte), model, output)?;
+        model.update_symbol(u32::from(byte));
+        self.history = self.history << 8 | u16::from(byte);
+        Ok(())
+    }
+
+    /// Encodes the EOF. Call this before
+    /// [`finish_encode`](ArithmeticEncoder::finish_encode).
+    pub fn encode_eof<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+    ) -> Result<(), Error> {
+        let model = self.model();
+        encoder.encode(model.eof(), model, output)
+    }
+
+    /// Decodes the next byte, or `None` once the EOF is decoded.
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<Option<u8>, Error> {
+        let model = self.model();
+        let symbol = decoder.decode(model, input)?;
+        if symbol == model.eof() {
+            return Ok(None);
+        }
+        model.update_symbol(symbol);
+        self.history = self.history << 8 | symbol as u16;
+        Ok(Some(symbol as u8))
+    }
+
+    /// Number of context models created so far, not counting the fallback.
+    pub const fn allocated_models(&self) -> usize {
+        self.allocated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::Order2Coder;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder};
+
+    fn roundtrip(data: &[u8], context_bits: u32, max_models: usize) -> usize {
+        let mut coder = Order2Coder::new(context_bits, max_models);
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &byte in data {
+            coder.encode(&mut encoder, &mut out_writer, byte).unwrap();
+        }
+        coder.encode_eof(&mut encoder, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let encoded = out_writer.get_ref().get_ref().clone();
+        assert!(coder.allocated_models() <= max_models);
+
+        let mut coder = Order2Coder::new(context_bits, max_models);
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(&encoded));
+        let mut decoded = vec![];
+        while let Some(byte) = coder.decode(&mut decoder, &mut in_reader).unwrap() {
+            decoded.push(byte);
+        }
+        assert_eq!(decoded, data);
+        encoded.len()
+    }
+
+    #[test]
+    fn e2e() {
+        let data = b"the quick brown fox jumps over the lazy dog. ".repeat(200);
+        let full = roundtrip(&data, 16, usize::MAX);
+        let hashed = roundtrip(&data, 8, usize::MAX);
+        let capped = roundtrip(&data, 16, 4);
+        assert!(full < data.len() / 3);
+        assert!(hashed < data.len() / 3);
+        assert!(full < capped);
+        // just the EOF of a fresh model, about 8 bits
+        assert_eq!(roundtrip(b"", 16, 0), 2);
+    }
+}
@@ -0,0 +1,294 @@
+//! Encoding large inputs on several threads.
+//!
+//! The input is split into fixed size blocks. A statistics pass counts the
+//! symbols of every block, and every block is then encoded with a frozen
+//! (never updated) model built from its own counts, which is stored in front
+//! of the block. Blocks are independent, so both passes run in parallel, and
+//! since the split only depends on the block size the output is the same
+//! for any number of threads. Decoding is a plain sequential loop.
+//!
+//! Layout, all integers little-endian:
+//! - `num_symbols: u32`, `blocks: u64`
+//! - for every block: `len: u64`, `num_symbols` counts as `u32`,
+//!   `payload_len: u64` and the payload
+
+use std::{
+    convert::TryInto,
+    io::{Cursor, Error, ErrorKind},
+    sync::Arc,
+    thread,
+};
+
+use bitbit::{BitReader, BitWriter, MSB};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Runs `job` on every block index on up to `threads` threads, returning
+/// the results in block order.
+fn run_blocks<T, F>(blocks: usize, threads: usize, job: F) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Fn(usize) -> T + Send + Sync + 'static,
+{
+    let threads = threads.max(1).min(blocks.max(1));
+    let job = Arc::new(job);
+    let handles: Vec<_> = (0..threads)
+        .map(|worker| {
+            let job = Arc::clone(&job);
+            thread::spawn(move || {
+                (worker..blocks)
+                    .step_by(threads)
+                    .map(|block| (block, job(block)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut results: Vec<Option<T>> = (0..blocks).map(|_| None).collect();
+    for handle in handles {
+        for (block, result) in handle.join().expect("worker thread panicked") {
+            results[block] = Some(result);
+        }
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/// Counts of a block, starting every symbol at 1 so the frozen model can
+/// code anything.
+fn block_counts(block: &[u32], num_symbols: u32) -> Result<Vec<u32>, Error> {
+    let mut counts = vec![1; num_symbols as usize];
+    for &symbol in block {
+        match counts.get_mut(symbol as usize) {
+            Some(count) => *count += 1,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "symbol {} is out of range for {} symbols",
+                        symbol, num_symbols
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(counts)
+}
+
+fn encode_block(block: &[u32], model: &Model, precision: u64) -> Result<Vec<u8>, Error> {
+    let mut encoder = ArithmeticEncoder::new(precision);
+    let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+    for &symbol in block {
+        encoder.encode(symbol, model, &mut out_writer)?;
+    }
+    encoder.finish_encode(&mut out_writer)?;
+    out_writer.pad_to_byte()?;
+    Ok(out_writer.get_ref().get_ref().clone())
+}
+
+/// Encodes `data` in blocks of `block_len` symbols using up to `threads`
+/// threads. `block_len` must be at least 1 and there must be at least 2
+/// symbols. `precision` must leave room for a total of
+/// `block_len + num_symbols`.
+pub fn encode_parallel(
+    data: &[u32],
+    num_symbols: u32,
+    block_len: usize,
+    precision: u64,
+    threads: usize,
+) -> Result<Vec<u8>, Error> {
+    if block_len == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "block_len must be at least 1",
+        ));
+    }
+    if num_symbols < 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "blocks need at least 2 symbols",
+        ));
+    }
+    let data: Arc<Vec<u32>> = Arc::new(data.to_vec());
+    let blocks = (data.len() + block_len - 1) / block_len;
+    let len = data.len();
+    let range = move |block: usize| block * block_len..((block + 1) * block_len).min(len);
+
+    let stats_data = Arc::clone(&data);
+    let counts = run_blocks(blocks, threads, move |block| {
+        block_counts(&stats_data[range(block)], num_symbols)
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let counts = Arc::new(counts);
+    let frozen = Arc::clone(&counts);
+    let payloads = run_blocks(blocks, threads, move |block| {
+        let model = Model::builder().counts(frozen[block].clone()).try_build()?;
+        encode_block(&data[range(block)], &model, precision)
+    })
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let mut output = Vec::new();
+    output.extend_from_slice(&num_symbols.to_le_bytes());
+    output.extend_from_slice(&(blocks as u64).to_le_bytes());
+    for (counts, payload) in counts.iter().zip(&payloads) {
+        let total: u64 = counts.iter().map(|&count| u64::from(count)).sum();
+        let len = total - u64::from(num_symbols);
+        output.extend_from_slice(&len.to_le_bytes());
+        for count in counts {
+            output.extend_from_slice(&count.to_le_bytes());
+        }
+        output.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        output.extend_from_slice(payload);
+    }
+    Ok(output)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "the stream is truncated")
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        return Err(truncated());
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken)
+}
+
+fn take_u32(input: &mut &[u8]) -> Result<u32, Error> {
+    Ok(u32::from_le_bytes(take(input, 4)?.try_into().unwrap()))
+}
+
+fn take_u64(input: &mut &[u8]) -> Result<u64, Error> {
+    Ok(u64::from_le_bytes(take(input, 8)?.try_into().unwrap()))
+}
+
+fn invalid_block(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Decodes a stream written by [`encode_parallel`] on the calling thread.
+///
+/// Every block is checked against what the encoder writes before it is
+/// decoded: its counts must be in the input, start at 1 and add up to its
+/// length, so a corrupt or crafted stream fails with
+/// [`ErrorKind::InvalidData`] or [`ErrorKind::UnexpectedEof`] instead of
+/// allocating or decoding without bound.
+pub fn decode_blocks(mut input: &[u8], precision: u64) -> Result<Vec<u32>, Error> {
+    let num_symbols = take_u32(&mut input)?;
+    let blocks = take_u64(&mut input)?;
+    if num_symbols < 2 {
+        return Err(invalid_block("blocks need at least 2 symbols"));
+    }
+
+    let mut data = Vec::new();
+    for _ in 0..blocks {
+        let len = take_u64(&mut input)?;
+        let counts_len = (num_symbols as usize)
+            .checked_mul(4)
+            .ok_or_else(truncated)?;
+        let counts: Vec<u32> = take(&mut input, counts_len)?
+            .chunks_exact(4)
+            .map(|count| u32::from_le_bytes(count.try_into().unwrap()))
+            .collect();
+        let payload_len = take_u64(&mut input)?;
+        let payload = take(&mut input, payload_len as usize)?;
+
+        // the encoder starts every count at 1 and adds one per symbol
+        if counts.contains(&0) {
+            return Err(invalid_block("a block has a count of 0"));
+        }
+        let total: u64 = counts.iter().map(|&count| u64::from(count)).sum();
+        if total - u64::from(num_symbols) != len {
+            return Err(invalid_block(
+                "a block's counts do not add up to its length",
+            ));
+        }
+        let model = Model::builder()
+            .counts(counts)
+            .try_build()
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        let mut decoder = ArithmeticDecoder::new(precision);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(payload);
+        for _ in 0..len {
+            data.push(decoder.decode(&model, &mut in_reader)?);
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::{decode_blocks, encode_parallel};
+
+    #[test]
+    fn deterministic() {
+        let data: Vec<u32> = (0..10_000u32)
+            .map(|i| if i < 5_000 { i % 3 } else { (i * 7) % 16 })
+            .collect();
+
+        let single = encode_parallel(&data, 16, 1_000, 40, 1).unwrap();
+        for &threads in &[2, 3, 8, 64] {
+            assert_eq!(
+                encode_parallel(&data, 16, 1_000, 40, threads).unwrap(),
+                single
+            );
+        }
+        assert_eq!(decode_blocks(&single, 40).unwrap(), data);
+
+        // the first half only uses 3 symbols and compresses much better
+        assert!(single.len() < data.len() / 2);
+    }
+
+    #[test]
+    fn edge_cases() {
+        let empty = encode_parallel(&[], 4, 10, 32, 4).unwrap();
+        assert_eq!(decode_blocks(&empty, 32).unwrap(), Vec::<u32>::new());
+
+        let data = [1, 2, 3];
+        let encoded = encode_parallel(&data, 4, 2, 32, 4).unwrap();
+        assert_eq!(decode_blocks(&encoded, 32).unwrap(), data);
+        assert!(decode_blocks(&encoded[..encoded.len() - 1], 32).is_err());
+
+        assert!(encode_parallel(&[4], 4, 2, 32, 1).is_err());
+        assert!(encode_parallel(&data, 4, 0, 32, 1).is_err());
+        assert!(encode_parallel(&[0], 1, 2, 32, 1).is_err());
+    }
+
+    #[test]
+    fn untrusted_headers() {
+        let header = |num_symbols: u32, len: u64, counts: &[u32]| {
+            let mut input = num_symbols.to_le_bytes().to_vec();
+            input.extend_from_slice(&1u64.to_le_bytes());
+            input.extend_from_slice(&len.to_le_bytes());
+            for count in counts {
+                input.extend_from_slice(&count.to_le_bytes());
+            }
+            input.extend_from_slice(&0u64.to_le_bytes());
+            input
+        };
+        let error = |input: &[u8]| decode_blocks(input, 32).unwrap_err().kind();
+
+        // more counts than the input holds
+        assert_eq!(
+            error(&header(u32::MAX, 0, &[1, 1])),
+            ErrorKind::UnexpectedEof
+        );
+        // counts summing past u32::MAX
+        let len = 2 * u64::from(u32::MAX) - 2;
+        assert_eq!(
+            error(&header(2, len, &[u32::MAX, u32::MAX])),
+            ErrorKind::InvalidData
+        );
+        // a single symbol, which decodes in 0 bits
+        assert_eq!(error(&header(1, 1 << 40, &[1])), ErrorKind::InvalidData);
+        // a length the counts do not account for
+        assert_eq!(error(&header(2, 1 << 40, &[1, 1])), ErrorKind::InvalidData);
+        assert_eq!(error(&header(2, 0, &[0, 2])), ErrorKind::InvalidData);
+    }
+}
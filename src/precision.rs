@@ -0,0 +1,168 @@
+//! Choosing a bit precision for the encoder and decoder.
+
+use crate::{error::PrecisionError, range::check_precision, Range, SourceModel};
+
+/// How a model's counts are kept from growing without bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rescale {
+    /// Counts only ever grow, by the model's increment per coded symbol.
+    Never,
+    /// Counts are scaled down whenever the total would exceed this value,
+    /// see [`Builder::rescale_at`](crate::model::Builder::rescale_at).
+    AtTotal(u64),
+}
+
+/// Extra bits above the largest model total. The coder loses roughly
+/// `total / width` bits per symbol to rounding, so 8 bits keeps that below
+/// 1/256 of a bit.
+const ROUNDING_MARGIN: u64 = 8;
+
+/// Largest precision the coder supports.
+pub const MAX_PRECISION: u64 = 62;
+
+/// Smallest precision the coder supports, just enough for a binary model.
+pub const MIN_PRECISION: u64 = 3;
+
+/// Smallest precision that keeps every symbol of the model distinguishable
+/// (see [`Range::can_represent`](crate::Range::can_represent)) for the whole
+/// stream, plus a margin to keep rounding losses negligible.
+///
+/// `num_symbols` is the size of the model including any EOF or control
+/// symbols, all starting with a count of 1, and `expected_len` is the
+/// maximum number of symbols that will update the model. The result is
+/// capped at [`MAX_PRECISION`].
+pub fn recommended_precision(num_symbols: u32, expected_len: u64, rescale: Rescale) -> u64 {
+    let grown = u64::from(num_symbols).saturating_add(expected_len);
+    let max_total = match rescale {
+        Rescale::Never => grown,
+        Rescale::AtTotal(limit) => grown.min(limit),
+    };
+
+    // the range is wider than a quarter after renormalization
+    let precision = ceil_log2(max_total) + 2 + ROUNDING_MARGIN;
+    precision.min(MAX_PRECISION)
+}
+
+/// Errors if a coder with `precision` can not use `source_model` as it is
+/// now: the precision is out of range or too low to give every symbol an
+/// interval, which would make symbols indistinguishable and decode garbage.
+/// Adaptive models grow, so use [`recommended_precision`] to pick a
+/// precision for a whole stream.
+pub fn check_compatible<M: SourceModel + ?Sized>(
+    precision: u64,
+    source_model: &M,
+) -> Result<(), PrecisionError> {
+    check_precision(precision)?;
+    let total_count = source_model.total_count();
+    if Range::new(precision).can_represent(total_count.into()) {
+        Ok(())
+    } else {
+        Err(PrecisionError::TooLowForModel {
+            precision,
+            total_count,
+        })
+    }
+}
+
+fn ceil_log2(value: u64) -> u64 {
+    if value <= 1 {
+        0
+    } else {
+        u64::from(64 - (value - 1).leading_zeros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_compatible, recommended_precision, Rescale, MAX_PRECISION};
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use crate::{
+        error::PrecisionError, ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model, Range,
+    };
+
+    #[test]
+    fn compatible() {
+        let model = Model::builder().counts(vec![100, 28]).build();
+        // 128 is a quarter of 2^9
+        assert_eq!(check_compatible(9, &model), Ok(()));
+        assert_eq!(
+            check_compatible(8, &model),
+            Err(PrecisionError::TooLowForModel {
+                precision: 8,
+                total_count: 128
+            })
+        );
+        assert!(matches!(
+            check_compatible(63, &model),
+            Err(PrecisionError::OutOfRange { precision: 63 })
+        ));
+        assert!(ArithmeticEncoder::for_model(8, &model).is_err());
+        assert!(ArithmeticEncoder::for_model(32, &model).is_ok());
+    }
+
+    #[test]
+    fn with_model() {
+        let data = include_str!("../tests/sherlock.rs").as_bytes();
+        let build = || Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let expected_len = data.len() as u64 + 1;
+
+        let mut model = build();
+        let mut encoder = ArithmeticEncoder::with_expected_len(&model, expected_len);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for symbol in data.iter().map(|&byte| u32::from(byte)).chain(Some(256)) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut model = build();
+        let mut decoder = ArithmeticDecoder::with_expected_len(&model, expected_len);
+        let compressed = out_writer.get_ref().get_ref();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = decoder.decode(&model, &mut in_reader).unwrap();
+            model.update_symbol(symbol);
+            decoded.push(symbol as u8);
+        }
+        decoded.pop();
+        assert_eq!(decoded, data);
+
+        // any u32 total fits
+        let model = Model::builder().counts(vec![u32::MAX - 1, 1]).build();
+        let mut encoder = ArithmeticEncoder::with_model(&model);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        encoder.encode(1, &model, &mut out_writer).unwrap();
+    }
+
+    #[test]
+    fn byte_model() {
+        // 257 symbols plus a million updates is just under 2^20
+        assert_eq!(recommended_precision(257, 1_000_000, Rescale::Never), 30);
+        assert_eq!(
+            recommended_precision(257, 1_000_000, Rescale::AtTotal(1 << 16)),
+            26
+        );
+    }
+
+    #[test]
+    fn capped() {
+        assert_eq!(
+            recommended_precision(u32::MAX, u64::MAX, Rescale::Never),
+            MAX_PRECISION
+        );
+    }
+
+    #[test]
+    fn representable() {
+        for &(symbols, len) in &[(2, 0), (3, 5), (256, 1 << 20), (1 << 24, 1 << 30)] {
+            let precision = recommended_precision(symbols, len, Rescale::Never);
+            let range = Range::new(precision);
+            assert!(range.can_represent(u64::from(symbols) + len));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Coding sequences of floating point values by quantizing them to symbols.
+//!
+//! [`encode_values`] writes the [`Quantizer`] and the number of values
+//! before the symbols, so [`decode_values`] needs nothing but the stream to
+//! reconstruct them.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{binary::BinaryCoder, ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Maps values to symbols in `[0, levels)` and back. Symbol `levels / 2`
+/// stands for 0, and values past the outermost levels are clamped to them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantizer {
+    /// Evenly spaced levels `step` apart.
+    Uniform { step: f64, levels: u32 },
+    /// µ-law companded levels over `[-max, max]`, closer together near 0.
+    MuLaw { mu: f64, max: f64, levels: u32 },
+}
+
+impl Quantizer {
+    pub const fn levels(&self) -> u32 {
+        match *self {
+            Self::Uniform { levels, .. } | Self::MuLaw { levels, .. } => levels,
+        }
+    }
+
+    const fn center(&self) -> i64 {
+        (self.levels() / 2) as i64
+    }
+
+    /// The symbol for `value`. NaN maps to the symbol for 0.
+    pub fn quantize(&self, value: f64) -> u32 {
+        let scaled = match *self {
+            Self::Uniform { step, .. } => value / step,
+            Self::MuLaw { mu, max, .. } => {
+                let magnitude = (value.abs() / max).min(1.0);
+                let companded = (1.0 + mu * magnitude).ln() / (1.0 + mu).ln();
+                companded.copysign(value) * self.center() as f64
+            }
+        };
+
+        let index = if scaled.is_nan() {
+            0
+        } else {
+            let max_index = (i64::from(self.levels()) - 1 - self.center()) as f64;
+            scaled.round().max(-self.center() as f64).min(max_index) as i64
+        };
+        (index + self.center()) as u32
+    }
+
+    /// The value `symbol` stands for.
+    pub fn reconstruct(&self, symbol: u32) -> f64 {
+        let index = (i64::from(symbol) - self.center()) as f64;
+        match *self {
+            Self::Uniform { step, .. } => index * step,
+            Self::MuLaw { mu, max, .. } => {
+                let companded = index / self.center() as f64;
+                let magnitude = ((1.0 + mu).powf(companded.abs()) - 1.0) / mu;
+                (magnitude * max).copysign(companded)
+            }
+        }
+    }
+}
+
+/// A single level would code every value in 0 bits.
+fn check_levels(levels: u32, kind: ErrorKind) -> Result<(), Error> {
+    if levels < 2 {
+        return Err(Error::new(
+            kind,
+            format!("a quantizer needs at least 2 levels, not {}", levels),
+        ));
+    }
+    Ok(())
+}
+
+fn encode_u64<W: Write>(
+    header: &mut BinaryCoder,
+    encoder: &mut ArithmeticEncoder,
+    output: &mut BitWriter<W>,
+    value: u64,
+) -> Result<(), Error> {
+    header.encode(encoder, output, (value >> 32) as u32)?;
+    header.encode(encoder, output, value as u32)
+}
+
+fn decode_u64<R: Read, B: Bit>(
+    header: &mut BinaryCoder,
+    decoder: &mut ArithmeticDecoder,
+    input: &mut BitReader<R, B>,
+) -> Result<u64, Error> {
+    let high = header.decode(decoder, input)?;
+    let low = header.decode(decoder, input)?;
+    Ok(u64::from(high) << 32 | u64::from(low))
+}
+
+/// Quantizes and encodes `values` with an adaptive model over the levels of
+/// `quantizer`, which must have at least 2 levels so every symbol carries
+/// information. The encoder's precision must be able to represent that
+/// model as it grows (see [`recommended_precision`](crate::recommended_precision)).
+pub fn encode_values<T, W>(
+    values: &[T],
+    quantizer: &Quantizer,
+    encoder: &mut ArithmeticEncoder,
+    output: &mut BitWriter<W>,
+) -> Result<(), Error>
+where
+    T: Copy + Into<f64>,
+    W: Write,
+{
+    check_levels(quantizer.levels(), ErrorKind::InvalidInput)?;
+    let mut header = BinaryCoder::new(32);
+    let (kind, first, second) = match *quantizer {
+        Quantizer::Uniform { step, .. } => (0, step, 0.0),
+        Quantizer::MuLaw { mu, max, .. } => (1, mu, max),
+    };
+    header.encode(encoder, output, kind)?;
+    header.encode(encoder, output, quantizer.levels())?;
+    encode_u64(&mut header, encoder, output, first.to_bits())?;
+    encode_u64(&mut header, encoder, output, second.to_bits())?;
+    encode_u64(&mut header, encoder, output, values.len() as u64)?;
+
+    let mut model = Model::builder().num_symbols(quantizer.levels()).build();
+    for &value in values {
+        let symbol = quantizer.quantize(value.into());
+        encoder.encode(symbol, &model, output)?;
+        model.update_symbol(symbol);
+    }
+    Ok(())
+}
+
+/// Decodes values written by [`encode_values`], returning the
+/// reconstructed values along with the quantizer they were coded with.
+/// Fails with [`ErrorKind::InvalidData`] instead of decoding a stream that
+/// claims more than `max_len` values or fewer than 2 levels, so untrusted
+/// input can not make it run without bound.
+pub fn decode_values<R: Read, B: Bit>(
+    decoder: &mut ArithmeticDecoder,
+    input: &mut BitReader<R, B>,
+    max_len: u64,
+) -> Result<(Vec<f64>, Quantizer), Error> {
+    let mut header = BinaryCoder::new(32);
+    let kind = header.decode(decoder, input)?;
+    let levels = header.decode(decoder, input)?;
+    let first = f64::from_bits(decode_u64(&mut header, decoder, input)?);
+    let second = f64::from_bits(decode_u64(&mut header, decoder, input)?);
+    let len = decode_u64(&mut header, decoder, input)?;
+    check_levels(levels, ErrorKind::InvalidData)?;
+    if len > max_len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} values is more than the limit of {}", len, max_len),
+        ));
+    }
+
+    let quantizer = match kind {
+        0 => Quantizer::Uniform {
+            step: first,
+            levels,
+        },
+        1 => Quantizer::MuLaw {
+            mu: first,
+            max: second,
+            levels,
+        },
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unknown quantizer in the stream",
+            ))
+        }
+    };
+
+    let mut model = Model::builder().num_symbols(levels).build();
+    let mut values = Vec::new();
+    for _ in 0..len {
+        let symbol = decoder.decode(&model, input)?;
+        model.update_symbol(symbol);
+        values.push(quantizer.reconstruct(symbol));
+    }
+    Ok((values, quantizer))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{decode_values, encode_values, Quantizer};
+    use crate::{binary::BinaryCoder, ArithmeticDecoder, ArithmeticEncoder};
+
+    #[test]
+    fn uniform() {
+        let quantizer = Quantizer::Uniform {
+            step: 0.5,
+            levels: 9,
+        };
+        assert_eq!(quantizer.quantize(0.0), 4);
+        assert_eq!(quantizer.quantize(0.7), 5);
+        assert_eq!(quantizer.quantize(-1.3), 1);
+        assert_eq!(quantizer.quantize(100.0), 8);
+        assert_eq!(quantizer.quantize(f64::NAN), 4);
+        assert_eq!(quantizer.reconstruct(1), -1.5);
+        assert_eq!(quantizer.reconstruct(8), 2.0);
+    }
+
+    #[test]
+    fn mu_law() {
+        let quantizer = Quantizer::MuLaw {
+            mu: 255.0,
+            max: 1.0,
+            levels: 255,
+        };
+        assert_eq!(quantizer.quantize(0.0), 127);
+        assert_eq!(quantizer.quantize(1.0), 254);
+        assert_eq!(quantizer.quantize(-2.0), 0);
+        for &value in &[0.001, 0.01, -0.1, 0.5, -0.9] {
+            let error = (quantizer.reconstruct(quantizer.quantize(value)) - value).abs();
+            // the levels are spaced roughly in proportion to the magnitude
+            assert!(error < 0.03 * value.abs() + 1e-4, "{} {}", value, error);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let values: Vec<f32> = (0..200).map(|i| (i as f32 / 10.0).sin() * 3.0).collect();
+        let quantizer = Quantizer::Uniform {
+            step: 0.05,
+            levels: 129,
+        };
+
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        encode_values(&values, &quantizer, &mut encoder, &mut out_writer).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let input = Cursor::new(out_writer.get_ref().get_ref().clone());
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+        let mut decoder = ArithmeticDecoder::new(48);
+        let (decoded, decoded_quantizer) =
+            decode_values(&mut decoder, &mut in_reader, 200).unwrap();
+
+        assert_eq!(decoded_quantizer, quantizer);
+        assert_eq!(decoded.len(), values.len());
+        for (&value, &decoded) in values.iter().zip(&decoded) {
+            assert!((f64::from(value) - decoded).abs() <= 0.025 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn untrusted_header() {
+        let values = [0.5f32; 3];
+        let encode = |levels| {
+            let quantizer = Quantizer::Uniform { step: 0.5, levels };
+            let mut encoder = ArithmeticEncoder::new(48);
+            let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+            let result = encode_values(&values, &quantizer, &mut encoder, &mut out_writer);
+            encoder.finish_encode(&mut out_writer).unwrap();
+            out_writer.pad_to_byte().unwrap();
+            result.map(|_| out_writer.get_ref().get_ref().clone())
+        };
+        let decode = |encoded: &[u8], max_len| {
+            let mut in_reader: BitReader<_, MSB> = BitReader::new(encoded);
+            let mut decoder = ArithmeticDecoder::new(48);
+            decode_values(&mut decoder, &mut in_reader, max_len).map(|(values, _)| values)
+        };
+
+        assert_eq!(encode(1).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let encoded = encode(5).unwrap();
+        assert_eq!(decode(&encoded, 3).unwrap().len(), 3);
+        let error = decode(&encoded, 2).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // a header with a single level, as a crafted stream would have
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        let mut header = BinaryCoder::new(32);
+        for &value in &[0, 1, 0, 0, 0, 0, 1 << 8, 0] {
+            header.encode(&mut encoder, &mut out_writer, value).unwrap();
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let error = decode(out_writer.get_ref().get_ref(), u64::MAX).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,5 +1,22 @@
-use crate::Model;
+use crate::{
+    error::PrecisionError,
+    precision::{MAX_PRECISION, MIN_PRECISION},
+    Model, SourceModel,
+};
 
+/// The current interval of an arithmetic coder, `[low, high)`, in units of
+/// `1 / 2^precision`.
+///
+/// Invariants, which hold from [`new`](Self::new) on as long as intervals
+/// passed to [`update_range`](Self::update_range) come from
+/// [`interval`](Self::interval) or [`interval_counts`](Self::interval_counts)
+/// and the range is renormalized after every update:
+/// - `low < high <= 2^precision`
+/// - after renormalization (scaling while in the bottom, upper or middle
+///   half) the width is greater than a quarter of `2^precision`, so any model
+///   whose total is at most [`quarter`](Self::quarter) gives every symbol
+///   with a non-zero count a non-empty interval.
+#[derive(Clone, Debug)]
 pub struct Range {
     high: u64,
     low: u64,
@@ -9,6 +26,8 @@ pub struct Range {
 }
 
 impl Range {
+    /// The full interval `[0, 2^precision)`. Panics if `precision` is 64 or
+    /// more.
     pub fn new(precision: u64) -> Self {
         assert!(precision < 64);
 
@@ -22,69 +41,322 @@ impl Range {
         }
     }
 
+    /// Like [`new`](Self::new) but returns an error instead of panicking,
+    /// and also rejects precisions too small for any model or too large for
+    /// the coder's arithmetic.
+    pub fn try_new(precision: u64) -> Result<Self, PrecisionError> {
+        check_precision(precision)?;
+        Ok(Self::new(precision))
+    }
+
+    /// Whether every symbol of a model with `total_count` can still be given
+    /// a non-empty interval. After renormalization the range is always wider
+    /// than a quarter, so the total must not exceed it.
+    pub const fn can_represent(&self, total_count: u64) -> bool {
+        total_count <= self.one_quarter_mark
+    }
+
+    /// Whether the interval lies entirely below the midpoint.
     pub const fn in_bottom_half(&self) -> bool {
         self.high < self.half
     }
 
+    /// Whether the interval lies entirely above the midpoint.
     pub const fn in_upper_half(&self) -> bool {
         self.low > self.half
     }
 
+    /// Whether the interval lies strictly between the first and third
+    /// quarter marks, straddling the midpoint.
     pub const fn in_middle_half(&self) -> bool {
         self.low > self.one_quarter_mark && self.high < self.three_quarter_mark
     }
 
+    /// Whether the interval starts at or below the first quarter mark.
     pub const fn in_bottom_quarter(&self) -> bool {
         self.low <= self.one_quarter_mark
     }
 
-    /// scaling the upper half is a left shift, to avoid overflow we minus a 1/2
-    /// first
+    /// Doubles an interval in the upper half, subtracting the half first so
+    /// the shift can not overflow.
     pub fn scale_upper_half(&mut self) {
         self.low = (self.low - self.half) << 1;
         self.high = (self.high - self.half) << 1;
     }
 
-    /// subtract a quarter is the same as shifting out the second most
-    /// significant bit
+    /// Doubles an interval in the middle half around the midpoint,
+    /// subtracting a quarter to shift out the second most significant bit.
     pub fn scale_middle_half(&mut self) {
         self.low = (self.low - self.one_quarter_mark) << 1;
         self.high = (self.high - self.one_quarter_mark) << 1;
     }
 
-    /// scaling the bottom half in a left shift
+    /// Doubles an interval in the bottom half with a left shift.
     pub fn scale_bottom_half(&mut self) {
         self.low <<= 1;
         self.high <<= 1;
     }
 
+    /// Scales the cumulative counts of `symbol` to the current range with
+    /// integer arithmetic, so encoders and decoders agree bit for bit on
+    /// every platform.
+    /// returns (low, high)
+    pub fn calculate_range<M: SourceModel + ?Sized>(
+        &self,
+        symbol: u32,
+        source_model: &M,
+    ) -> (u64, u64) {
+        let (low, high) = source_model.cumulative(symbol);
+        self.interval_counts(low.into(), high.into(), source_model.total_count().into())
+    }
+
+    /// Like [`calculate_range`](Self::calculate_range) but as if the symbols
+    /// in `exclusions` had a count of 0.
+    /// returns (low, high)
+    pub fn calculate_range_excluding(
+        &self,
+        symbol: u32,
+        exclusions: &[u32],
+        source_model: &Model,
+    ) -> (u64, u64) {
+        let (low, high, total) = source_model.cumulative_excluding(symbol, exclusions);
+        self.interval_counts(low.into(), high.into(), total.into())
+    }
+
+    /// Scales a `(low, high)` probability pair to the current range. The
+    /// coder itself uses [`interval_counts`](Self::interval_counts), as
+    /// floating point rounding is not guaranteed to match across platforms.
     /// returns (low, high)
-    pub fn calculate_range(&self, symbol: u32, source_model: &Model) -> (u64, u64) {
+    pub fn interval(&self, (low, high): (f64, f64)) -> (u64, u64) {
         let new_width = self.high - self.low;
-        let (low, high) = source_model.probability(symbol);
         (
             (self.low + (new_width as f64 * low) as u64),
             (self.low + (new_width as f64 * high) as u64),
         )
     }
 
+    /// Scales the cumulative count interval `[low_count, high_count)` out of
+    /// `total` to the current range using only integer arithmetic. The
+    /// returned interval is non-empty if `low_count < high_count` and
+    /// `total <= width()`. A total that is a power of two is scaled with a
+    /// shift instead of a division, and 128-bit arithmetic is only used when
+    /// `width() * total` does not fit in a `u64`.
+    /// returns (low, high)
+    pub fn interval_counts(&self, low_count: u64, high_count: u64, total: u64) -> (u64, u64) {
+        debug_assert!(low_count <= high_count && high_count <= total && total > 0);
+        scale_interval(self.low, self.width(), low_count, high_count, total)
+    }
+
+    /// Narrows the interval to `(low, high)`, as returned by
+    /// [`calculate_range`](Self::calculate_range).
     pub fn update_range(&mut self, (low, high): (u64, u64)) {
         self.low = low;
         self.high = high;
     }
 
+    /// Inclusive lower bound of the interval.
+    pub const fn low(&self) -> u64 {
+        self.low
+    }
+
+    /// Exclusive upper bound of the interval.
+    pub const fn high(&self) -> u64 {
+        self.high
+    }
+
+    /// Size of the interval, `high - low`.
+    pub const fn width(&self) -> u64 {
+        self.high - self.low
+    }
+
+    /// Midpoint of the full range, `2^(precision - 1)`.
     pub const fn half(&self) -> u64 {
         self.half
     }
 
+    /// First quarter mark of the full range, also the largest model total
+    /// the range can [represent](Self::can_represent).
     pub const fn quarter(&self) -> u64 {
         self.one_quarter_mark
     }
+
+    /// Third quarter mark of the full range.
+    pub const fn three_quarters(&self) -> u64 {
+        self.three_quarter_mark
+    }
+}
+
+/// [`Range::interval_counts`] for a range starting at `low` of `width`,
+/// shared with [`ConstRange`] so both compute the same intervals.
+const fn scale_interval(
+    low: u64,
+    width: u64,
+    low_count: u64,
+    high_count: u64,
+    total: u64,
+) -> (u64, u64) {
+    // counts are at most `total`, so neither product overflows
+    let narrow = width.checked_mul(total).is_some();
+    (
+        low + scale_count(width, low_count, total, narrow),
+        low + scale_count(width, high_count, total, narrow),
+    )
+}
+
+/// `width * count / total`, in 128-bit arithmetic unless `narrow`.
+const fn scale_count(width: u64, count: u64, total: u64, narrow: bool) -> u64 {
+    // a power of two total, see `Model::normalize`, needs no division
+    let shift = total.trailing_zeros();
+    if narrow {
+        let scaled = width * count;
+        if total.is_power_of_two() {
+            scaled >> shift
+        } else {
+            scaled / total
+        }
+    } else {
+        let scaled = width as u128 * count as u128;
+        if total.is_power_of_two() {
+            (scaled >> shift) as u64
+        } else {
+            (scaled / total as u128) as u64
+        }
+    }
+}
+
+/// A [`Range`] whose precision is fixed at compile time, so the half and
+/// quarter marks are constants the renormalization compares against. It
+/// computes exactly the same intervals as `Range::new(PRECISION)`.
+#[derive(Clone, Debug)]
+pub struct ConstRange<const PRECISION: u64> {
+    high: u64,
+    low: u64,
+}
+
+impl<const PRECISION: u64> Default for ConstRange<PRECISION> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PRECISION: u64> ConstRange<PRECISION> {
+    const ONE: u64 = 1 << PRECISION;
+    const HALF: u64 = Self::ONE / 2;
+    const QUARTER: u64 = Self::ONE / 4;
+    const THREE_QUARTERS: u64 = Self::QUARTER * 3;
+
+    pub fn new() -> Self {
+        assert!(PRECISION < 64);
+        Self {
+            high: Self::ONE,
+            low: 0,
+        }
+    }
+
+    /// See [`Range::can_represent`].
+    pub const fn can_represent(&self, total_count: u64) -> bool {
+        total_count <= Self::QUARTER
+    }
+
+    pub const fn in_bottom_half(&self) -> bool {
+        self.high < Self::HALF
+    }
+
+    pub const fn in_upper_half(&self) -> bool {
+        self.low > Self::HALF
+    }
+
+    pub const fn in_middle_half(&self) -> bool {
+        self.low > Self::QUARTER && self.high < Self::THREE_QUARTERS
+    }
+
+    pub const fn in_bottom_quarter(&self) -> bool {
+        self.low <= Self::QUARTER
+    }
+
+    pub fn scale_upper_half(&mut self) {
+        self.low = (self.low - Self::HALF) << 1;
+        self.high = (self.high - Self::HALF) << 1;
+    }
+
+    pub fn scale_middle_half(&mut self) {
+        self.low = (self.low - Self::QUARTER) << 1;
+        self.high = (self.high - Self::QUARTER) << 1;
+    }
+
+    pub fn scale_bottom_half(&mut self) {
+        self.low <<= 1;
+        self.high <<= 1;
+    }
+
+    /// See [`Range::calculate_range`].
+    /// returns (low, high)
+    pub fn calculate_range<M: SourceModel + ?Sized>(
+        &self,
+        symbol: u32,
+        source_model: &M,
+    ) -> (u64, u64) {
+        let (low, high) = source_model.cumulative(symbol);
+        self.interval_counts(low.into(), high.into(), source_model.total_count().into())
+    }
+
+    /// See [`Range::interval_counts`].
+    /// returns (low, high)
+    pub fn interval_counts(&self, low_count: u64, high_count: u64, total: u64) -> (u64, u64) {
+        debug_assert!(low_count <= high_count && high_count <= total && total > 0);
+        scale_interval(self.low, self.width(), low_count, high_count, total)
+    }
+
+    pub fn update_range(&mut self, (low, high): (u64, u64)) {
+        self.low = low;
+        self.high = high;
+    }
+
+    pub const fn low(&self) -> u64 {
+        self.low
+    }
+
+    pub const fn high(&self) -> u64 {
+        self.high
+    }
+
+    pub const fn width(&self) -> u64 {
+        self.high - self.low
+    }
+
+    pub const fn half(&self) -> u64 {
+        Self::HALF
+    }
+
+    pub const fn quarter(&self) -> u64 {
+        Self::QUARTER
+    }
+
+    pub const fn three_quarters(&self) -> u64 {
+        Self::THREE_QUARTERS
+    }
+}
+
+impl<const PRECISION: u64> From<ConstRange<PRECISION>> for Range {
+    fn from(range: ConstRange<PRECISION>) -> Self {
+        let mut runtime = Self::new(PRECISION);
+        runtime.update_range((range.low, range.high));
+        runtime
+    }
+}
+
+pub(crate) const fn check_precision(precision: u64) -> Result<(), PrecisionError> {
+    if MIN_PRECISION <= precision && precision <= MAX_PRECISION {
+        Ok(())
+    } else {
+        Err(PrecisionError::OutOfRange { precision })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Model, Range};
+    use super::ConstRange;
+    use crate::{error::PrecisionError, Model, Range};
 
     #[test]
     fn constructor() {
@@ -99,6 +371,20 @@ mod tests {
 
         assert_eq!(range.half, range.half());
         assert_eq!(range.one_quarter_mark, range.quarter());
+        assert!(range.can_represent(8));
+        assert!(!range.can_represent(9));
+    }
+
+    #[test]
+    fn try_new() {
+        assert!(Range::try_new(3).is_ok());
+        assert!(Range::try_new(62).is_ok());
+        for &precision in &[0, 2, 63, 64, u64::MAX] {
+            assert_eq!(
+                Range::try_new(precision).err(),
+                Some(PrecisionError::OutOfRange { precision })
+            );
+        }
     }
 
     #[test]
@@ -109,6 +395,41 @@ mod tests {
         assert_eq!(range.calculate_range(0, &model), (0, 85));
         assert_eq!(range.calculate_range(1, &model), (85, 170));
         assert_eq!(range.calculate_range(2, &model), (170, 256));
+
+        let model = Model::builder().counts(vec![1, 2, 3, 4]).build();
+        assert_eq!(range.calculate_range(1, &model), (25, 76));
+        assert_eq!(range.calculate_range_excluding(2, &[1], &model), (32, 128));
+        assert_eq!(range.calculate_range_excluding(1, &[1], &model), (32, 32));
+    }
+
+    #[test]
+    fn interval_counts() {
+        let mut range = Range::new(8);
+        assert_eq!((range.low(), range.high(), range.width()), (0, 256, 256));
+        assert_eq!(range.three_quarters(), 192);
+        assert_eq!(range.interval_counts(0, 1, 3), (0, 85));
+        assert_eq!(range.interval_counts(1, 2, 3), (85, 170));
+        assert_eq!(range.interval_counts(2, 3, 3), (170, 256));
+
+        range.update_range((85, 170));
+        assert_eq!(range.interval_counts(0, 1, 2), (85, 127));
+        assert_eq!(range.interval_counts(1, 2, 2), (127, 170));
+        assert_eq!(range.interval_counts(1, 3, 4), (106, 148));
+
+        let range = Range::new(62);
+        let (low, high) = range.interval_counts(u64::MAX - 1, u64::MAX, u64::MAX);
+        assert!(low < high && high == range.high());
+
+        // either side of `width() * total` overflowing a u64
+        let range = Range::new(40);
+        for &total in &[(1 << 24) - 1, 1 << 24, (1 << 24) + 1] {
+            let count = total / 3;
+            let expected = u128::from(range.width()) * u128::from(count) / u128::from(total);
+            assert_eq!(
+                range.interval_counts(count, total, total),
+                (expected as u64, range.high())
+            );
+        }
     }
 
     #[test]
@@ -140,4 +461,53 @@ mod tests {
         assert!(range.in_middle_half());
         assert!(!range.in_bottom_quarter());
     }
+
+    #[test]
+    fn const_range() {
+        let mut model = Model::builder().counts(vec![5, 1, 9, 2]).build();
+        let mut range = Range::new(20);
+        let mut fixed = ConstRange::<20>::new();
+        assert!(fixed.can_represent(1 << 18) && !fixed.can_represent((1 << 18) + 1));
+        assert_eq!(
+            (fixed.half(), fixed.quarter(), fixed.three_quarters()),
+            (range.half(), range.quarter(), range.three_quarters())
+        );
+
+        for i in 0..2000 {
+            let symbol = (i * 7 + i / 5) % 4;
+            assert_eq!(
+                fixed.calculate_range(symbol, &model),
+                range.calculate_range(symbol, &model)
+            );
+            range.update_range(range.calculate_range(symbol, &model));
+            fixed.update_range(fixed.calculate_range(symbol, &model));
+            model.update_symbol(symbol);
+
+            while range.in_bottom_half() || range.in_upper_half() || range.in_middle_half() {
+                assert_eq!(fixed.in_bottom_half(), range.in_bottom_half());
+                assert_eq!(fixed.in_upper_half(), range.in_upper_half());
+                if range.in_bottom_half() {
+                    range.scale_bottom_half();
+                    fixed.scale_bottom_half();
+                } else if range.in_upper_half() {
+                    range.scale_upper_half();
+                    fixed.scale_upper_half();
+                } else {
+                    assert!(fixed.in_middle_half());
+                    range.scale_middle_half();
+                    fixed.scale_middle_half();
+                }
+            }
+            assert!(!fixed.in_middle_half());
+            assert_eq!((fixed.low(), fixed.high()), (range.low(), range.high()));
+            assert_eq!(fixed.in_bottom_quarter(), range.in_bottom_quarter());
+        }
+
+        let (low, high) = (fixed.low(), fixed.high());
+        let range: Range = fixed.into();
+        assert_eq!(
+            (range.low(), range.high(), range.half()),
+            (low, high, 1 << 19)
+        );
+    }
 }
@@ -0,0 +1,255 @@
+//! A byte-wise range coder (Subbotin's carryless scheme) sharing [`Model`]
+//! with the arithmetic coder.
+//!
+//! Renormalizing a byte at a time instead of a bit at a time makes it
+//! several times faster, at the cost of a slightly worse compression ratio
+//! and its own stream format: it reads and writes plain bytes through
+//! [`Read`] and [`Write`], not bitbit streams, and can not be mixed with
+//! [`ArithmeticEncoder`](crate::ArithmeticEncoder) output.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use crate::{
+    error::{DecodeError, DecodeFailure},
+    Model, SourceModel,
+};
+
+/// `low` and `low + range` agreeing above this many bits means the top byte
+/// is settled.
+const TOP: u64 = 1 << 56;
+/// The smallest range before it is forced down to the next byte boundary,
+/// which leaves room for any `u32` total.
+const BOTTOM: u64 = 1 << 48;
+
+pub struct RangeEncoder {
+    low: u64,
+    range: u64,
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RangeEncoder {
+    pub const fn new() -> Self {
+        Self {
+            low: 0,
+            range: u64::MAX,
+        }
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        symbol: u32,
+        source_model: &Model,
+        output: &mut W,
+    ) -> Result<(), Error> {
+        source_model.check_codable(symbol)?;
+        let (low, high) = source_model.cumulative(symbol);
+
+        let step = self.range / u64::from(source_model.total_count());
+        self.low = self.low.wrapping_add(step * u64::from(low));
+        self.range = step * u64::from(high - low);
+
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOTTOM {
+                    break;
+                }
+                // give up the part of the range above the next byte
+                // boundary so no carry can reach the bytes already written
+                self.range = self.low.wrapping_neg() & (BOTTOM - 1);
+            }
+            output.write_all(&[(self.low >> 56) as u8])?;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+        Ok(())
+    }
+
+    /// Writes the bytes the decoder needs to decode the last symbol.
+    pub fn finish_encode<W: Write>(&mut self, output: &mut W) -> Result<(), Error> {
+        output.write_all(&self.low.to_be_bytes())
+    }
+}
+
+pub struct RangeDecoder {
+    low: u64,
+    range: u64,
+    code: u64,
+    first_time: bool,
+    finished: bool,
+    bytes_read: u64,
+    symbols_decoded: u64,
+}
+
+impl Default for RangeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RangeDecoder {
+    pub const fn new() -> Self {
+        Self {
+            low: 0,
+            range: u64::MAX,
+            code: 0,
+            first_time: true,
+            finished: false,
+            bytes_read: 0,
+            symbols_decoded: 0,
+        }
+    }
+
+    pub fn decode<R: Read>(&mut self, source_model: &Model, input: &mut R) -> Result<u32, Error> {
+        if self.first_time {
+            for _ in 0..8 {
+                self.code = (self.code << 8) | self.byte(input)?;
+            }
+            self.first_time = false;
+        }
+
+        let total = source_model.total_count();
+        if total == 0 {
+            return Err(self.error(DecodeFailure::NoMatchingSymbol));
+        }
+        let step = self.range / u64::from(total);
+        let count = self.code.wrapping_sub(self.low) / step;
+        if count >= u64::from(total) {
+            return Err(self.error(DecodeFailure::NoMatchingSymbol));
+        }
+        let symbol = source_model.symbol_at(count as u32);
+        let (low, high) = source_model.cumulative(symbol);
+        self.symbols_decoded += 1;
+        self.finished = symbol == source_model.eof();
+
+        self.low = self.low.wrapping_add(step * u64::from(low));
+        self.range = step * u64::from(high - low);
+
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOTTOM {
+                    break;
+                }
+                self.range = self.low.wrapping_neg() & (BOTTOM - 1);
+            }
+            // stays 8 bytes ahead, so after the last symbol exactly the
+            // bytes of `finish_encode` have been read
+            self.code = (self.code << 8) | self.byte(input)?;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+        Ok(symbol)
+    }
+
+    fn byte<R: Read>(&mut self, input: &mut R) -> Result<u64, Error> {
+        let mut byte = [0];
+        match input.read_exact(&mut byte) {
+            Ok(()) => {
+                self.bytes_read += 1;
+                Ok(u64::from(byte[0]))
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                Err(self.error(DecodeFailure::MissingEof))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn error(&self, failure: DecodeFailure) -> Error {
+        DecodeError {
+            failure,
+            bit_offset: self.bytes_read * 8,
+            symbols_decoded: self.symbols_decoded,
+        }
+        .into()
+    }
+
+    pub const fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RangeDecoder, RangeEncoder};
+    use crate::{error::DecodeFailure, EOFKind, Model};
+
+    fn roundtrip(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut encoder = RangeEncoder::new();
+        let mut compressed = Vec::new();
+        for &byte in data {
+            encoder
+                .encode(byte.into(), &model, &mut compressed)
+                .unwrap();
+            model.update_symbol(byte.into());
+        }
+        encoder
+            .encode(model.eof(), &model, &mut compressed)
+            .unwrap();
+        encoder.finish_encode(&mut compressed).unwrap();
+
+        let mut model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut decoder = RangeDecoder::new();
+        let mut input = compressed.as_slice();
+        let mut decompressed = Vec::new();
+        loop {
+            let symbol = decoder.decode(&model, &mut input).unwrap();
+            if decoder.finished() {
+                break;
+            }
+            model.update_symbol(symbol);
+            decompressed.push(symbol as u8);
+        }
+        assert!(input.is_empty());
+        (compressed, decompressed)
+    }
+
+    #[test]
+    fn e2e() {
+        let sherlock = include_str!("../tests/sherlock.rs").as_bytes();
+        let mut skewed = vec![0u8; 5000];
+        skewed.extend((0..=255).cycle().take(3000));
+        for data in &[&b""[..], b"a", &[255; 300], &skewed, sherlock] {
+            let (compressed, decompressed) = roundtrip(data);
+            assert_eq!(&decompressed, data);
+            if data.len() > 1000 {
+                assert!(compressed.len() < data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn zero_counts() {
+        let model = Model::builder().counts(vec![3, 0, 5]).build();
+        let mut encoder = RangeEncoder::new();
+        let mut compressed = Vec::new();
+        assert!(encoder.encode(1, &model, &mut compressed).is_err());
+        for &symbol in &[0, 2, 2, 0, 2] {
+            encoder.encode(symbol, &model, &mut compressed).unwrap();
+        }
+        encoder.finish_encode(&mut compressed).unwrap();
+
+        let mut decoder = RangeDecoder::new();
+        let mut input = compressed.as_slice();
+        let decoded: Vec<u32> = (0..5)
+            .map(|_| decoder.decode(&model, &mut input).unwrap())
+            .collect();
+        assert_eq!(decoded, vec![0, 2, 2, 0, 2]);
+    }
+
+    #[test]
+    fn truncated() {
+        let model = Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build();
+        let mut decoder = RangeDecoder::new();
+        let error = decoder.decode(&model, &mut &[1u8, 2, 3][..]).unwrap_err();
+        let error = error.into_inner().unwrap();
+        let error = error.downcast_ref::<crate::error::DecodeError>().unwrap();
+        assert_eq!(error.failure, DecodeFailure::MissingEof);
+        assert_eq!(error.bit_offset, 24);
+    }
+}
@@ -0,0 +1,276 @@
+//! A decoder that can step back over the last few symbols it decoded.
+//!
+//! Parsers that guess at the structure of their input sometimes need to
+//! back out of a misparsed region. [`RewindableDecoder`] keeps a copy of the
+//! decoder state every `interval` symbols along with the symbols decoded
+//! since, which are the only changes made to the model. To rewind it undoes
+//! those model updates back to the closest checkpoint, restores the decoder
+//! and decodes forward again to the requested position, so a rewind costs
+//! at most `interval` symbols of decoding no matter how long the stream is.
+//! Rescaling, decay and batching can not be undone, so a model with
+//! [`rescale_at`](crate::model::Builder::rescale_at),
+//! [`decay`](crate::model::Builder::decay) or
+//! [`batch`](crate::model::Builder::batch) set is copied into every
+//! checkpoint instead.
+
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind},
+};
+
+use bitbit::{BitReader, MSB};
+
+use crate::{ArithmeticDecoder, Model};
+
+struct Checkpoint {
+    position: u64,
+    decoder: ArithmeticDecoder,
+    /// The model, if its updates can not be undone.
+    model: Option<Model>,
+}
+
+/// Decodes an in-memory stream with a single adaptive model, which is
+/// updated with every decoded symbol, and can [`rewind`](Self::rewind) by up
+/// to `max_rewind` symbols.
+pub struct RewindableDecoder<'a> {
+    input: &'a [u8],
+    reader: BitReader<&'a [u8], MSB>,
+    decoder: ArithmeticDecoder,
+    model: Model,
+    max_rewind: u64,
+    interval: u64,
+    checkpoints: VecDeque<Checkpoint>,
+    /// Every symbol decoded since the oldest checkpoint.
+    symbols: VecDeque<u32>,
+    position: u64,
+}
+
+impl<'a> RewindableDecoder<'a> {
+    /// `interval` trades memory for rewind speed: a checkpoint is taken
+    /// every `interval` symbols and rewinding decodes up to `interval - 1`
+    /// symbols again.
+    pub fn new(
+        input: &'a [u8],
+        model: Model,
+        precision: u64,
+        max_rewind: u64,
+        interval: u64,
+    ) -> Self {
+        assert!(interval > 0, "interval must be at least 1");
+        Self {
+            input,
+            reader: BitReader::new(input),
+            decoder: ArithmeticDecoder::new(precision),
+            model,
+            max_rewind,
+            interval,
+            checkpoints: VecDeque::new(),
+            symbols: VecDeque::new(),
+            position: 0,
+        }
+    }
+
+    /// Decodes the next symbol and updates the model with it.
+    pub fn decode(&mut self) -> Result<u32, Error> {
+        if self.position % self.interval == 0 {
+            self.checkpoints.push_back(Checkpoint {
+                position: self.position,
+                decoder: self.decoder.clone(),
+                model: if self.model.is_revertible() {
+                    None
+                } else {
+                    Some(self.model.clone())
+                },
+            });
+        }
+
+        let symbol = self.decoder.decode(&self.model, &mut self.reader)?;
+        self.model.update_symbol(symbol);
+        self.symbols.push_back(symbol);
+        self.position += 1;
+        self.forget();
+        Ok(symbol)
+    }
+
+    /// Drops the checkpoints and symbols that are too old to rewind to.
+    fn forget(&mut self) {
+        let oldest = self.position.saturating_sub(self.max_rewind);
+        while self.checkpoints.len() > 1 && self.checkpoints[1].position <= oldest {
+            let dropped = self.checkpoints.pop_front().unwrap();
+            for _ in dropped.position..self.checkpoints[0].position {
+                self.symbols.pop_front();
+            }
+        }
+    }
+
+    /// Puts the decoder and model back to where they were `symbols` symbols
+    /// ago.
+    pub fn rewind(&mut self, symbols: u64) -> Result<(), Error> {
+        if symbols > self.max_rewind || symbols > self.position {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "can not rewind {} symbols from position {} with a limit of {}",
+                    symbols, self.position, self.max_rewind
+                ),
+            ));
+        }
+        if symbols == 0 {
+            return Ok(());
+        }
+
+        let target = self.position - symbols;
+        while self.checkpoints.back().unwrap().position > target {
+            self.checkpoints.pop_back();
+        }
+        let checkpoint = self.checkpoints.back().unwrap();
+        let start = self.checkpoints.front().unwrap().position;
+
+        // undo the model updates back to the checkpoint
+        for _ in checkpoint.position..self.position {
+            let symbol = self.symbols.pop_back().unwrap();
+            if checkpoint.model.is_none() {
+                self.model.revert_symbol(symbol);
+            }
+        }
+        if let Some(model) = &checkpoint.model {
+            self.model = model.clone();
+        }
+
+        self.decoder = checkpoint.decoder.clone();
+        let bit = self.decoder.source_position();
+        self.reader = BitReader::new(&self.input[(bit / 8) as usize..]);
+        for _ in 0..bit % 8 {
+            self.reader.read_bit()?;
+        }
+
+        // decode forward again without taking new checkpoints
+        self.position = checkpoint.position;
+        while self.position < target {
+            let symbol = self.decoder.decode(&self.model, &mut self.reader)?;
+            self.model.update_symbol(symbol);
+            self.symbols.push_back(symbol);
+            self.position += 1;
+        }
+        debug_assert_eq!(self.symbols.len() as u64, target - start);
+        Ok(())
+    }
+
+    /// Number of symbols decoded, minus the ones rewound.
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub const fn finished(&self) -> bool {
+        self.decoder.finished()
+    }
+
+    pub const fn model(&self) -> &Model {
+        &self.model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::BitWriter;
+
+    use super::RewindableDecoder;
+    use crate::{ArithmeticEncoder, EOFKind, Model};
+
+    fn model() -> Model {
+        Model::builder().num_symbols(10).eof(EOFKind::End).build()
+    }
+
+    fn encode(data: &[u32], mut model: Model) -> Vec<u8> {
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in data.iter().chain(Some(&9)) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        out_writer.get_ref().get_ref().clone()
+    }
+
+    #[test]
+    fn rewind() {
+        let data: Vec<u32> = (0..200u32).map(|i| (i * i + i / 7) % 9).collect();
+        let encoded = encode(&data, model());
+
+        let mut decoder = RewindableDecoder::new(&encoded, model(), 32, 20, 8);
+        for (i, &symbol) in data[..50].iter().enumerate() {
+            assert_eq!(decoder.decode().unwrap(), symbol, "symbol {}", i);
+        }
+        for &back in &[1, 20, 0, 7, 8, 9, 16] {
+            let position = decoder.position();
+            decoder.rewind(back).unwrap();
+            assert_eq!(decoder.position(), position - back);
+
+            let mut reference = model();
+            data[..decoder.position() as usize]
+                .iter()
+                .for_each(|&symbol| reference.update_symbol(symbol));
+            assert_eq!(decoder.model().counts(), reference.counts());
+            assert_eq!(decoder.model().fenwick_counts(), reference.fenwick_counts());
+
+            let resume = decoder.position() as usize;
+            for &symbol in &data[resume..resume + 30] {
+                assert_eq!(decoder.decode().unwrap(), symbol);
+            }
+        }
+
+        assert!(decoder.rewind(21).is_err());
+        while !decoder.finished() {
+            decoder.decode().unwrap();
+        }
+        decoder.rewind(3).unwrap();
+        assert!(!decoder.finished());
+        assert_eq!(decoder.decode().unwrap(), data[data.len() - 2]);
+    }
+
+    #[test]
+    fn rewind_to_start() {
+        let data = [1, 2, 3, 4, 5];
+        let encoded = encode(&data, model());
+        let mut decoder = RewindableDecoder::new(&encoded, model(), 32, 100, 3);
+        assert!(decoder.rewind(1).is_err());
+        for _ in 0..4 {
+            decoder.decode().unwrap();
+        }
+        decoder.rewind(4).unwrap();
+        assert_eq!(decoder.model().counts(), model().counts());
+        for &symbol in &data {
+            assert_eq!(decoder.decode().unwrap(), symbol);
+        }
+    }
+
+    #[test]
+    fn rewind_across_rescale() {
+        let rescaling = || {
+            Model::builder()
+                .num_symbols(10)
+                .eof(EOFKind::End)
+                .rescale_at(24)
+                .build()
+        };
+        let data: Vec<u32> = (0..100u32).map(|i| (i * i + i / 7) % 9).collect();
+        let encoded = encode(&data, rescaling());
+
+        let mut decoder = RewindableDecoder::new(&encoded, rescaling(), 32, 20, 8);
+        for &symbol in &data[..50] {
+            assert_eq!(decoder.decode().unwrap(), symbol);
+        }
+        decoder.rewind(13).unwrap();
+        let mut reference = rescaling();
+        data[..37]
+            .iter()
+            .for_each(|&symbol| reference.update_symbol(symbol));
+        assert_eq!(decoder.model().counts(), reference.counts());
+        for &symbol in &data[37..] {
+            assert_eq!(decoder.decode().unwrap(), symbol);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Coding blocks of mostly zero values, such as quantized transform
+//! coefficients, as runs of zeros followed by a non-zero level.
+//!
+//! Each non-zero value is preceded by a run token (the number of zeros
+//! before it, up to [`MAX_RUN`]). Longer runs are split with a zero run
+//! length token that stands for `MAX_RUN + 1` zeros, and an end of block
+//! token stands for all of the zeros after the last non-zero value. A level
+//! is coded as its magnitude class (bit length), its sign and then the bits
+//! below its leading one with models dedicated to that class.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{binary::BinaryCoder, ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Longest run of zeros that fits in a single run token.
+pub const MAX_RUN: u32 = 15;
+const ZERO_RUN: u32 = MAX_RUN + 1;
+const END_OF_BLOCK: u32 = MAX_RUN + 2;
+const CLASSES: u32 = 32;
+
+pub struct RunLevelCoder {
+    runs: Model,
+    classes: Model,
+    signs: Model,
+    /// `magnitudes[class - 1]` codes the `class - 1` bits below the leading
+    /// one.
+    magnitudes: Vec<BinaryCoder>,
+}
+
+impl Default for RunLevelCoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunLevelCoder {
+    pub fn new() -> Self {
+        Self {
+            runs: Model::builder().num_symbols(END_OF_BLOCK + 1).build(),
+            classes: Model::builder().num_symbols(CLASSES).build(),
+            signs: Model::builder().binary().build(),
+            magnitudes: (0..CLASSES).map(BinaryCoder::new).collect(),
+        }
+    }
+
+    pub fn encode_block<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        block: &[i32],
+    ) -> Result<(), Error> {
+        let mut run = 0;
+        for &level in block {
+            if level == 0 {
+                run += 1;
+                continue;
+            }
+
+            while run > MAX_RUN {
+                self.encode_run(encoder, output, ZERO_RUN)?;
+                run -= ZERO_RUN;
+            }
+            self.encode_run(encoder, output, run)?;
+            run = 0;
+
+            let magnitude = level.unsigned_abs();
+            let class = 32 - magnitude.leading_zeros();
+            encoder.encode(class - 1, &self.classes, output)?;
+            self.classes.update_symbol(class - 1);
+
+            let sign = u32::from(level < 0);
+            encoder.encode(sign, &self.signs, output)?;
+            self.signs.update_symbol(sign);
+
+            let below_leading = magnitude ^ (1 << (class - 1));
+            self.magnitudes[class as usize - 1].encode(encoder, output, below_leading)?;
+        }
+
+        if run > 0 {
+            self.encode_run(encoder, output, END_OF_BLOCK)?;
+        }
+        Ok(())
+    }
+
+    fn encode_run<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        token: u32,
+    ) -> Result<(), Error> {
+        encoder.encode(token, &self.runs, output)?;
+        self.runs.update_symbol(token);
+        Ok(())
+    }
+
+    /// Decodes a block encoded with [`encode_block`](Self::encode_block) into
+    /// `block`, which must have the same length.
+    pub fn decode_block<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+        block: &mut [i32],
+    ) -> Result<(), Error> {
+        let mut position = 0;
+        while position < block.len() {
+            let token = decoder.decode(&self.runs, input)?;
+            self.runs.update_symbol(token);
+
+            let run = match token {
+                END_OF_BLOCK => {
+                    block[position..].iter_mut().for_each(|value| *value = 0);
+                    return Ok(());
+                }
+                ZERO_RUN => {
+                    Self::fill_zeros(block, &mut position, ZERO_RUN as usize)?;
+                    continue;
+                }
+                run => run as usize,
+            };
+            Self::fill_zeros(block, &mut position, run)?;
+            if position == block.len() {
+                return Err(Self::overrun());
+            }
+
+            let class = decoder.decode(&self.classes, input)? + 1;
+            self.classes.update_symbol(class - 1);
+
+            let sign = decoder.decode(&self.signs, input)?;
+            self.signs.update_symbol(sign);
+
+            let below_leading = self.magnitudes[class as usize - 1].decode(decoder, input)?;
+            let magnitude = (1u32 << (class - 1)) | below_leading;
+            // wrapping so a magnitude of 2^31 becomes i32::MIN
+            let level = magnitude as i32;
+            block[position] = if sign == 1 {
+                level.wrapping_neg()
+            } else {
+                level
+            };
+            position += 1;
+        }
+        Ok(())
+    }
+
+    fn fill_zeros(block: &mut [i32], position: &mut usize, run: usize) -> Result<(), Error> {
+        let end = *position + run;
+        if end > block.len() {
+            return Err(Self::overrun());
+        }
+        block[*position..end]
+            .iter_mut()
+            .for_each(|value| *value = 0);
+        *position = end;
+        Ok(())
+    }
+
+    fn overrun() -> Error {
+        Error::new(ErrorKind::InvalidData, "run of zeros runs past the block")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::RunLevelCoder;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder};
+
+    #[test]
+    fn e2e() {
+        let mut long_run = vec![0; 64];
+        long_run[40] = 3;
+        long_run[63] = -1;
+        let blocks = vec![
+            vec![12, 0, 0, -3, 1, 0, 0, 0],
+            vec![0; 8],
+            vec![5, 0, 0, 0, 0, 0, 0, 2],
+            vec![i32::MAX, i32::MIN, 0, 0],
+            long_run,
+            vec![],
+        ];
+
+        let mut coder = RunLevelCoder::new();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for block in &blocks {
+            coder
+                .encode_block(&mut encoder, &mut out_writer, block)
+                .unwrap();
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut coder = RunLevelCoder::new();
+        let mut decoder = ArithmeticDecoder::new(48);
+        let input = Cursor::new(out_writer.get_ref().get_ref().clone());
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input);
+        for block in &blocks {
+            // garbage to make sure every value is written
+            let mut decoded = vec![7; block.len()];
+            coder
+                .decode_block(&mut decoder, &mut in_reader, &mut decoded)
+                .unwrap();
+            assert_eq!(&decoded, block);
+        }
+    }
+}
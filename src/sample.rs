@@ -0,0 +1,140 @@
+//! Generating symbol streams from models by arithmetic decoding bits.
+//!
+//! Decoding uniformly random bits produces symbols distributed as the model
+//! says, so the models used for compression can produce synthetic test
+//! data. Decoding a payload instead hides it in a symbol stream that looks
+//! like it was drawn from the model, and encoding that stream with the same
+//! model gives the payload back, see [`embed`] and [`extract`].
+
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use bitbit::{reader::Bit, BitReader, BitWriter, MSB};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// Decodes `len` symbols following `model` from `bit_source`. Once the
+/// source runs out it reads as zeros. A model with an EOF symbol stops the
+/// stream early if the EOF is decoded.
+pub fn generate<R: Read, B: Bit>(
+    model: &Model,
+    bit_source: &mut BitReader<R, B>,
+    precision: u64,
+    len: usize,
+) -> Result<Vec<u32>, Error> {
+    let mut decoder = ArithmeticDecoder::new(precision);
+    let mut symbols = Vec::with_capacity(len);
+    while symbols.len() < len && !decoder.finished() {
+        symbols.push(decoder.decode(model, bit_source)?);
+    }
+    Ok(symbols)
+}
+
+/// Turns `payload` into symbols that follow `model`, decoding just enough
+/// of them to pin down every payload bit. `model` must not have an EOF
+/// symbol and needs at least two symbols with a non-zero count, as symbols
+/// of a model with only one carry no bits.
+pub fn embed(payload: &[u8], model: &Model, precision: u64) -> Result<Vec<u32>, Error> {
+    if model.counts().iter().filter(|&&count| count > 0).count() < 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "embedding needs a model with at least two symbols with a non-zero count",
+        ));
+    }
+    let payload_bits = payload.len() as u64 * 8;
+    // the decoder reads ahead of the bits it has pinned down
+    let mut bit_source: BitReader<_, MSB> = BitReader::new(payload.chain(Filler(FILLER_SEED)));
+    let mut decoder = ArithmeticDecoder::new(precision);
+    let mut symbols = Vec::new();
+    // every settled bit is one the encoder will write
+    while decoder.bits_settled() < payload_bits {
+        symbols.push(decoder.decode(model, &mut bit_source)?);
+    }
+    Ok(symbols)
+}
+
+const FILLER_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// Endless xorshift bytes read after the payload. Padding with zeros can
+/// leave the input exactly on a half boundary, where the decoder scales
+/// the middle half forever without settling another bit.
+struct Filler(u64);
+
+impl Read for Filler {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        for byte in buf.iter_mut() {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            *byte = (self.0 >> 56) as u8;
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Recovers `payload_len` bytes embedded with [`embed`] using the same
+/// model and precision.
+pub fn extract(
+    symbols: &[u32],
+    model: &Model,
+    precision: u64,
+    payload_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut encoder = ArithmeticEncoder::new(precision);
+    let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+    for &symbol in symbols {
+        encoder.encode(symbol, model, &mut out_writer)?;
+    }
+    encoder.finish_encode(&mut out_writer)?;
+    out_writer.pad_to_byte()?;
+
+    let mut payload = out_writer.get_ref().get_ref().clone();
+    payload.resize(payload_len, 0);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::{embed, extract, generate};
+    use crate::Model;
+    use bitbit::{BitReader, MSB};
+
+    #[test]
+    fn generate_follows_model() {
+        let model = Model::builder().counts(vec![8, 1, 1, 6]).build();
+        let random: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut bit_source: BitReader<_, MSB> = BitReader::new(random.as_slice());
+        let symbols = generate(&model, &mut bit_source, 32, 8000).unwrap();
+        assert_eq!(symbols.len(), 8000);
+
+        let mut histogram = [0i32; 4];
+        symbols
+            .iter()
+            .for_each(|&symbol| histogram[symbol as usize] += 1);
+        for (&observed, &expected) in histogram.iter().zip(&[4000, 500, 500, 3000]) {
+            assert!((observed - expected).abs() < 300, "{:?}", histogram);
+        }
+    }
+
+    #[test]
+    fn embed_and_extract() {
+        let model = Model::builder().counts(vec![20, 5, 3, 1, 1]).build();
+        for payload in &[&b""[..], b"x", b"hidden in plain sight", &[0xFF; 40]] {
+            let symbols = embed(payload, &model, 40).unwrap();
+            assert_eq!(symbols.is_empty(), payload.is_empty());
+            assert_eq!(
+                &extract(&symbols, &model, 40, payload.len()).unwrap(),
+                payload
+            );
+        }
+
+        for counts in [vec![3], vec![0, 4, 0]] {
+            let model = Model::builder().counts(counts).build();
+            let error = embed(b"x", &model, 40).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
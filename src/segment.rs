@@ -0,0 +1,228 @@
+//! Streams made of several independently decodable segments.
+//!
+//! A segment ends with
+//! [`ArithmeticEncoder::finish_segment`](crate::ArithmeticEncoder::finish_segment)
+//! (or [`finish_encode`](crate::ArithmeticEncoder::finish_encode) followed
+//! by padding to a byte), so segments always end on a byte boundary. The
+//! decoder finds the end of a segment once all of its symbols are decoded
+//! and moves on with
+//! [`ArithmeticDecoder::continue_with_next_segment`](crate::ArithmeticDecoder::continue_with_next_segment).
+//! Appending to a log therefore never requires re-encoding what is already
+//! there.
+//!
+//! Sections are segments that each start with their own precision, written
+//! in-band by
+//! [`ArithmeticEncoder::finish_section`](crate::ArithmeticEncoder::finish_section)
+//! and picked up by
+//! [`ArithmeticDecoder::continue_with_next_section`](crate::ArithmeticDecoder::continue_with_next_section).
+
+/// Joins encoded segments (or whole streams ending at a flush point) into a
+/// single stream that decodes them in order.
+pub fn splice<'a, I: IntoIterator<Item = &'a [u8]>>(segments: I) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for segment in segments {
+        stream.extend_from_slice(segment);
+    }
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::splice;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    fn model() -> Model {
+        Model::builder().num_symbols(6).eof(EOFKind::End).build()
+    }
+
+    fn encode(data: &[u32], precision: u64) -> Vec<u8> {
+        let mut model = model();
+        let mut encoder = ArithmeticEncoder::new(precision);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for &symbol in data.iter().chain(Some(&5)) {
+            encoder.encode(symbol, &model, &mut out_writer).unwrap();
+            model.update_symbol(symbol);
+        }
+        encoder.finish_segment(&mut out_writer).unwrap();
+        out_writer.get_ref().get_ref().clone()
+    }
+
+    fn decode_segments(stream: &[u8], segments: usize, precision: u64) -> Vec<Vec<u32>> {
+        let mut decoder = ArithmeticDecoder::new(precision);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(stream));
+        let mut decoded = vec![];
+        for segment in 0..segments {
+            if segment > 0 {
+                decoder.continue_with_next_segment(&mut in_reader).unwrap();
+            }
+            let mut model = model();
+            let mut symbols = vec![];
+            while !decoder.finished() {
+                let info = decoder.decode_with_info(&model, &mut in_reader).unwrap();
+                assert_eq!(info.after_resync, segment > 0);
+                model.update_symbol(info.symbol);
+                symbols.push(info.symbol);
+            }
+            symbols.pop();
+            decoded.push(symbols);
+        }
+        assert!(decoder.continue_with_next_segment(&mut in_reader).is_err());
+        assert_eq!(decoder.resyncs(), segments as u64 - 1);
+        decoded
+    }
+
+    #[test]
+    fn splice_streams() {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        for &precision in &[8, 12, 32, 48] {
+            let segments: Vec<Vec<u32>> = (0..20)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let len = (state % 30) as usize;
+                    (0..len).map(|i| (state >> (i % 60)) as u32 % 5).collect()
+                })
+                .collect();
+
+            let encoded: Vec<Vec<u8>> = segments
+                .iter()
+                .map(|data| encode(data, precision))
+                .collect();
+            let stream = splice(encoded.iter().map(Vec::as_slice));
+            assert_eq!(
+                decode_segments(&stream, segments.len(), precision),
+                segments
+            );
+        }
+    }
+
+    #[test]
+    fn finish_segment() {
+        // one encoder writing several segments is the same as splicing
+        let segments = [vec![1, 2, 3], vec![], vec![4, 4, 4, 4, 0]];
+        let mut encoder = ArithmeticEncoder::new(32);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for data in &segments {
+            let mut model = model();
+            for &symbol in data.iter().chain(Some(&5)) {
+                encoder.encode(symbol, &model, &mut out_writer).unwrap();
+                model.update_symbol(symbol);
+            }
+            encoder.finish_segment(&mut out_writer).unwrap();
+        }
+
+        let stream = out_writer.get_ref().get_ref().clone();
+        let spliced = splice(
+            segments
+                .iter()
+                .map(|data| encode(data, 32))
+                .collect::<Vec<_>>()
+                .iter()
+                .map(Vec::as_slice),
+        );
+        assert_eq!(stream, spliced);
+        assert_eq!(decode_segments(&stream, 3, 32), segments);
+    }
+
+    #[test]
+    fn sections() {
+        let sections: [(u64, Vec<u32>); 5] = [
+            (48, vec![0, 1, 2]),
+            (12, (0..100).map(|i| i % 5).collect()),
+            (62, vec![]),
+            (20, vec![3, 3, 3, 3, 3, 3, 3, 3, 3]),
+            (10, vec![4]),
+        ];
+
+        let mut encoder = ArithmeticEncoder::new(sections[0].0);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for (i, (_, data)) in sections.iter().enumerate() {
+            if i > 0 {
+                encoder
+                    .finish_section(&mut out_writer, sections[i].0)
+                    .unwrap();
+            }
+            let mut model = model();
+            for &symbol in data.iter().chain(Some(&5)) {
+                encoder.encode(symbol, &model, &mut out_writer).unwrap();
+                model.update_symbol(symbol);
+            }
+        }
+        encoder.finish_segment(&mut out_writer).unwrap();
+        assert!(encoder.finish_section(&mut out_writer, 64).is_err());
+
+        let stream = out_writer.get_ref().get_ref().clone();
+        let mut decoder = ArithmeticDecoder::new(sections[0].0);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(&stream));
+        for (i, (_, data)) in sections.iter().enumerate() {
+            if i > 0 {
+                decoder.continue_with_next_section(&mut in_reader).unwrap();
+            }
+            let mut model = model();
+            let mut symbols = vec![];
+            while !decoder.finished() {
+                let symbol = decoder.decode(&model, &mut in_reader).unwrap();
+                model.update_symbol(symbol);
+                symbols.push(symbol);
+            }
+            symbols.pop();
+            assert_eq!(&symbols, data);
+        }
+        assert!(decoder.continue_with_next_section(&mut in_reader).is_err());
+    }
+
+    #[test]
+    fn random_sections() {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let sections: Vec<(u64, Vec<u32>)> = (0..40)
+            .map(|_| {
+                let precision = 10 + next() % 53;
+                let len = next() % 20;
+                (precision, (0..len).map(|_| (next() % 5) as u32).collect())
+            })
+            .collect();
+
+        let mut encoder = ArithmeticEncoder::new(sections[0].0);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        for (i, (precision, data)) in sections.iter().enumerate() {
+            if i > 0 {
+                encoder.finish_section(&mut out_writer, *precision).unwrap();
+            }
+            let mut model = model();
+            for &symbol in data.iter().chain(Some(&5)) {
+                encoder.encode(symbol, &model, &mut out_writer).unwrap();
+                model.update_symbol(symbol);
+            }
+        }
+        encoder.finish_segment(&mut out_writer).unwrap();
+
+        let stream = out_writer.get_ref().get_ref().clone();
+        let mut decoder = ArithmeticDecoder::new(sections[0].0);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(&stream));
+        for (i, (_, data)) in sections.iter().enumerate() {
+            if i > 0 {
+                decoder.continue_with_next_section(&mut in_reader).unwrap();
+            }
+            let mut model = model();
+            let mut symbols = vec![];
+            while !decoder.finished() {
+                let symbol = decoder.decode(&model, &mut in_reader).unwrap();
+                model.update_symbol(symbol);
+                symbols.push(symbol);
+            }
+            symbols.pop();
+            assert_eq!(&symbols, data);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Saving an encoder or decoder mid-stream and restoring it later, to
+//! resume long running jobs after a restart.
+//!
+//! A [`CoderState`] holds the coder's own registers only. The caller keeps
+//! the rest of the stream consistent: models must be rebuilt exactly as they
+//! were at the checkpoint, a restored encoder appends to the bits written
+//! before it (including any a [`BitWriter`](bitbit::BitWriter) still held in
+//! its partial byte) and a restored decoder reads on from
+//! [`bits_read`](CoderState::bits_read) bits into the input.
+
+use std::io::{Error, ErrorKind};
+
+use crate::{range::check_precision, Range};
+
+const ENCODER_TAG: u8 = b'E';
+const DECODER_TAG: u8 = b'D';
+
+/// Registers of an [`ArithmeticEncoder`](crate::ArithmeticEncoder), from
+/// [`checkpoint`](crate::ArithmeticEncoder::checkpoint), or an
+/// [`ArithmeticDecoder`](crate::ArithmeticDecoder), from
+/// [`checkpoint`](crate::ArithmeticDecoder::checkpoint).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoderState {
+    decoder: bool,
+    words: Vec<u64>,
+}
+
+impl CoderState {
+    pub(crate) fn encoder(words: Vec<u64>) -> Self {
+        Self {
+            decoder: false,
+            words,
+        }
+    }
+
+    pub(crate) fn decoder(words: Vec<u64>) -> Self {
+        Self {
+            decoder: true,
+            words,
+        }
+    }
+
+    pub const fn is_decoder(&self) -> bool {
+        self.decoder
+    }
+
+    /// For a decoder, the number of bits it had read from its input, which
+    /// is where a restored decoder continues reading.
+    pub fn bits_read(&self) -> Option<u64> {
+        if self.decoder {
+            Some(self.words[crate::decode::BITS_READ_WORD])
+        } else {
+            None
+        }
+    }
+
+    /// A tag byte followed by the registers as little endian `u64`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.words.len() * 8);
+        bytes.push(if self.decoder {
+            DECODER_TAG
+        } else {
+            ENCODER_TAG
+        });
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Reads a state written with [`to_bytes`](Self::to_bytes). Whether it
+    /// describes a usable coder is checked when it is restored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (decoder, words) = match bytes.split_first() {
+            Some((&ENCODER_TAG, words)) => (false, words),
+            Some((&DECODER_TAG, words)) => (true, words),
+            _ => return Err(invalid()),
+        };
+        let expected = if decoder {
+            crate::decode::STATE_WORDS
+        } else {
+            crate::encode::STATE_WORDS
+        };
+        if words.len() != expected * 8 {
+            return Err(invalid());
+        }
+        let words = words
+            .chunks_exact(8)
+            .map(|word| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(word);
+                u64::from_le_bytes(bytes)
+            })
+            .collect();
+        Ok(Self { decoder, words })
+    }
+
+    /// The registers of an encoder, if this is one.
+    pub(crate) fn encoder_words(&self) -> Result<&[u64], Error> {
+        if self.decoder || self.words.len() != crate::encode::STATE_WORDS {
+            Err(invalid())
+        } else {
+            Ok(&self.words)
+        }
+    }
+
+    /// The registers of a decoder, if this is one.
+    pub(crate) fn decoder_words(&self) -> Result<&[u64], Error> {
+        if !self.decoder || self.words.len() != crate::decode::STATE_WORDS {
+            Err(invalid())
+        } else {
+            Ok(&self.words)
+        }
+    }
+}
+
+/// A range of `precision` bits narrowed to `[low, high)`, if that is one
+/// the coder could have reached.
+pub(crate) fn restore_range(precision: u64, low: u64, high: u64) -> Result<Range, Error> {
+    check_precision(precision)?;
+    if low >= high || high > 1 << precision {
+        return Err(invalid());
+    }
+    let mut range = Range::new(precision);
+    range.update_range((low, high));
+    Ok(range)
+}
+
+pub(crate) fn invalid() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid coder state")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::CoderState;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    fn model() -> Model {
+        Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build()
+    }
+
+    #[test]
+    fn resume() {
+        let data = include_str!("../tests/sherlock.rs").as_bytes();
+        let symbols: Vec<u32> = data.iter().map(|&byte| byte.into()).collect();
+        let middle = symbols.len() / 2;
+
+        let mut source_model = model();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        for (index, &symbol) in symbols.iter().chain(&[256]).enumerate() {
+            if index == middle {
+                let saved = encoder.checkpoint().to_bytes();
+                encoder =
+                    ArithmeticEncoder::restore(&CoderState::from_bytes(&saved).unwrap()).unwrap();
+            }
+            encoder.encode(symbol, &source_model, &mut output).unwrap();
+            source_model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+        let compressed = output.get_ref().get_ref().clone();
+
+        let mut source_model = model();
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            if decoded.len() == middle {
+                let state = CoderState::from_bytes(&decoder.checkpoint().to_bytes()).unwrap();
+                assert!(state.is_decoder());
+                decoder = ArithmeticDecoder::restore(&state).unwrap();
+                // a fresh reader, moved to where the old one was
+                input = BitReader::new(compressed.as_slice());
+                for _ in 0..state.bits_read().unwrap() {
+                    input.read_bit().unwrap();
+                }
+            }
+            let symbol = decoder.decode(&source_model, &mut input).unwrap();
+            source_model.update_symbol(symbol);
+            decoded.push(symbol);
+        }
+        decoded.pop();
+        assert_eq!(decoded, symbols);
+    }
+
+    #[test]
+    fn invalid() {
+        let encoder = ArithmeticEncoder::new(32).checkpoint();
+        let decoder = ArithmeticDecoder::new(32).checkpoint();
+        assert!(ArithmeticDecoder::restore(&encoder).is_err());
+        assert!(ArithmeticEncoder::restore(&decoder).is_err());
+        assert_eq!(encoder.bits_read(), None);
+
+        let mut bytes = encoder.to_bytes();
+        assert!(CoderState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        // a precision of 63
+        bytes[1] = 63;
+        let state = CoderState::from_bytes(&bytes).unwrap();
+        assert!(ArithmeticEncoder::restore(&state).is_err());
+        assert!(CoderState::from_bytes(b"X").is_err());
+    }
+}
@@ -0,0 +1,782 @@
+//! Switching between models in the middle of a stream, either driven by the
+//! recent coding cost of several candidates, explicitly at markers, or to
+//! stay under a target rate.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{
+    binary::BinaryCoder, cost, error::ModelError, ArithmeticDecoder, ArithmeticEncoder, EOFKind,
+    Model, SourceModel,
+};
+
+/// Codes symbols with whichever of its candidate models has been cheapest
+/// recently.
+///
+/// Every candidate model is updated with every symbol and charged the cost
+/// the symbol would have had under it. At the start of every `period`
+/// symbols the encoder picks the cheapest model and signals its index
+/// in-band (with its own adaptive model), so the decoder always follows the
+/// encoder. Running costs are halved at every period boundary so older
+/// symbols matter less.
+///
+/// All candidate models must have the same number of symbols.
+pub struct ModelSwitcher {
+    models: Vec<Model>,
+    costs: Vec<u64>,
+    selector: Model,
+    active: usize,
+    period: u32,
+    position: u32,
+}
+
+impl ModelSwitcher {
+    /// Fails if there are no models, `period` is 0 or the models do not all
+    /// have the same number of symbols.
+    pub fn new(models: Vec<Model>, period: u32) -> Result<Self, Error> {
+        if models.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "at least one model is required",
+            ));
+        }
+        if period == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "period must be at least 1",
+            ));
+        }
+        if models
+            .iter()
+            .any(|model| model.num_symbols() != models[0].num_symbols())
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "all models must have the same number of symbols",
+            ));
+        }
+
+        Ok(Self {
+            costs: vec![0; models.len()],
+            selector: Model::builder().num_symbols(models.len() as u32).build(),
+            models,
+            active: 0,
+            period,
+            position: 0,
+        })
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        symbol: u32,
+    ) -> Result<(), Error> {
+        let next = if self.position == 0 {
+            self.cheapest()
+        } else {
+            self.active
+        };
+        self.models[next].check_codable(symbol)?;
+        if self.position == 0 {
+            encoder.encode(next as u32, &self.selector, output)?;
+            self.select(next);
+        }
+
+        encoder.encode(symbol, &self.models[self.active], output)?;
+        self.update(symbol)?;
+        Ok(())
+    }
+
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        if self.position == 0 {
+            let index = decoder.decode(&self.selector, input)?;
+            self.select(index as usize);
+        }
+
+        let symbol = decoder.decode(&self.models[self.active], input)?;
+        self.update(symbol)?;
+        Ok(symbol)
+    }
+
+    /// Index of the model currently used for coding.
+    pub const fn active(&self) -> usize {
+        self.active
+    }
+
+    /// The active model. Useful for checking for the EOF symbol.
+    pub fn active_model(&self) -> &Model {
+        &self.models[self.active]
+    }
+
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    /// Decayed fixed-point cost of each model over the recent symbols.
+    pub fn costs(&self) -> &[u64] {
+        &self.costs
+    }
+
+    fn cheapest(&self) -> usize {
+        let mut best = 0;
+        for (index, &cost) in self.costs.iter().enumerate() {
+            if cost < self.costs[best] {
+                best = index;
+            }
+        }
+        best
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selector.update_symbol(index as u32);
+        self.active = index;
+    }
+
+    /// Charges and updates every model, all of which have the same number
+    /// of symbols as the first.
+    fn update(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.models[0].check_symbol(symbol)?;
+        for (model, total) in self.models.iter_mut().zip(&mut self.costs) {
+            let freq = model.counts()[symbol as usize];
+            *total += u64::from(cost::cost(freq.into(), model.total_count().into()));
+            model.update_symbol(symbol);
+        }
+
+        self.position += 1;
+        if self.position == self.period {
+            self.position = 0;
+            for total in &mut self.costs {
+                *total /= 2;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Codes symbols with a single active model that can be swapped for one of
+/// several pre-agreed presets, or for a model sent inline, in the middle of
+/// the stream.
+///
+/// A swap is signaled by coding the `marker` symbol with the active model
+/// followed by which model comes next. [`decode`](Self::decode) consumes
+/// swaps transparently and only ever returns regular symbols, so the marker
+/// symbol must be reserved in every model that is used. An inline model
+/// can have at most as many symbols as the largest preset, which bounds
+/// what a corrupt stream can make the decoder read and allocate.
+pub struct PresetSwitcher {
+    presets: Vec<Model>,
+    active: Model,
+    marker: u32,
+    selector: Model,
+    inline: BinaryCoder,
+}
+
+impl PresetSwitcher {
+    /// Starts with the first preset active. Fails if there are no presets
+    /// or `marker` is not a symbol other than the EOF in all of them.
+    pub fn new(presets: Vec<Model>, marker: u32) -> Result<Self, Error> {
+        if presets.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "at least one preset is required",
+            ));
+        }
+        for preset in &presets {
+            Self::check_marker(preset, marker)?;
+        }
+
+        Ok(Self {
+            active: presets[0].clone(),
+            // the last selector symbol announces an inline model
+            selector: Model::builder()
+                .num_symbols(presets.len() as u32 + 1)
+                .build(),
+            presets,
+            marker,
+            inline: BinaryCoder::new(32),
+        })
+    }
+
+    fn check_marker(model: &Model, marker: u32) -> Result<(), Error> {
+        model.check_symbol(marker)?;
+        if marker == model.eof() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the marker can not be the EOF symbol",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The most symbols an inline model can have.
+    fn max_inline_symbols(&self) -> u32 {
+        self.presets
+            .iter()
+            .map(Model::num_symbols)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        symbol: u32,
+    ) -> Result<(), Error> {
+        encoder.encode(symbol, &self.active, output)?;
+        self.active.update_symbol(symbol);
+        Ok(())
+    }
+
+    /// Switches to a fresh copy of preset `preset`, failing without
+    /// writing anything if there is no such preset.
+    pub fn swap<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        preset: usize,
+    ) -> Result<(), Error> {
+        let next = self.presets.get(preset).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("there is no preset {}", preset),
+            )
+        })?;
+        encoder.encode(self.marker, &self.active, output)?;
+        encoder.encode(preset as u32, &self.selector, output)?;
+        self.selector.update_symbol(preset as u32);
+        self.active = next.clone();
+        Ok(())
+    }
+
+    /// Switches to `model`, sending its counts and EOF inline. Fails
+    /// without writing anything if `model` does not reserve the marker or
+    /// has more symbols than the largest preset.
+    pub fn swap_inline<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        model: Model,
+    ) -> Result<(), Error> {
+        Self::check_marker(&model, self.marker)?;
+        if model.num_symbols() > self.max_inline_symbols() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "an inline model of {} symbols is larger than every preset",
+                    model.num_symbols()
+                ),
+            ));
+        }
+        let inline = self.presets.len() as u32;
+
+        encoder.encode(self.marker, &self.active, output)?;
+        encoder.encode(inline, &self.selector, output)?;
+        self.selector.update_symbol(inline);
+
+        self.inline.encode(encoder, output, model.num_symbols())?;
+        self.inline.encode(encoder, output, model.eof())?;
+        for &count in model.counts() {
+            self.inline.encode(encoder, output, count)?;
+        }
+
+        self.active = model;
+        Ok(())
+    }
+
+    /// Decodes the next regular symbol, following any swaps on the way.
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        loop {
+            let symbol = decoder.decode(&self.active, input)?;
+            if symbol != self.marker {
+                self.active.update_symbol(symbol);
+                return Ok(symbol);
+            }
+
+            let index = decoder.decode(&self.selector, input)?;
+            self.selector.update_symbol(index);
+            self.active = match self.presets.get(index as usize) {
+                Some(preset) => preset.clone(),
+                None => self.decode_inline(decoder, input)?,
+            };
+        }
+    }
+
+    fn decode_inline<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<Model, Error> {
+        let num_symbols = self.inline.decode(decoder, input)?;
+        if num_symbols > self.max_inline_symbols() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "an inline model of {} symbols is larger than every preset",
+                    num_symbols
+                ),
+            ));
+        }
+        let eof = self.inline.decode(decoder, input)?;
+        if self.marker >= num_symbols || self.marker == eof {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the inline model does not reserve the marker",
+            ));
+        }
+        let mut counts = Vec::new();
+        for _ in 0..num_symbols {
+            counts.push(self.inline.decode(decoder, input)?);
+        }
+
+        let eof = if eof < num_symbols {
+            EOFKind::Specify(eof)
+        } else {
+            EOFKind::None
+        };
+        Model::builder()
+            .counts(counts)
+            .eof(eof)
+            .try_build()
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+    }
+
+    pub const fn active(&self) -> &Model {
+        &self.active
+    }
+}
+
+/// Flattening level at which every count of at most `u32::MAX` becomes 1
+/// or 2, which is close enough to uniform.
+const MAX_FLATTENING: u32 = 31;
+
+/// Step signaled at the start of every period by [`RateTargetedCoder`].
+const LOWER: u32 = 0;
+const KEEP: u32 = 1;
+const RAISE: u32 = 2;
+
+/// Codes symbols with an adaptive model that is progressively flattened
+/// towards uniform while the realized rate is over a target, bounding the
+/// output of a badly mismatched model near `log2(num_symbols)` bits per
+/// symbol.
+///
+/// At the start of every `period` symbols the encoder compares the cost of
+/// the previous period to the target and signals in-band whether to raise,
+/// keep or lower the flattening level. At level `n > 0` symbols are coded
+/// with a frozen model whose counts are those of the adaptive model shifted
+/// right by `n`, plus 1; the adaptive model keeps learning from every symbol
+/// so it can take over again once it predicts well, which is when a period
+/// comes in under 7/8 of the target.
+pub struct RateTargetedCoder {
+    model: Model,
+    flattened: Option<Model>,
+    level: u32,
+    steps: Model,
+    target: u64,
+    period: u32,
+    position: u32,
+    period_cost: u64,
+}
+
+impl RateTargetedCoder {
+    /// `target` is in bits per symbol. Fails if `period` is 0 or `target`
+    /// is not a positive number.
+    pub fn new(model: Model, target: f64, period: u32) -> Result<Self, Error> {
+        if period == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "period must be at least 1",
+            ));
+        }
+        if !target.is_finite() || target <= 0.0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the target rate must be positive",
+            ));
+        }
+
+        Ok(Self {
+            model,
+            flattened: None,
+            level: 0,
+            steps: Model::builder().num_symbols(3).build(),
+            target: (target * f64::from(cost::COST_ONE_BIT) * f64::from(period)) as u64,
+            period,
+            position: 0,
+            period_cost: 0,
+        })
+    }
+
+    pub fn encode<W: Write>(
+        &mut self,
+        encoder: &mut ArithmeticEncoder,
+        output: &mut BitWriter<W>,
+        symbol: u32,
+    ) -> Result<(), Error> {
+        // before the step is written
+        self.model.check_symbol(symbol)?;
+        if self.position == 0 {
+            let step = self.next_step();
+            encoder.encode(step, &self.steps, output)?;
+            self.apply(step);
+        }
+
+        encoder.encode(symbol, self.active_model(), output)?;
+        self.update(symbol)?;
+        Ok(())
+    }
+
+    pub fn decode<R: Read, B: Bit>(
+        &mut self,
+        decoder: &mut ArithmeticDecoder,
+        input: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        if self.position == 0 {
+            let step = decoder.decode(&self.steps, input)?;
+            self.apply(step);
+        }
+
+        let symbol = decoder.decode(self.active_model(), input)?;
+        self.update(symbol)?;
+        Ok(symbol)
+    }
+
+    /// Current flattening level, 0 when coding with the adaptive model.
+    pub const fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// The model the next symbol is coded with. Useful for checking for the
+    /// EOF symbol.
+    pub fn active_model(&self) -> &Model {
+        self.flattened.as_ref().unwrap_or(&self.model)
+    }
+
+    /// The adaptive model, which is updated with every symbol.
+    pub const fn model(&self) -> &Model {
+        &self.model
+    }
+
+    fn next_step(&self) -> u32 {
+        if self.period_cost > self.target && self.level < MAX_FLATTENING {
+            RAISE
+        } else if self.period_cost < self.target - self.target / 8 && self.level > 0 {
+            LOWER
+        } else {
+            KEEP
+        }
+    }
+
+    fn apply(&mut self, step: u32) {
+        self.steps.update_symbol(step);
+        self.period_cost = 0;
+        // saturating so a corrupt stream can not push the level out of range
+        match step {
+            RAISE => self.level = (self.level + 1).min(MAX_FLATTENING),
+            LOWER => self.level = self.level.saturating_sub(1),
+            _ => {}
+        }
+
+        self.flattened = if self.level == 0 {
+            None
+        } else {
+            let counts = self
+                .model
+                .counts()
+                .iter()
+                .map(|&count| (count >> self.level) + 1)
+                .collect();
+            let eof = self.model.eof();
+            let eof = if eof < self.model.num_symbols() {
+                EOFKind::Specify(eof)
+            } else {
+                EOFKind::None
+            };
+            Some(Model::builder().counts(counts).eof(eof).build())
+        };
+    }
+
+    fn update(&mut self, symbol: u32) -> Result<(), ModelError> {
+        self.model.check_symbol(symbol)?;
+        let active = self.active_model();
+        let freq = active.counts()[symbol as usize];
+        self.period_cost += u64::from(cost::cost(freq.into(), active.total_count().into()));
+        self.model.update_symbol(symbol);
+
+        self.position += 1;
+        if self.position == self.period {
+            self.position = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{ModelSwitcher, PresetSwitcher, RateTargetedCoder};
+    use crate::{binary::BinaryCoder, ArithmeticDecoder, ArithmeticEncoder, EOFKind, Model};
+
+    fn candidates() -> Vec<Model> {
+        let mut low = vec![1; 16];
+        low[..4].iter_mut().for_each(|count| *count = 100);
+        let mut high = vec![1; 16];
+        high[12..15].iter_mut().for_each(|count| *count = 100);
+
+        vec![
+            Model::builder().counts(low).eof(EOFKind::End).build(),
+            Model::builder().counts(high).eof(EOFKind::End).build(),
+        ]
+    }
+
+    #[test]
+    fn e2e() {
+        let data: Vec<u32> = (0..400)
+            .map(|i| if i < 200 { i % 4 } else { 12 + i % 3 })
+            .collect();
+
+        let mut switcher = ModelSwitcher::new(candidates(), 16).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = Cursor::new(vec![]);
+        let mut out_writer = BitWriter::new(&mut output);
+        let mut used = vec![];
+        for &symbol in &data {
+            switcher
+                .encode(&mut encoder, &mut out_writer, symbol)
+                .unwrap();
+            used.push(switcher.active());
+        }
+        let eof = switcher.active_model().eof();
+        switcher.encode(&mut encoder, &mut out_writer, eof).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        assert_eq!(used[100], 0);
+        assert_eq!(used[399], 1);
+
+        let mut switcher = ModelSwitcher::new(candidates(), 16).unwrap();
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(output.into_inner()));
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = switcher.decode(&mut decoder, &mut in_reader).unwrap();
+            if !decoder.finished() {
+                decoded.push(symbol);
+            }
+        }
+
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn out_of_range_symbol() {
+        let mut switcher = ModelSwitcher::new(candidates(), 16).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        assert!(switcher.encode(&mut encoder, &mut out_writer, 16).is_err());
+        assert!(switcher.update(16).is_err());
+        assert_eq!(switcher.costs(), &[0, 0]);
+        assert_eq!(switcher.models()[0].counts(), candidates()[0].counts());
+        assert_eq!(encoder.bits_written(), 0);
+        assert_eq!(switcher.position, 0);
+
+        // a symbol the selected model can not code writes no selector
+        let models = vec![
+            Model::builder().counts(vec![5, 0, 5]).build(),
+            Model::builder().counts(vec![1, 1, 1]).build(),
+        ];
+        let mut switcher = ModelSwitcher::new(models, 4).unwrap();
+        let error = switcher
+            .encode(&mut encoder, &mut out_writer, 1)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(encoder.bits_written(), 0);
+        assert_eq!(switcher.selector.counts(), &[1, 1]);
+        assert_eq!(switcher.position, 0);
+    }
+
+    #[test]
+    fn switcher_errors() {
+        assert!(ModelSwitcher::new(vec![], 16).is_err());
+        assert!(ModelSwitcher::new(candidates(), 0).is_err());
+        let mismatched = vec![
+            Model::builder().num_symbols(4).build(),
+            Model::builder().num_symbols(5).build(),
+        ];
+        let error = ModelSwitcher::new(mismatched, 16).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn preset_swaps() {
+        // symbol 8 is the marker and 9 the EOF
+        let presets = vec![
+            Model::builder().num_symbols(10).eof(EOFKind::End).build(),
+            Model::builder()
+                .counts(vec![50, 50, 1, 1, 1, 1, 1, 1, 1, 1])
+                .eof(EOFKind::End)
+                .build(),
+        ];
+        let inline = Model::builder()
+            .counts(vec![1, 1, 1, 1, 1, 1, 80, 80, 1, 1])
+            .eof(EOFKind::End)
+            .build();
+
+        let mut switcher = PresetSwitcher::new(presets.clone(), 8).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = Cursor::new(vec![]);
+        let mut out_writer = BitWriter::new(&mut output);
+        let data = [3, 4, 0, 1, 1, 0, 6, 7, 7, 2];
+        for (i, &symbol) in data.iter().enumerate() {
+            match i {
+                2 => switcher.swap(&mut encoder, &mut out_writer, 1).unwrap(),
+                6 => switcher
+                    .swap_inline(&mut encoder, &mut out_writer, inline.clone())
+                    .unwrap(),
+                _ => {}
+            }
+            switcher
+                .encode(&mut encoder, &mut out_writer, symbol)
+                .unwrap();
+        }
+        switcher.encode(&mut encoder, &mut out_writer, 9).unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let mut switcher = PresetSwitcher::new(presets, 8).unwrap();
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(output.into_inner()));
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            let symbol = switcher.decode(&mut decoder, &mut in_reader).unwrap();
+            if !decoder.finished() {
+                decoded.push(symbol);
+            }
+        }
+
+        assert_eq!(decoded, data);
+        assert_eq!(switcher.active().counts()[6], 81);
+    }
+
+    #[test]
+    fn preset_errors() {
+        let presets = vec![Model::builder().num_symbols(10).eof(EOFKind::End).build()];
+        assert!(PresetSwitcher::new(vec![], 8).is_err());
+        assert!(PresetSwitcher::new(presets.clone(), 9).is_err());
+        assert!(PresetSwitcher::new(presets.clone(), 10).is_err());
+
+        let mut switcher = PresetSwitcher::new(presets.clone(), 8).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        assert!(switcher.swap(&mut encoder, &mut out_writer, 1).is_err());
+        let larger = Model::builder().num_symbols(11).build();
+        assert!(switcher
+            .swap_inline(&mut encoder, &mut out_writer, larger)
+            .is_err());
+        let unreserved = Model::builder().num_symbols(9).eof(EOFKind::End).build();
+        assert!(switcher
+            .swap_inline(&mut encoder, &mut out_writer, unreserved)
+            .is_err());
+        assert_eq!(encoder.bits_written(), 0);
+
+        // a corrupt stream announcing a huge inline model
+        let mut selector = Model::builder().num_symbols(2).build();
+        encoder.encode(8, &presets[0], &mut out_writer).unwrap();
+        encoder.encode(1, &selector, &mut out_writer).unwrap();
+        selector.update_symbol(1);
+        BinaryCoder::new(32)
+            .encode(&mut encoder, &mut out_writer, 1 << 30)
+            .unwrap();
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+
+        let input = out_writer.get_ref().get_ref().clone();
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(input.as_slice());
+        let mut decoder = ArithmeticDecoder::new(48);
+        let error = switcher.decode(&mut decoder, &mut in_reader).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("larger than every preset"));
+    }
+
+    #[test]
+    fn rate_targeted_out_of_range_symbol() {
+        let model = Model::builder().num_symbols(5).build();
+        let mut coder = RateTargetedCoder::new(model, 1.0, 8).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+        assert!(coder.encode(&mut encoder, &mut out_writer, 5).is_err());
+        assert!(coder.update(5).is_err());
+        assert_eq!(coder.model().total_count(), 5);
+        assert_eq!(encoder.bits_written(), 0);
+        assert_eq!(coder.position, 0);
+    }
+
+    #[test]
+    fn rate_targeted_errors() {
+        let model = Model::builder().num_symbols(5).build();
+        assert!(RateTargetedCoder::new(model.clone(), 1.0, 0).is_err());
+        for &target in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let error = RateTargetedCoder::new(model.clone(), target, 8)
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    fn encode_rate_targeted(data: &[u32], target: f64) -> (Vec<u8>, u32) {
+        let model = Model::builder()
+            .counts(vec![5000, 1, 1, 1, 1])
+            .eof(EOFKind::End)
+            .build();
+        let mut coder = RateTargetedCoder::new(model.clone(), target, 8).unwrap();
+        let mut encoder = ArithmeticEncoder::new(48);
+        let mut output = Cursor::new(vec![]);
+        let mut out_writer = BitWriter::new(&mut output);
+        let mut max_level = 0;
+        for &symbol in data.iter().chain(Some(&4)) {
+            coder.encode(&mut encoder, &mut out_writer, symbol).unwrap();
+            max_level = max_level.max(coder.level());
+        }
+        encoder.finish_encode(&mut out_writer).unwrap();
+        out_writer.pad_to_byte().unwrap();
+        let output = output.into_inner();
+
+        let mut coder = RateTargetedCoder::new(model, target, 8).unwrap();
+        let mut decoder = ArithmeticDecoder::new(48);
+        let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(output.clone()));
+        let mut decoded = vec![];
+        while !decoder.finished() {
+            decoded.push(coder.decode(&mut decoder, &mut in_reader).unwrap());
+        }
+        decoded.pop();
+        assert_eq!(decoded, data);
+        (output, max_level)
+    }
+
+    #[test]
+    fn rate_targeted() {
+        // the model is badly mismatched with the data
+        let data: Vec<u32> = (0..400).map(|i| 1 + i % 3).collect();
+
+        // a target that is never exceeded leaves the adaptive model alone
+        let (unbounded, level) = encode_rate_targeted(&data, 100.0);
+        assert_eq!(level, 0);
+
+        let (bounded, level) = encode_rate_targeted(&data, 2.5);
+        assert!(level > 0);
+        assert!(bounded.len() < unbounded.len() * 3 / 4);
+    }
+}
@@ -0,0 +1,105 @@
+//! Round-trip checking of an adaptive coding setup.
+
+use std::{
+    io::{Cursor, Error, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use bitbit::{BitReader, BitWriter, MSB};
+
+use crate::{ArithmeticDecoder, ArithmeticEncoder, Model};
+
+/// How [`verify`] codes the data: every symbol is coded with `model`, which
+/// is updated after each symbol just as in the crate level examples.
+#[derive(Clone)]
+pub struct RoundtripConfig {
+    pub model: Model,
+    pub precision: u64,
+}
+
+/// Result of a successful [`verify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundtripReport {
+    pub symbols: u64,
+    /// Size of the encoded stream, padded to a byte.
+    pub encoded_bytes: u64,
+    pub encode_time: Duration,
+    pub decode_time: Duration,
+}
+
+/// Encodes `data`, decodes it again and checks the decoded symbols match.
+///
+/// The number of symbols is taken from `data`, so the model does not need an
+/// EOF symbol. A mismatch is reported as an [`ErrorKind::InvalidData`] error.
+pub fn verify(data: &[u32], config: &RoundtripConfig) -> Result<RoundtripReport, Error> {
+    let start = Instant::now();
+    let mut model = config.model.clone();
+    let mut encoder = ArithmeticEncoder::new(config.precision);
+    let mut out_writer = BitWriter::new(Cursor::new(vec![]));
+    for &symbol in data {
+        encoder.encode(symbol, &model, &mut out_writer)?;
+        model.update_symbol(symbol);
+    }
+    encoder.finish_encode(&mut out_writer)?;
+    out_writer.pad_to_byte()?;
+    let encoded = out_writer.get_ref().get_ref().clone();
+    let encode_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut model = config.model.clone();
+    let mut decoder = ArithmeticDecoder::new(config.precision);
+    let mut in_reader: BitReader<_, MSB> = BitReader::new(Cursor::new(&encoded));
+    for (position, &expected) in data.iter().enumerate() {
+        let symbol = decoder.decode(&model, &mut in_reader)?;
+        if symbol != expected {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "decoded symbol {} instead of {} at position {}",
+                    symbol, expected, position
+                ),
+            ));
+        }
+        model.update_symbol(symbol);
+    }
+    let decode_time = start.elapsed();
+
+    Ok(RoundtripReport {
+        symbols: data.len() as u64,
+        encoded_bytes: encoded.len() as u64,
+        encode_time,
+        decode_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, RoundtripConfig};
+    use crate::Model;
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u32> = b"she sells sea shells by the sea shore"
+            .iter()
+            .map(|&b| u32::from(b))
+            .collect();
+        let config = RoundtripConfig {
+            model: Model::builder().num_bits(8).build(),
+            precision: 48,
+        };
+
+        let report = verify(&data, &config).unwrap();
+        assert_eq!(report.symbols, data.len() as u64);
+        assert!(report.encoded_bytes > 0);
+        assert!(report.encoded_bytes < data.len() as u64);
+    }
+
+    #[test]
+    fn invalid_symbol() {
+        let config = RoundtripConfig {
+            model: Model::builder().num_symbols(4).build(),
+            precision: 32,
+        };
+        assert!(verify(&[1, 2, 4], &config).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Coders whose state is `u128` instead of `u64`, for precisions above
+//! [`MAX_PRECISION`](crate::precision::MAX_PRECISION) when studying how
+//! precision trades against compression.
+//!
+//! Intervals are computed exactly like [`Range`](crate::Range) does, so at
+//! precisions both support the streams are identical to those of
+//! [`ArithmeticEncoder`](crate::ArithmeticEncoder). The arithmetic is
+//! emulated on most targets and the decoder searches the counts instead of
+//! dividing, so these are several times slower than the `u64` coders.
+
+use crate::word::{WordDecoder, WordEncoder, WordRange};
+
+/// The largest precision a [`WideRange`] can hold: `2^precision` has to
+/// fit in a `u128`.
+pub const MAX_WIDE_PRECISION: u32 = 127;
+
+/// [`Range`](crate::Range) on `u128`, see the [module](self) docs.
+pub type WideRange = WordRange<u128>;
+
+/// [`ArithmeticEncoder`](crate::ArithmeticEncoder) on a [`WideRange`].
+pub type WideEncoder = WordEncoder<u128>;
+
+/// [`ArithmeticDecoder`](crate::ArithmeticDecoder) on a [`WideRange`].
+pub type WideDecoder = WordDecoder<u128>;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{WideDecoder, WideEncoder, WideRange};
+    use crate::{error::PrecisionError, ArithmeticEncoder, EOFKind, Model};
+
+    fn model() -> Model {
+        Model::builder().num_bits(8).eof(EOFKind::EndAddOne).build()
+    }
+
+    fn encode(symbols: &[u32], precision: u32) -> Vec<u8> {
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        let mut encoder = WideEncoder::new(precision);
+        let mut source_model = model();
+        for &symbol in symbols.iter().chain(&[256]) {
+            encoder.encode(symbol, &source_model, &mut output).unwrap();
+            source_model.update_symbol(symbol);
+        }
+        encoder.finish_encode(&mut output).unwrap();
+        output.pad_to_byte().unwrap();
+        output.get_ref().get_ref().clone()
+    }
+
+    #[test]
+    fn e2e() {
+        let data = include_str!("../tests/sherlock.rs").as_bytes();
+        let symbols: Vec<u32> = data.iter().map(|&byte| byte.into()).collect();
+
+        for &precision in &[48, 62, 100, 127] {
+            let compressed = encode(&symbols, precision);
+            if precision <= 62 {
+                let mut output = BitWriter::new(Cursor::new(vec![]));
+                let mut encoder = ArithmeticEncoder::new(precision.into());
+                let mut source_model = model();
+                for &symbol in symbols.iter().chain(&[256]) {
+                    encoder.encode(symbol, &source_model, &mut output).unwrap();
+                    source_model.update_symbol(symbol);
+                }
+                encoder.finish_encode(&mut output).unwrap();
+                output.pad_to_byte().unwrap();
+                assert_eq!(&compressed, output.get_ref().get_ref());
+            }
+
+            let mut decoder = WideDecoder::new(precision);
+            let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+            let mut source_model = model();
+            let mut decoded = vec![];
+            while !decoder.finished() {
+                let symbol = decoder.decode(&source_model, &mut input).unwrap();
+                source_model.update_symbol(symbol);
+                decoded.push(symbol);
+            }
+            decoded.pop();
+            assert_eq!(decoded, symbols);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert!(WideRange::try_new(127).is_ok());
+        assert_eq!(
+            WideRange::try_new(128).err(),
+            Some(PrecisionError::OutOfRange { precision: 128 })
+        );
+        assert!(WideRange::try_new(1).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut decoder = WideDecoder::new(100);
+        let error = decoder
+            .decode(&model(), &mut BitReader::<_, MSB>::new(&[][..]))
+            .unwrap_err();
+        assert!(error.to_string().contains("EOF"));
+    }
+}
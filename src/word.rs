@@ -0,0 +1,473 @@
+//! The range and coders behind [`narrow`](crate::narrow) and
+//! [`wide`](crate::wide), generic over the unsigned integer their state is
+//! kept in.
+//!
+//! Intervals are computed exactly like [`Range`](crate::Range) does, so at
+//! any precision the word can hold the streams are identical to those of
+//! [`ArithmeticEncoder`](crate::ArithmeticEncoder).
+
+use std::{
+    fmt::Debug,
+    io::{Error, ErrorKind, Read, Write},
+    ops::{Add, BitOr, Mul, Shl, Shr, Sub},
+};
+
+use bitbit::{reader::Bit, BitReader, BitWriter};
+
+use crate::{
+    error::{DecodeError, DecodeFailure, FinishedError, PrecisionError},
+    narrow::MAX_NARROW_PRECISION,
+    precision::MIN_PRECISION,
+    wide::MAX_WIDE_PRECISION,
+    SourceModel,
+};
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u32 {}
+    impl Sealed for u128 {}
+}
+
+/// An unsigned integer a [`WordRange`] can be kept in. Implemented for `u32`
+/// and `u128`.
+pub trait Word:
+    private::Sealed
+    + Copy
+    + Debug
+    + Ord
+    + From<u32>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + BitOr<Output = Self>
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+{
+    /// The largest precision whose `2^precision` fits in the word.
+    const MAX_PRECISION: u32;
+
+    /// `width * count / total`, rounded down.
+    fn scale(width: Self, count: u32, total: u32) -> Self;
+
+    /// The largest count below `total` that scales to at most `offset`.
+    fn count_at(offset: Self, width: Self, total: u32) -> u32;
+}
+
+impl Word for u32 {
+    const MAX_PRECISION: u32 = MAX_NARROW_PRECISION;
+
+    fn scale(width: Self, count: u32, total: u32) -> Self {
+        // the product still needs 64 bits
+        (u64::from(width) * u64::from(count) / u64::from(total)) as u32
+    }
+
+    fn count_at(offset: Self, width: Self, total: u32) -> u32 {
+        let total = u64::from(total);
+        (((u64::from(offset) + 1) * total - 1) / u64::from(width)) as u32
+    }
+}
+
+impl Word for u128 {
+    const MAX_PRECISION: u32 = MAX_WIDE_PRECISION;
+
+    /// The width is split into whole multiples of `total` and a remainder
+    /// below it so the product never overflows.
+    fn scale(width: Self, count: u32, total: u32) -> Self {
+        let (count, total) = (u128::from(count), u128::from(total));
+        width / total * count + width % total * count / total
+    }
+
+    /// Dividing by the width like the `u32` word does would need a 256-bit
+    /// product, so this searches the counts instead.
+    fn count_at(offset: Self, width: Self, total: u32) -> u32 {
+        let (mut low, mut high) = (0, total - 1);
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if Self::scale(width, mid, total) <= offset {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+}
+
+/// [`Range`](crate::Range) on a [`Word`].
+#[derive(Clone, Debug)]
+pub struct WordRange<W> {
+    high: W,
+    low: W,
+    half: W,
+    one_quarter_mark: W,
+    three_quarter_mark: W,
+}
+
+impl<W: Word> WordRange<W> {
+    /// Panics if `2^precision` does not fit in `W`.
+    pub fn new(precision: u32) -> Self {
+        assert!(precision <= W::MAX_PRECISION);
+
+        let high = W::from(1) << precision;
+        let one_quarter_mark = high >> 2;
+        Self {
+            high,
+            low: W::from(0),
+            half: high >> 1,
+            one_quarter_mark,
+            three_quarter_mark: one_quarter_mark * W::from(3),
+        }
+    }
+
+    /// Like [`new`](Self::new) but returns an error instead of panicking.
+    pub fn try_new(precision: u32) -> Result<Self, PrecisionError> {
+        if (MIN_PRECISION..=u64::from(W::MAX_PRECISION)).contains(&u64::from(precision)) {
+            Ok(Self::new(precision))
+        } else {
+            Err(PrecisionError::OutOfRange {
+                precision: precision.into(),
+            })
+        }
+    }
+
+    /// Whether a model totalling `total_count` fits in a quarter of the range.
+    pub fn can_represent(&self, total_count: u32) -> bool {
+        W::from(total_count) <= self.one_quarter_mark
+    }
+
+    /// Whether the interval lies entirely below the midpoint.
+    pub fn in_bottom_half(&self) -> bool {
+        self.high < self.half
+    }
+
+    /// Whether the interval lies entirely above the midpoint.
+    pub fn in_upper_half(&self) -> bool {
+        self.low > self.half
+    }
+
+    /// Whether the interval lies strictly between the quarter marks.
+    pub fn in_middle_half(&self) -> bool {
+        self.low > self.one_quarter_mark && self.high < self.three_quarter_mark
+    }
+
+    /// Whether the interval starts at or below the first quarter mark.
+    pub fn in_bottom_quarter(&self) -> bool {
+        self.low <= self.one_quarter_mark
+    }
+
+    /// Doubles an interval in the upper half back to the full range.
+    pub fn scale_upper_half(&mut self) {
+        self.low = (self.low - self.half) << 1;
+        self.high = (self.high - self.half) << 1;
+    }
+
+    /// Doubles an interval in the middle half back to the full range.
+    pub fn scale_middle_half(&mut self) {
+        self.low = (self.low - self.one_quarter_mark) << 1;
+        self.high = (self.high - self.one_quarter_mark) << 1;
+    }
+
+    /// Doubles an interval in the bottom half back to the full range.
+    pub fn scale_bottom_half(&mut self) {
+        self.low = self.low << 1;
+        self.high = self.high << 1;
+    }
+
+    /// returns (low, high)
+    pub fn calculate_range<M: SourceModel + ?Sized>(
+        &self,
+        symbol: u32,
+        source_model: &M,
+    ) -> (W, W) {
+        let (low, high) = source_model.cumulative(symbol);
+        let total = source_model.total_count();
+        let width = self.width();
+        (
+            self.low + W::scale(width, low, total),
+            self.low + W::scale(width, high, total),
+        )
+    }
+
+    /// Narrows the interval to `(low, high)`.
+    pub fn update_range(&mut self, (low, high): (W, W)) {
+        self.low = low;
+        self.high = high;
+    }
+
+    /// The bottom of the interval.
+    pub fn low(&self) -> W {
+        self.low
+    }
+
+    /// The top of the interval.
+    pub fn high(&self) -> W {
+        self.high
+    }
+
+    /// The size of the interval.
+    pub fn width(&self) -> W {
+        self.high - self.low
+    }
+
+    /// The midpoint of the full range.
+    pub fn half(&self) -> W {
+        self.half
+    }
+
+    /// The first quarter mark of the full range.
+    pub fn quarter(&self) -> W {
+        self.one_quarter_mark
+    }
+}
+
+fn check_headroom<W: Word, M: SourceModel + ?Sized>(
+    range: &WordRange<W>,
+    source_model: &M,
+) -> Result<(), Error> {
+    if range.can_represent(source_model.total_count()) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the model total count exceeds what the precision can represent",
+        ))
+    }
+}
+
+/// [`ArithmeticEncoder`](crate::ArithmeticEncoder) on a [`WordRange`].
+pub struct WordEncoder<W> {
+    pending_bit_count: u32,
+    range: WordRange<W>,
+    finished: bool,
+}
+
+impl<W: Word> WordEncoder<W> {
+    pub fn new(precision: u32) -> Self {
+        Self {
+            pending_bit_count: 0,
+            range: WordRange::new(precision),
+            finished: false,
+        }
+    }
+
+    pub fn encode<T: Write, M: SourceModel + ?Sized>(
+        &mut self,
+        symbol: u32,
+        source_model: &M,
+        output: &mut BitWriter<T>,
+    ) -> Result<(), Error> {
+        if self.finished {
+            return Err(FinishedError.into());
+        }
+        check_headroom(&self.range, source_model)?;
+        source_model.check_codable(symbol)?;
+        let low_high = self.range.calculate_range(symbol, source_model);
+        self.range.update_range(low_high);
+
+        while self.range.in_bottom_half() || self.range.in_upper_half() {
+            if self.range.in_bottom_half() {
+                self.range.scale_bottom_half();
+                self.emit(false, output)?;
+            } else {
+                self.range.scale_upper_half();
+                self.emit(true, output)?;
+            }
+        }
+
+        while self.range.in_middle_half() {
+            self.pending_bit_count += 1;
+            self.range.scale_middle_half();
+        }
+
+        Ok(())
+    }
+
+    fn emit<T: Write>(&mut self, bit: bool, output: &mut BitWriter<T>) -> Result<(), Error> {
+        output.write_bit(bit)?;
+
+        while self.pending_bit_count > 0 {
+            output.write_bit(!bit)?;
+            self.pending_bit_count -= 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish_encode<T: Write>(&mut self, output: &mut BitWriter<T>) -> Result<(), Error> {
+        if self.finished {
+            return Err(FinishedError.into());
+        }
+        self.pending_bit_count += 1;
+        let bit = !self.range.in_bottom_quarter();
+        self.emit(bit, output)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+/// [`ArithmeticDecoder`](crate::ArithmeticDecoder) on a [`WordRange`].
+pub struct WordDecoder<W> {
+    range: WordRange<W>,
+    input_buffer: W,
+    /// Zeros that may still be read past the end of the input.
+    slack: u32,
+    precision: u32,
+    first_time: bool,
+    finished: bool,
+    bits_read: u64,
+    symbols_decoded: u64,
+}
+
+impl<W: Word> WordDecoder<W> {
+    pub fn new(precision: u32) -> Self {
+        Self {
+            range: WordRange::new(precision),
+            input_buffer: W::from(0),
+            slack: precision,
+            precision,
+            first_time: true,
+            finished: false,
+            bits_read: 0,
+            symbols_decoded: 0,
+        }
+    }
+
+    pub fn decode<R: Read, B: Bit, M: SourceModel + ?Sized>(
+        &mut self,
+        source_model: &M,
+        bit_source: &mut BitReader<R, B>,
+    ) -> Result<u32, Error> {
+        self.check_unfinished()?;
+        check_headroom(&self.range, source_model)?;
+        if self.first_time {
+            for _ in 0..self.precision {
+                self.input_buffer = (self.input_buffer << 1) | self.bit(bit_source)?;
+            }
+            self.first_time = false;
+        }
+
+        let total = source_model.total_count();
+        if total == 0 || !(self.range.low()..self.range.high()).contains(&self.input_buffer) {
+            return Err(self.error(DecodeFailure::NoMatchingSymbol));
+        }
+        let count = W::count_at(
+            self.input_buffer - self.range.low(),
+            self.range.width(),
+            total,
+        );
+        let symbol = source_model.symbol_at(count);
+        let low_high = self.range.calculate_range(symbol, source_model);
+        self.symbols_decoded += 1;
+
+        if symbol == source_model.eof() {
+            self.finished = true;
+            return Ok(symbol);
+        }
+
+        self.range.update_range(low_high);
+
+        while self.range.in_bottom_half() || self.range.in_upper_half() {
+            if self.range.in_bottom_half() {
+                self.range.scale_bottom_half();
+                self.input_buffer = (self.input_buffer << 1) | self.bit(bit_source)?;
+            } else {
+                self.range.scale_upper_half();
+                self.input_buffer =
+                    ((self.input_buffer - self.range.half()) << 1) | self.bit(bit_source)?;
+            }
+        }
+
+        while self.range.in_middle_half() {
+            self.range.scale_middle_half();
+            self.input_buffer =
+                ((self.input_buffer - self.range.quarter()) << 1) | self.bit(bit_source)?;
+        }
+
+        Ok(symbol)
+    }
+
+    fn bit<R: Read, B: Bit>(&mut self, source: &mut BitReader<R, B>) -> Result<W, Error> {
+        match source.read_bit() {
+            Ok(res) => {
+                self.bits_read += 1;
+                Ok(W::from(u32::from(res)))
+            }
+            Err(_e) => {
+                if self.slack == 0 {
+                    return Err(self.error(DecodeFailure::MissingEof));
+                }
+                self.slack -= 1;
+                Ok(W::from(0))
+            }
+        }
+    }
+
+    fn check_unfinished(&self) -> Result<(), Error> {
+        if self.finished {
+            Err(self.error(DecodeFailure::Finished))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn error(&self, failure: DecodeFailure) -> Error {
+        DecodeError {
+            failure,
+            bit_offset: self.bits_read,
+            symbols_decoded: self.symbols_decoded,
+        }
+        .into()
+    }
+}
+
+impl<W> WordDecoder<W> {
+    pub const fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitbit::{BitReader, BitWriter, MSB};
+
+    use super::{Word, WordDecoder, WordEncoder};
+    use crate::{
+        error::{DecodeError, DecodeFailure, FinishedError},
+        EOFKind, Model,
+    };
+
+    fn used_after_finish<W: Word>(precision: u32) {
+        let model = Model::builder().num_symbols(4).eof(EOFKind::End).build();
+        let mut output = BitWriter::new(Cursor::new(vec![]));
+        let mut encoder = WordEncoder::<W>::new(precision);
+        encoder.encode(1, &model, &mut output).unwrap();
+        encoder.encode(3, &model, &mut output).unwrap();
+        encoder.finish_encode(&mut output).unwrap();
+        let error = encoder.encode(1, &model, &mut output).unwrap_err();
+        assert!(error.into_inner().unwrap().is::<FinishedError>());
+        assert!(encoder.finish_encode(&mut output).is_err());
+        output.pad_to_byte().unwrap();
+
+        let compressed = output.get_ref().get_ref().clone();
+        let mut input: BitReader<_, MSB> = BitReader::new(compressed.as_slice());
+        let mut decoder = WordDecoder::<W>::new(precision);
+        assert_eq!(decoder.decode(&model, &mut input).unwrap(), 1);
+        assert_eq!(decoder.decode(&model, &mut input).unwrap(), 3);
+        let error = decoder.decode(&model, &mut input).unwrap_err();
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<DecodeError>()
+            .unwrap();
+        assert_eq!(error.failure, DecodeFailure::Finished);
+    }
+
+    #[test]
+    fn finished_guards() {
+        used_after_finish::<u32>(16);
+        used_after_finish::<u128>(100);
+    }
+}